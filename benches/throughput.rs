@@ -0,0 +1,126 @@
+#![allow(clippy::needless_return)]
+//measures CPU usage of an idle server and client, and how many messages the server delivers per second
+//run with: cargo bench --bench throughput
+use chat::net::*;
+use chat::{ChatClient, SeMessage};
+use std::io::prelude::*;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+const IDLE_CLIENTS : usize = 20;
+const IDLE_TIME : Duration = Duration::from_secs(2);
+const RECEIVERS : usize = 10;
+const MESSAGES : usize = 5000;
+//USER_HZ, the unit of times in /proc/<pid>/stat, is 100 on Linux
+const TICKS_PER_SEC : f64 = 100.0;
+
+//user and system CPU time the process has taken so far, in seconds
+fn cpu_time(pid : u32) -> Option<f64>{
+	let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+	//the command name might contain spaces, fields are counted from after it
+	let fields : Vec<&str> = stat[stat.rfind(')')? + 2..].split(' ').collect();
+	let utime : f64 = fields.get(11)?.parse().ok()?;
+	let stime : f64 = fields.get(12)?.parse().ok()?;
+	return Some((utime + stime) / TICKS_PER_SEC);
+}
+
+//share of one core the process takes while nothing happens
+fn idle_cpu(pid : u32) -> Option<f64>{
+	let before = cpu_time(pid)?;
+	std::thread::sleep(IDLE_TIME);
+	let after = cpu_time(pid)?;
+	return Some((after - before) / IDLE_TIME.as_secs_f64() * 100.0);
+}
+
+fn report_cpu(what : &str, pid : u32){
+	match idle_cpu(pid){
+	    Some(cpu) => {println!("{}: {:.1}% CPU", what, cpu)}
+	    None => {println!("{}: CPU usage is not available on this system", what)}
+	}
+}
+
+fn start_server(dir : &std::path::Path, addr : &str) -> Child{
+	//the sender floods the server on purpose, it isn't to be throttled
+	let mut server = Command::new(env!("CARGO_BIN_EXE_server"))
+		.args(["--rate-limit", "0", "--ip-rate-limit", "0"])
+		.current_dir(dir)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::null())
+		.spawn()
+		.expect("Couldn't start the server");
+	writeln!(server.stdin.as_mut().unwrap(), "{}", addr).unwrap();
+	let started_at = Instant::now();
+	while std::net::TcpStream::connect(addr).is_err(){
+		assert!(started_at.elapsed() < Duration::from_secs(5), "Server hasn't started");
+		std::thread::sleep(Duration::from_millis(10));
+	}
+	return server;
+}
+
+fn receive(mut client : ChatClient, count : usize) -> Duration{
+	let started_at = Instant::now();
+	let mut received = 0;
+	for event in client.events(){
+		if let SeMessage::Mesg(m) = event{
+			if m.mesg.starts_with("sender: "){
+				received += 1;
+				if received == count{
+					break;
+				}
+			}
+		}
+	}
+	return started_at.elapsed();
+}
+
+fn main(){
+	let dir = std::env::temp_dir().join(format!("chat-bench-{}", std::process::id()));
+	std::fs::create_dir_all(&dir).unwrap();
+	let addr = "127.0.0.1:7979";
+	let mut server = start_server(&dir, addr);
+
+	report_cpu("Idle server without clients", server.id());
+	let mut idle = Vec::new();
+	for i in 0..IDLE_CLIENTS{
+		idle.push(ChatClient::join(addr, &format!("idle{}", i), None).unwrap());
+	}
+	report_cpu(&format!("Idle server with {} clients", IDLE_CLIENTS), server.id());
+	drop(idle);
+
+	let mut client = Command::new(env!("CARGO_BIN_EXE_client"))
+		.stdin(Stdio::piped())
+		.stdout(Stdio::null())
+		.spawn()
+		.expect("Couldn't start the client");
+	writeln!(client.stdin.as_mut().unwrap(), "idleclient\n/join {}", addr).unwrap();
+	std::thread::sleep(Duration::from_millis(500));
+	report_cpu("Idle client connected to the server", client.id());
+	let _ = client.kill();
+	let _ = client.wait();
+
+	let mut receivers = Vec::new();
+	for i in 0..RECEIVERS{
+		let client = ChatClient::join(addr, &format!("receiver{}", i), None).unwrap();
+		receivers.push(std::thread::spawn(move || {receive(client, MESSAGES)}));
+	}
+	let mut sender = ChatClient::join(addr, "sender", None).unwrap();
+	let started_at = Instant::now();
+	for i in 0..MESSAGES{
+		sender.say(LOBBY, &format!("message number {}", i)).unwrap();
+		//let the server take what it can, and don't let the queue grow without bound
+		sender.poll();
+	}
+	let mut slowest = Duration::from_secs(0);
+	for receiver in receivers{
+		slowest = slowest.max(receiver.join().unwrap());
+	}
+	let elapsed = started_at.elapsed().max(slowest);
+	println!("Sent {} messages to {} receivers in {:.2}s: {:.0} messages/s in, {:.0} deliveries/s out",
+		MESSAGES, RECEIVERS, elapsed.as_secs_f64(),
+		MESSAGES as f64 / elapsed.as_secs_f64(),
+		(MESSAGES * RECEIVERS) as f64 / elapsed.as_secs_f64());
+
+	let _ = server.kill();
+	let _ = server.wait();
+	let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,35 @@
+//a bot which repeats back whatever is said to it privately
+//usage: cargo run --example echo_bot -- <adress> [username]
+use chat::{ChatClient, SeMessage};
+
+fn main(){
+	let mut args = std::env::args().skip(1);
+	let addr = args.next().expect("No adress provided");
+	let username = args.next().unwrap_or_else(|| "echo".to_string());
+	let mut client = match ChatClient::join(&addr, &username, None){
+	    Ok(v) => {v}
+	    Err(e) => {
+			eprintln!("{}", e);
+			std::process::exit(1);
+		}
+	};
+	let mut replies = Vec::new();
+	loop{
+		for event in client.poll(){
+			match event{
+			    SeMessage::Direct(m) if m.from != username => {replies.push((m.from, m.mesg))}
+			    SeMessage::UQuit(m) => {
+					println!("Disconnected: {}", m.reason);
+					return;
+				}
+			    _ => {}
+			}
+		}
+		for (to, mesg) in replies.drain(..){
+			if let Err(e) = client.direct(&to, &mesg){
+				eprintln!("Couldn't reply to {}: {}", to, e);
+			}
+		}
+		std::thread::sleep(std::time::Duration::from_millis(50));
+	}
+}
@@ -0,0 +1,94 @@
+use std::fs;
+use std::io::prelude::*;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::SaltString;
+
+const SALT_LEN : usize = 16;
+
+//server-side database of registered users
+//stored as a text file, one user per line: username:hash, the hash of the password by Argon2id in the PHC format,
+//with its parameters and salt
+pub struct Accounts{
+	path : PathBuf,
+	users : HashMap<String, String>
+}
+
+impl Accounts{
+	pub fn load(path : &str) -> Self{
+		let mut accounts = Accounts{
+			path : PathBuf::from(path),
+			users : HashMap::new()
+		};
+		let content = match fs::read_to_string(path){
+		    Ok(v) => {v}
+		    Err(_) => {return accounts;} //no one has registered yet
+		};
+		for (n, line) in content.lines().enumerate(){
+			match line.split_once(':'){
+			    Some((username, hash)) if PasswordHash::new(hash).is_ok() => {
+					accounts.users.insert(username.to_string(), hash.to_string());
+				}
+			    _ => {log!("Malformed line {} in {}", n + 1, path);}
+			}
+		}
+		return accounts;
+	}
+
+	pub fn is_registered(&self, username : &str) -> bool{
+		return self.users.contains_key(username);
+	}
+
+	//the hash of the user's password, to be checked against with verify
+	pub fn hash_of(&self, username : &str) -> Option<&str>{
+		return self.users.get(username).map(|hash| hash.as_str());
+	}
+
+	//hashes the password right away, which takes a while, clients' accounts are added with the hash from the hasher
+	pub fn register(&mut self, username : &str, password : &str) -> Result<(), String>{
+		if self.is_registered(username){
+			return Err("Username is already registered".to_string());
+		}
+		let hash = hash(password)?;
+		return self.add(username, hash);
+	}
+
+	//appends the account with the hash of its password to the file
+	pub fn add(&mut self, username : &str, hash : String) -> Result<(), String>{
+		if self.is_registered(username){
+			return Err("Username is already registered".to_string());
+		}
+		let line = format!("{}:{}", username, hash);
+		let file = fs::OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(&self.path);
+		if let Err(e) = file.and_then(|mut file| writeln!(file, "{}", line)){
+			log!("Couldn't save account {}: {}", username, e);
+			return Err("Couldn't save the account".to_string());
+		}
+		self.users.insert(username.to_string(), hash);
+		return Ok(());
+	}
+}
+
+//the hash of the password by Argon2id with a random salt, in the PHC format
+pub fn hash(password : &str) -> Result<String, String>{
+	let salt : Vec<u8> = (0..SALT_LEN).map(|_| rand::random::<u8>()).collect();
+	match SaltString::encode_b64(&salt).and_then(|salt| Ok(Argon2::default().hash_password(password.as_bytes(), &salt)?.to_string())){
+	    Ok(v) => {return Ok(v);}
+	    Err(e) => {
+			log!("Couldn't hash a password: {}", e);
+			return Err("Couldn't save the account".to_string());
+		}
+	}
+}
+
+//whether the password matches the hash
+pub fn verify(hash : &str, password : &str) -> bool{
+	match PasswordHash::new(hash){
+	    Ok(hash) => {return Argon2::default().verify_password(password.as_bytes(), &hash).is_ok();}
+	    Err(_) => {return false;}
+	}
+}
@@ -0,0 +1,230 @@
+use std::fmt;
+
+//commands for running a server, given on its terminal, sent to its admin socket by chatctl,
+//or sent by privileged users in ClMessage::Admin
+//over the socket each command and each reply is a line of JSON, a reply comes for every command:
+//{"Kick":{"username":"bob","reason":"spam"}}
+//{"Done":"bob was kicked"}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
+pub enum Command{
+	Kick{username : String, reason : String},
+	Say(String), //a message to everyone, from the server
+	Stop,
+	Mute{username : String, duration : Option<u64>}, //the user may no longer post, for the seconds if they are given
+	Unmute{username : String},
+	//kicks the user, or everyone connected from the addresses, who may not come back till the ban expires
+	//the target is a username, an IP address, or a range of them written as address/bits
+	Ban{target : String, reason : String, duration : Option<u64>}, //seconds, forever if it isn't given
+	Unban{target : String},
+	SetRole{username : String, role : Role},
+	BanList,
+	SlowMode{room : String, seconds : u64}, //each user may post in the room once in the seconds, 0 turns it off
+	//creates an account, users with roles may not register themselves, so that no one else takes their names first
+	Register{username : String, password : String}
+}
+
+//what has come out of a command
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
+pub enum Reply{
+	Done(String),
+	Failed(String)
+}
+
+//what a user is allowed to do, each role may do everything the ones before it may
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role{
+	User,
+	Moderator, //kicks and mutes users
+	Admin, //bans users, makes them moderators, and speaks for the server
+	Owner //makes users admins, and stops the server
+}
+
+impl Role{
+	pub fn parse(name : &str) -> Result<Self, String>{
+		match name{
+		    "user" => {return Ok(Role::User);}
+		    "moderator" => {return Ok(Role::Moderator);}
+		    "admin" => {return Ok(Role::Admin);}
+		    "owner" => {return Ok(Role::Owner);}
+		    _ => {return Err(format!("No such role: {}", name));}
+		}
+	}
+
+	//the article to put before the name of the role
+	pub fn article(&self) -> &'static str{
+		match self{
+		    Role::Admin | Role::Owner => {"an"}
+		    _ => {"a"}
+		}
+	}
+}
+
+impl fmt::Display for Role{
+	fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result{
+		match self{
+		    Role::User => {write!(f, "user")}
+		    Role::Moderator => {write!(f, "moderator")}
+		    Role::Admin => {write!(f, "admin")}
+		    Role::Owner => {write!(f, "owner")}
+		}
+	}
+}
+
+pub const HELP : &str = "kick <username> [reason] - kicks a user with <username>
+mute <username> [duration] - forbids a user to post, for 30m, 12h, 7d... or till unmuted
+unmute <username> - lets a muted user post again
+ban <username|address[/bits]> [duration] [reason] - kicks a user or addresses and forbids to come back, for 30m, 12h, 7d... or for good
+unban <username|address[/bits]> - lets a banned user or address range come back
+banlist - lists the bans
+slow <room> <duration|off> - lets each user post in a room once in the duration
+role <username> <user|moderator|admin|owner> - gives a role to a registered user
+register <username> <password> - creates an account, the only way for users with roles to get one
+say <message> - sends a message
+stop - stops the server";
+
+//the first word of arg, and the rest of it
+fn split(arg : &str) -> (&str, &str){
+	let arg = arg.trim();
+	match arg.find(' '){
+	    Some(pos) => {return (&arg[..pos], arg[pos..].trim());}
+	    None => {return (arg, "");}
+	}
+}
+
+fn username(arg : &str) -> Result<(String, String), String>{
+	let (username, rest) = split(arg);
+	if username.is_empty(){
+		return Err("No username provided".to_string());
+	}
+	return Ok((username.to_string(), rest.split_whitespace().collect::<Vec<&str>>().join(" ")));
+}
+
+impl Command{
+	//parses a command the way it is typed, the leading / is optional
+	pub fn parse(line : &str) -> Result<Self, String>{
+		let line = line.trim();
+		let (name, arg) = split(line.strip_prefix('/').unwrap_or(line));
+		match name{
+		    "kick" => {
+				let (username, reason) = username(arg)?;
+				return Ok(Command::Kick{username, reason});
+			}
+		    "say" => {
+				if arg.is_empty(){
+					return Err("No message provided".to_string());
+				}
+				return Ok(Command::Say(arg.to_string()));
+			}
+		    "stop" => {return Ok(Command::Stop);}
+		    "mute" => {
+				let (username, duration) = username(arg)?;
+				if duration.is_empty(){
+					return Ok(Command::Mute{username, duration : None});
+				}
+				return Ok(Command::Mute{username, duration : Some(parse_duration(&duration).ok_or("Invalid duration, try 30s, 10m or 2h")?)});
+			}
+		    "unmute" => {return Ok(Command::Unmute{username : username(arg)?.0});}
+		    "ban" => {
+				let (target, rest) = username(arg)?;
+				let (first, reason) = split(&rest);
+				match parse_duration(first){
+				    Some(duration) => {return Ok(Command::Ban{target, reason : reason.to_string(), duration : Some(duration)});}
+				    None => {return Ok(Command::Ban{target, reason : rest, duration : None});}
+				}
+			}
+		    "unban" => {return Ok(Command::Unban{target : username(arg)?.0});}
+		    "banlist" => {return Ok(Command::BanList);}
+		    "slow" => {
+				let (room, duration) = split(arg);
+				if room.is_empty(){
+					return Err("No room provided".to_string());
+				}
+				let seconds = match duration{
+				    "off" => {0}
+				    _ => {parse_duration(duration).ok_or("Invalid duration, try 30s, 10m or off")?}
+				};
+				return Ok(Command::SlowMode{room : room.to_string(), seconds});
+			}
+		    "role" => {
+				let (username, role) = username(arg)?;
+				return Ok(Command::SetRole{username, role : Role::parse(&role)?});
+			}
+		    "register" => {
+				let (username, password) = username(arg)?;
+				if password.is_empty(){
+					return Err("No password provided".to_string());
+				}
+				return Ok(Command::Register{username, password});
+			}
+		    _ => {return Err(format!("Unrecognized command: {}", name));}
+		}
+	}
+
+	//the least role a user needs to give the command, a server's terminal and admin socket may give any
+	pub fn required_role(&self) -> Role{
+		match self{
+		    Command::Kick{..} | Command::Mute{..} | Command::Unmute{..} | Command::BanList
+		    | Command::SlowMode{..} => {return Role::Moderator;}
+		    Command::Ban{..} | Command::Unban{..} | Command::Say(_) | Command::SetRole{..} => {return Role::Admin;}
+		    Command::Stop | Command::Register{..} => {return Role::Owner;}
+		}
+	}
+
+	//the command as it may be written down, without passwords
+	pub fn redacted(&self) -> Command{
+		match self{
+		    Command::Register{username, ..} => {return Command::Register{username : username.clone(), password : "***".to_string()};}
+		    _ => {return self.clone();}
+		}
+	}
+
+	//the name of the command, as it is typed
+	pub fn name(&self) -> &'static str{
+		match self{
+		    Command::Kick{..} => {"kick"}
+		    Command::Say(_) => {"say"}
+		    Command::Stop => {"stop"}
+		    Command::Mute{..} => {"mute"}
+		    Command::Unmute{..} => {"unmute"}
+		    Command::Ban{..} => {"ban"}
+		    Command::Unban{..} => {"unban"}
+		    Command::SetRole{..} => {"role"}
+		    Command::BanList => {"banlist"}
+		    Command::SlowMode{..} => {"slow"}
+		    Command::Register{..} => {"register"}
+		}
+	}
+}
+
+//units of durations, as they are written after the number
+const UNITS : [(char, u64); 5] = [('w', 7 * 24 * 3600), ('d', 24 * 3600), ('h', 3600), ('m', 60), ('s', 1)];
+
+//seconds in a duration such as 90s, 30m, 12h, 7d or 2w, None if the word isn't one
+pub fn parse_duration(word : &str) -> Option<u64>{
+	let unit = word.chars().last()?;
+	let (_, seconds) = UNITS.iter().find(|(c, _)| *c == unit)?;
+	let number = &word[..word.len() - 1];
+	if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()){
+		return None;
+	}
+	return number.parse::<u64>().ok()?.checked_mul(*seconds).filter(|d| *d > 0);
+}
+
+//a duration in at most two units, such as 1d 6h, rounded down
+pub fn format_duration(seconds : u64) -> String{
+	let mut parts = Vec::new();
+	let mut left = seconds;
+	for (unit, length) in UNITS.iter(){
+		if left >= *length && parts.len() < 2{
+			parts.push(format!("{}{}", left / length, unit));
+			left %= length;
+		}else if !parts.is_empty(){
+			break;
+		}
+	}
+	if parts.is_empty(){
+		return "0s".to_string();
+	}
+	return parts.join(" ");
+}
@@ -0,0 +1,38 @@
+use std::fs;
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use chat::admin::{Command, Reply};
+
+//append-only record of every privileged command, whether it was carried out or refused
+//one JSON object per line, with the time in seconds since the epoch:
+//{"time":1700000000,"by":"alice","command":{"Kick":{"username":"bob","reason":"spam"}},"reply":{"Done":"bob was kicked"}}
+pub struct Audit{
+	path : PathBuf
+}
+
+#[derive(serde::Serialize)]
+struct Entry<'a>{
+	time : u64,
+	by : &'a str,
+	command : &'a Command,
+	reply : &'a Reply
+}
+
+impl Audit{
+	pub fn new(path : &str) -> Self{
+		return Audit{path : PathBuf::from(path)};
+	}
+
+	pub fn record(&self, by : &str, command : &Command, reply : &Reply){
+		let time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|t| t.as_secs()).unwrap_or(0);
+		let line = serde_json::to_string(&Entry{time, by, command, reply}).expect("Failed to serialize");
+		let file = fs::OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(&self.path);
+		if let Err(e) = file.and_then(|mut file| writeln!(file, "{}", line)){
+			log!("Couldn't write to audit log {}: {}", self.path.display(), e);
+		}
+	}
+}
@@ -0,0 +1,239 @@
+use std::fs;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use chat::admin::format_duration;
+
+//what a ban covers, a user or a range of addresses
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target{
+	User(String),
+	Addresses(Network)
+}
+
+impl Target{
+	//usernames are alphanumeric, so anything with a dot, a colon or a slash is taken for an address
+	pub fn parse(target : &str) -> Result<Self, String>{
+		if target.chars().all(char::is_alphanumeric){
+			return Ok(Target::User(target.to_string()));
+		}
+		return Ok(Target::Addresses(Network::parse(target)?));
+	}
+}
+
+impl fmt::Display for Target{
+	fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result{
+		match self{
+		    Target::User(username) => {write!(f, "{}", username)}
+		    Target::Addresses(network) => {write!(f, "{}", network)}
+		}
+	}
+}
+
+//a range of IP addresses which share the first bits, a single address has all of them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Network{
+	addr : IpAddr, //with the bits past the prefix cleared
+	bits : u8
+}
+
+impl Network{
+	pub fn parse(text : &str) -> Result<Self, String>{
+		let invalid = || format!("{} is neither a username nor an address", text);
+		let (addr, bits) = match text.split_once('/'){
+		    Some((addr, bits)) => {(addr, Some(bits))}
+		    None => {(text, None)}
+		};
+		let addr : IpAddr = addr.parse().map_err(|_| invalid())?;
+		let addr = addr.to_canonical();
+		let max = if addr.is_ipv4() {32} else {128};
+		let bits = match bits{
+		    Some(bits) => {bits.parse::<u8>().ok().filter(|bits| *bits <= max).ok_or_else(invalid)?}
+		    None => {max}
+		};
+		return Ok(Network{addr : mask(addr, bits), bits});
+	}
+
+	//whether the range takes in a large part of the internet, such as 10.0.0.0/8 or 2001::/32
+	pub fn is_broad(&self) -> bool{
+		match self.addr{
+		    IpAddr::V4(_) => {return self.bits <= 8;}
+		    IpAddr::V6(_) => {return self.bits <= 32;}
+		}
+	}
+
+	pub fn contains(&self, addr : IpAddr) -> bool{
+		let addr = addr.to_canonical();
+		return addr.is_ipv4() == self.addr.is_ipv4() && mask(addr, self.bits) == self.addr;
+	}
+}
+
+impl fmt::Display for Network{
+	fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result{
+		match (self.addr, self.bits){
+		    (IpAddr::V4(addr), 32) => {write!(f, "{}", addr)}
+		    (IpAddr::V6(addr), 128) => {write!(f, "{}", addr)}
+		    (addr, bits) => {write!(f, "{}/{}", addr, bits)}
+		}
+	}
+}
+
+//the address with only its first bits kept
+fn mask(addr : IpAddr, bits : u8) -> IpAddr{
+	match addr{
+	    IpAddr::V4(addr) => {
+			let mask = u32::MAX.checked_shl(32 - bits as u32).unwrap_or(0);
+			return IpAddr::V4((u32::from(addr) & mask).into());
+		}
+	    IpAddr::V6(addr) => {
+			let mask = u128::MAX.checked_shl(128 - bits as u32).unwrap_or(0);
+			return IpAddr::V6((u128::from(addr) & mask).into());
+		}
+	}
+}
+
+//seconds since the epoch, bans expire at such a time so that they may outlive the server
+pub fn now() -> u64{
+	return SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|t| t.as_secs()).unwrap_or(0);
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct Ban{
+	pub target : String,
+	pub reason : String,
+	pub by : String,
+	pub until : Option<u64> //when the ban expires, in seconds since the epoch, never if it isn't set
+}
+
+impl Ban{
+	fn is_over(&self, now : u64) -> bool{
+		return matches!(self.until, Some(until) if until <= now);
+	}
+
+	//how long the ban lasts yet
+	fn left(&self) -> String{
+		match self.until{
+		    Some(until) => {return format!("for {}", format_duration(until.saturating_sub(now())));}
+		    None => {return "for good".to_string();}
+		}
+	}
+
+	//what a banned user is told
+	pub fn message(&self) -> String{
+		match self.until{
+		    Some(_) => {return format!("You are banned {}: {}", self.left(), self.reason);}
+		    None => {return format!("You are banned: {}", self.reason);}
+		}
+	}
+}
+
+impl fmt::Display for Ban{
+	fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result{
+		write!(f, "{} - banned by {} {}", self.target, self.by, self.left())?;
+		if !self.reason.is_empty(){
+			write!(f, ": {}", self.reason)?;
+		}
+		return Ok(());
+	}
+}
+
+//bans of users and addresses, expired ones are left out as they are come across
+//stored as JSON lines, one ban per line, the whole file is rewritten on every change:
+//{"target":"10.0.0.0/8","reason":"spam","by":"alice","until":1700000000}
+pub struct Bans{
+	path : PathBuf,
+	bans : Vec<(Target, Ban)>
+}
+
+impl Bans{
+	pub fn load(path : &str) -> Self{
+		let mut bans = Bans{
+			path : PathBuf::from(path),
+			bans : Vec::new()
+		};
+		let content = match fs::read_to_string(path){
+		    Ok(v) => {v}
+		    Err(_) => {return bans;} //no one has been banned yet
+		};
+		for (n, line) in content.lines().enumerate(){
+			let ban : Ban = match serde_json::from_str(line){
+			    Ok(v) => {v}
+			    Err(_) => {
+					log!("Malformed line {} in {}", n + 1, path);
+					continue;
+				}
+			};
+			match Target::parse(&ban.target){
+			    Ok(target) => {bans.bans.push((target, ban));}
+			    Err(_) => {log!("Malformed line {} in {}", n + 1, path);}
+			}
+		}
+		return bans;
+	}
+
+	//the ban of the user, if there is one
+	pub fn of_user(&mut self, username : &str) -> Option<&Ban>{
+		self.expire();
+		return self.bans.iter().find(|(target, _)| matches!(target, Target::User(u) if u == username)).map(|(_, ban)| ban);
+	}
+
+	//a ban of a range the address is in, if there is one
+	pub fn of_address(&mut self, addr : IpAddr) -> Option<&Ban>{
+		self.expire();
+		return self.bans.iter().find(|(target, _)| matches!(target, Target::Addresses(n) if n.contains(addr))).map(|(_, ban)| ban);
+	}
+
+	pub fn list(&mut self) -> &[(Target, Ban)]{
+		self.expire();
+		return &self.bans;
+	}
+
+	//bans the target, or changes its ban
+	pub fn add(&mut self, target : Target, ban : Ban) -> Result<(), String>{
+		self.expire();
+		let old = self.bans.clone();
+		self.bans.retain(|(t, _)| *t != target);
+		self.bans.push((target, ban));
+		return self.commit(old);
+	}
+
+	//whether there was a ban to lift
+	pub fn remove(&mut self, target : &Target) -> Result<bool, String>{
+		self.expire();
+		let old = self.bans.clone();
+		self.bans.retain(|(t, _)| t != target);
+		if self.bans.len() == old.len(){
+			return Ok(false);
+		}
+		self.commit(old)?;
+		return Ok(true);
+	}
+
+	//lets go of the bans which are over, they are dropped from the file with the next change
+	fn expire(&mut self){
+		let now = now();
+		self.bans.retain(|(_, ban)| !ban.is_over(now));
+	}
+
+	//saves the bans, going back to the old ones if they can't be
+	fn commit(&mut self, old : Vec<(Target, Ban)>) -> Result<(), String>{
+		if let Err(e) = self.save(){
+			log!("Couldn't save bans to {}: {}", self.path.display(), e);
+			self.bans = old;
+			return Err("Couldn't save the ban".to_string());
+		}
+		return Ok(());
+	}
+
+	//writes a new file and puts it in place of the old one, so that a failed write loses nothing
+	fn save(&self) -> std::io::Result<()>{
+		let content : String = self.bans.iter()
+			.map(|(_, ban)| serde_json::to_string(ban).expect("Failed to serialize") + "\n")
+			.collect();
+		let mut tmp = self.path.clone().into_os_string();
+		tmp.push(".tmp");
+		fs::write(&tmp, content)?;
+		return fs::rename(&tmp, &self.path);
+	}
+}
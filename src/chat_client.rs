@@ -0,0 +1,349 @@
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::time;
+use crate::net::*;
+use crate::tls;
+use crate::admin::Command;
+
+//how long to wait for the server to answer the hello
+const HELLO_TIMEOUT : time::Duration = time::Duration::from_secs(10);
+//token of the server's connection in the client's own poll
+const SERVER : mio::Token = mio::Token(0);
+
+pub enum ConnectError{
+	Io(String), //couldn't reach the server
+	Refused(String), //server has refused to let the client in, with its reason
+	Timeout, //server hasn't answered the hello
+	Unexpected, //server has answered the hello with something else than a hello
+	Protocol(Error) //the hello couldn't be sent, or the answer couldn't be read
+}
+
+impl fmt::Display for ConnectError{
+	fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result{
+		match self{
+		    ConnectError::Io(e) => {write!(f, "Couldn't connect: {}", e)}
+		    ConnectError::Refused(reason) => {write!(f, "Server refused in connection for the reason: {}", reason)}
+		    ConnectError::Timeout => {write!(f, "Timed out")}
+		    ConnectError::Unexpected => {write!(f, "Expected hello from server, got something else")}
+		    ConnectError::Protocol(e) => {write!(f, "{}", e)}
+		}
+	}
+}
+
+impl fmt::Debug for ConnectError{
+	fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result{
+		return fmt::Display::fmt(self, f);
+	}
+}
+
+//a connection to a chat server, speaking the protocol on behalf of a user
+//requests are sent right away, while whatever the server says comes as SeMessage events,
+//either from poll() which doesn't wait, or from the events() iterator which does
+//a client can be registered into an outside mio::Poll as any other event source,
+//then poll() is to be called whenever it gets ready, and keep_alive() at least every ping_in()
+pub struct ChatClient{
+	peer : Peer<SeMessage>,
+	closed : bool, //whether the server has disconnected the client
+	lost : bool, //whether the connection was closed without the server saying why
+	own_poll : Option<(mio::Poll, mio::Events)> //for waiting in events(), unless registered elsewhere
+}
+
+impl ChatClient{
+	//connects as a guest
+	pub fn join(addr : &str, username : &str, tls : Option<&Arc<rustls::ClientConfig>>) -> Result<Self, ConnectError>{
+		let hello = ClMessage::Hello(ClHello{username : username.to_string()});
+		return ChatClient::connect(addr, username, &hello, tls);
+	}
+
+	//connects with a registered account
+	pub fn login(addr : &str, username : &str, password : &str, tls : Option<&Arc<rustls::ClientConfig>>)
+		-> Result<Self, ConnectError>{
+		let hello = ClMessage::Login(ClCredentials{username : username.to_string(), password : password.to_string()});
+		return ChatClient::connect(addr, username, &hello, tls);
+	}
+
+	//registers an account and connects with it
+	pub fn register(addr : &str, username : &str, password : &str, tls : Option<&Arc<rustls::ClientConfig>>)
+		-> Result<Self, ConnectError>{
+		let hello = ClMessage::Register(ClCredentials{username : username.to_string(), password : password.to_string()});
+		return ChatClient::connect(addr, username, &hello, tls);
+	}
+
+	//takes back the session of a lost connection, with the username and the token it had
+	//the server sends SeMessage::Room for every room of the session, then the messages missed in between
+	pub fn resume(addr : &str, username : &str, token : Token, tls : Option<&Arc<rustls::ClientConfig>>)
+		-> Result<Self, ConnectError>{
+		return ChatClient::connect(addr, username, &ClMessage::Resume(token), tls);
+	}
+
+	//connects to a server and greets it with hello, which is either of Hello, Register, Login or Resume
+	//the connection is encrypted if tls is given
+	pub fn connect(addr : &str, username : &str, hello : &ClMessage, tls : Option<&Arc<rustls::ClientConfig>>)
+		-> Result<Self, ConnectError>{
+		return ChatClient::connect_encoded(addr, username, hello, tls, Encoding::Bincode);
+	}
+
+	//like connect, but speaks the given encoding
+	pub fn connect_encoded(addr : &str, username : &str, hello : &ClMessage, tls : Option<&Arc<rustls::ClientConfig>>,
+		encoding : Encoding) -> Result<Self, ConnectError>{
+		let io_err = |e : io::Error| ConnectError::Io(e.to_string());
+		let stream = match tls{
+		    Some(config) => {tls::connect(config, addr).map_err(ConnectError::Io)?}
+		    None => {
+				let stream = std::net::TcpStream::connect(addr).map_err(io_err)?;
+				stream.set_nonblocking(true).map_err(io_err)?;
+				Stream::Plain(mio::net::TcpStream::from_std(stream))
+			}
+		};
+		let mut peer : Peer<SeMessage> = Peer::with_encoding(&0u64, stream, Some(encoding));
+		peer.username = username.to_string();
+		let poll = mio::Poll::new().map_err(io_err)?;
+		let interests = mio::Interest::READABLE | mio::Interest::WRITABLE;
+		poll.registry().register(&mut peer, SERVER, interests).map_err(io_err)?;
+		let mut client = ChatClient{peer, closed : false, lost : false, own_poll : Some((poll, mio::Events::with_capacity(16)))};
+		let version = ClVersion{
+			version : PROTOCOL_VERSION,
+			oldest : PROTOCOL_VERSION,
+			capabilities : CAPABILITIES.iter().map(|c| c.to_string()).collect()
+		};
+		client.peer.send(&ClMessage::Version(version)).map_err(ConnectError::Protocol)?;
+		client.peer.send(hello).map_err(ConnectError::Protocol)?;
+		let started_at = time::Instant::now();
+		loop{ //loop till something arrives
+			//wait some time for server to respond
+			let left = match HELLO_TIMEOUT.checked_sub(started_at.elapsed()){
+			    Some(v) => {v}
+			    None => {return Err(ConnectError::Timeout);}
+			};
+			let response = match client.peer.messages.pop_front(){
+			    Some(v) => {v}
+			    None => {
+					client.wait(Some(left)).map_err(io_err)?;
+					client.peer.flush();
+					//a refusal comes right before the server closes the connection
+					if let Err(e) = client.peer.get_messages(){
+						if client.peer.messages.is_empty(){
+							return Err(ConnectError::Protocol(e));
+						}
+					}
+					continue;
+				}
+			};
+			match response{
+			    SeMessage::Version(msg) => {
+					client.peer.version = msg.version;
+					client.peer.capabilities = msg.capabilities;
+				}
+			    SeMessage::Hello(msg) => {
+					client.peer.token = msg.token;
+					client.peer.state = PeerState::Chatting;
+					break;
+				}
+			    SeMessage::UQuit(mesg) => {return Err(ConnectError::Refused(mesg.reason));}
+			    _ => {return Err(ConnectError::Unexpected);}
+			}
+		}
+		return Ok(client);
+	}
+
+	//blocks until the server says something, or the timeout passes
+	//the client waits in its own poll, which is created anew if it was registered elsewhere
+	pub fn wait(&mut self, timeout : Option<time::Duration>) -> io::Result<()>{
+		if self.own_poll.is_none(){
+			let poll = mio::Poll::new()?;
+			let interests = mio::Interest::READABLE | mio::Interest::WRITABLE;
+			poll.registry().register(&mut self.peer, SERVER, interests)?;
+			self.own_poll = Some((poll, mio::Events::with_capacity(16)));
+		}
+		if let Some((poll, events)) = &mut self.own_poll{
+			match poll.poll(events, timeout){
+			    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+			    Err(e) => {return Err(e);}
+			    Ok(_) => {}
+			}
+		}
+		return Ok(());
+	}
+
+	pub fn token(&self) -> Token{
+		return self.peer.token;
+	}
+
+	pub fn username(&self) -> &str{
+		return &self.peer.username;
+	}
+
+	//the version of the protocol spoken with the server
+	pub fn version(&self) -> u16{
+		return self.peer.version;
+	}
+
+	//whether the server supports a feature of the protocol, one of net::CAPABILITIES
+	pub fn has_capability(&self, capability : &str) -> bool{
+		return self.peer.capabilities.iter().any(|c| c == capability);
+	}
+
+	//whether the server has disconnected the client
+	pub fn is_closed(&self) -> bool{
+		return self.closed;
+	}
+
+	//whether the connection has been closed or reset without the server saying why,
+	//like when the server has gone down or the network has failed
+	pub fn is_lost(&self) -> bool{
+		return self.lost;
+	}
+
+	//requests fail only if the message can't be sent at all, like when it is too large
+	pub fn say(&mut self, room : &str, mesg : &str) -> Result<(), Error>{
+		let mesg = ClMesg{
+			token : self.peer.token,
+			room : room.to_string(),
+			mesg : mesg.to_string()
+		};
+		return self.peer.send(&ClMessage::Mesg(mesg));
+	}
+
+	pub fn direct(&mut self, to : &str, mesg : &str) -> Result<(), Error>{
+		let mesg = ClDirect{
+			token : self.peer.token,
+			to : to.to_string(),
+			mesg : mesg.to_string()
+		};
+		return self.peer.send(&ClMessage::Direct(mesg));
+	}
+
+	//asks for SeMessage::Info
+	pub fn info(&mut self) -> Result<(), Error>{
+		return self.peer.send(&ClMessage::IWantInfo(self.peer.token));
+	}
+
+	//asks for SeMessage::History with up to count last messages of the room
+	pub fn history(&mut self, room : &str, count : usize) -> Result<(), Error>{
+		let mesg = ClHistory{token : self.peer.token, room : room.to_string(), count};
+		return self.peer.send(&ClMessage::History(mesg));
+	}
+
+	//asks for another username, the server confirms it with SeMessage::Renamed
+	pub fn rename(&mut self, username : &str) -> Result<(), Error>{
+		let mesg = ClRename{token : self.peer.token, username : username.to_string()};
+		return self.peer.send(&ClMessage::Rename(mesg));
+	}
+
+	//gives a command which the user's role allows, the server answers with SeMessage::Reply
+	pub fn admin(&mut self, command : Command) -> Result<(), Error>{
+		let mesg = ClAdmin{token : self.peer.token, command};
+		return self.peer.send(&ClMessage::Admin(mesg));
+	}
+
+	//creates, enters or leaves a room, the server confirms it with SeMessage::Room
+	pub fn room(&mut self, room : &str, action : RoomAction) -> Result<(), Error>{
+		let mesg = ClRoom{token : self.peer.token, room : room.to_string(), action};
+		return self.peer.send(&ClMessage::Room(mesg));
+	}
+
+	//pings the server if the client has been silent for long enough to risk being kicked
+	pub fn keep_alive(&mut self){
+		//if had been silent for more than half of allowed max silence time
+		if self.peer.silent_from.elapsed() > MAX_SILENCE / 2{
+			//a ping always fits into a packet
+			let _ = self.peer.send(&ClMessage::Ping(self.peer.token));
+		}
+	}
+
+	//how long the client may stay silent before keep_alive() is due
+	pub fn ping_in(&self) -> time::Duration{
+		return (MAX_SILENCE / 2).saturating_sub(self.peer.silent_from.elapsed());
+	}
+
+	//leaves the server
+	pub fn quit(mut self){
+		let _ = self.peer.send(&ClMessage::IQuit(self.peer.token));
+	}
+
+	//returns what the server has said since the last call, without waiting
+	//if the server sends something unreadable or the connection breaks, the client ends with an UQuit telling why
+	pub fn poll(&mut self) -> Vec<SeMessage>{
+		if self.closed{
+			return Vec::new();
+		}
+		self.keep_alive();
+		self.peer.flush();
+		if let Err(e) = self.peer.get_messages(){
+			//the server says why it closes the connection before closing it
+			let told = self.peer.messages.iter().any(|m| matches!(m, SeMessage::UQuit(_)));
+			if !told{
+				let reason = match e{
+				    Error::Closed => {
+						self.lost = true;
+						"Connection lost".to_string()
+					}
+				    e => {format!("Protocol error: {}", e)}
+				};
+				self.peer.messages.push_back(SeMessage::UQuit(SeUQuit{reason}));
+			}
+		}
+		let mut messages = Vec::new();
+		while let Some(msg) = self.peer.messages.pop_front(){
+			match &msg{
+			    SeMessage::UQuit(_) => {self.closed = true;}
+			    SeMessage::Renamed(m) => {self.peer.username = m.new.clone();}
+			    _ => {}
+			}
+			messages.push(msg);
+		}
+		return messages;
+	}
+
+	//iterator over messages from the server, which waits for each of them
+	//it ends after the server has disconnected the client
+	pub fn events(&mut self) -> Events<'_>{
+		return Events{client : self, pending : std::collections::VecDeque::new()};
+	}
+}
+
+pub struct Events<'a>{
+	client : &'a mut ChatClient,
+	pending : std::collections::VecDeque<SeMessage>
+}
+
+impl Iterator for Events<'_>{
+	type Item = SeMessage;
+
+	fn next(&mut self) -> Option<SeMessage>{
+		loop{
+			if let Some(msg) = self.pending.pop_front(){
+				return Some(msg);
+			}
+			if self.client.is_closed(){
+				return None;
+			}
+			self.pending.extend(self.client.poll());
+			if self.pending.is_empty(){
+				let timeout = self.client.ping_in();
+				if self.client.wait(Some(timeout)).is_err(){
+					return None;
+				}
+			}
+		}
+	}
+}
+
+//registering the client elsewhere takes it out of its own poll
+impl mio::event::Source for ChatClient{
+	fn register(&mut self, registry : &mio::Registry, token : mio::Token, interests : mio::Interest) -> io::Result<()>{
+		if let Some((poll, _)) = self.own_poll.take(){
+			poll.registry().deregister(&mut self.peer)?;
+		}
+		return self.peer.register(registry, token, interests);
+	}
+
+	fn reregister(&mut self, registry : &mio::Registry, token : mio::Token, interests : mio::Interest) -> io::Result<()>{
+		return self.peer.reregister(registry, token, interests);
+	}
+
+	fn deregister(&mut self, registry : &mio::Registry) -> io::Result<()>{
+		return self.peer.deregister(registry);
+	}
+}
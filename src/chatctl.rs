@@ -0,0 +1,88 @@
+#![allow(clippy::needless_return)]
+//sends a command to a running server through its admin socket, and prints the reply
+use chat::admin::{self, Command, Reply};
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+const DEFAULT_SOCKET : &str = "chat.sock";
+
+fn print_usage(){
+	println!("Usage: chatctl [--socket <file>] <command>
+--socket <file> - the admin socket of the server, {} by default
+Commands:
+{}", DEFAULT_SOCKET, admin::HELP);
+}
+
+//the path of the socket and the command, from the command-line arguments
+fn parse_args() -> Result<(String, Command), String>{
+	let mut socket = DEFAULT_SOCKET.to_string();
+	let mut words : Vec<String> = Vec::new();
+	let mut args = std::env::args().skip(1);
+	while let Some(arg) = args.next(){
+		match arg.as_str(){
+		    "--socket" if words.is_empty() => {
+				match args.next(){
+				    Some(v) => {socket = v}
+				    None => {return Err("--socket needs a path".to_string());}
+				}
+			}
+		    "--help" | "help" if words.is_empty() => {
+				print_usage();
+				std::process::exit(0);
+			}
+		    _ => {words.push(arg)}
+		}
+	}
+	if words.is_empty(){
+		return Err("No command given".to_string());
+	}
+	let command = Command::parse(&words.join(" "))?;
+	return Ok((socket, command));
+}
+
+fn send(socket : &str, command : &Command) -> Result<Reply, String>{
+	let mut stream = match UnixStream::connect(socket){
+	    Ok(v) => {v}
+	    Err(e) => {return Err(format!("Couldn't connect to {}: {}", socket, e));}
+	};
+	let _ = stream.set_read_timeout(Some(Duration::from_secs(10)));
+	let mut line = serde_json::to_string(command).expect("Failed to serialize");
+	line.push('\n');
+	if let Err(e) = stream.write_all(line.as_bytes()){
+		return Err(format!("Couldn't send the command: {}", e));
+	}
+	let mut reply = String::new();
+	match BufReader::new(stream).read_line(&mut reply){
+	    Ok(0) => {return Err("The server has closed the connection without a reply".to_string());}
+	    Ok(_) => {}
+	    Err(e) => {return Err(format!("Couldn't get a reply: {}", e));}
+	}
+	match serde_json::from_str(&reply){
+	    Ok(v) => {return Ok(v);}
+	    Err(e) => {return Err(format!("Malformed reply: {}", e));}
+	}
+}
+
+fn main(){
+	let (socket, command) = match parse_args(){
+	    Ok(v) => {v}
+	    Err(e) => {
+			eprintln!("{}", e);
+			print_usage();
+			std::process::exit(2);
+		}
+	};
+	match send(&socket, &command){
+	    Ok(Reply::Done(v)) => {println!("{}", v)}
+	    Ok(Reply::Failed(v)) => {
+			eprintln!("{}", v);
+			std::process::exit(1);
+		}
+	    Err(e) => {
+			eprintln!("{}", e);
+			std::process::exit(2);
+		}
+	}
+}
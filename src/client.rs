@@ -1,60 +1,143 @@
-mod net;
-use net::*;
+#![allow(clippy::needless_return)]
+use chat::net::*;
+use chat::tls;
+use chat::admin::{self, Command, Reply};
+use chat::{ChatClient, ConnectError, Encoding};
+use std::io;
+use std::sync::{mpsc, Arc};
 use std::time;
-use std::sync::mpsc;
+
+//how many messages /history asks for if no count is given
+const DEFAULT_HISTORY : usize = 20;
+
+//tokens of the event sources in poll
+const INPUT : mio::Token = mio::Token(0);
+const SERVER : mio::Token = mio::Token(1);
+
+//after a lost connection, the client waits this long before the first try to restore it,
+//and twice as long before every next one, but never longer than MAX_RETRY
+const FIRST_RETRY : time::Duration = time::Duration::from_secs(1);
+const MAX_RETRY : time::Duration = time::Duration::from_secs(30);
+//how many tries the client makes before giving up
+const MAX_RECONNECTS : u32 = 10;
+
+//ends a message written with /compose, on a line of its own
+const END_OF_MESSAGE : &str = ".";
+
+//a lost connection which the client tries to restore
+struct Reconnect{
+	username : String, //of the lost session
+	token : Token, //of the lost session, to resume it
+	attempts : u32,
+	next_at : time::Instant
+}
+
+//a message of many lines being written, sent once a line with END_OF_MESSAGE comes
+struct Compose{
+	to : Option<String>, //the user it goes to, it goes to the current room if it is None
+	lines : Vec<String>
+}
+
+//what has come of a message being composed after a line
+#[derive(Debug, PartialEq)]
+enum Composed{
+	Writing, //the message goes on
+	Cancelled,
+	Empty, //it has ended with nothing but blanks in it
+	Finished(String)
+}
+
+impl Compose{
+	fn new(to : Option<String>) -> Self{
+		return Compose{to, lines : Vec::new()};
+	}
+
+	//takes a line of the message, which is kept as it was typed, leading spaces included
+	fn add(&mut self, input : &str) -> Composed{
+		let line = input.trim_end_matches(['\r', '\n']);
+		if line == "/cancel"{
+			return Composed::Cancelled;
+		}
+		if line != END_OF_MESSAGE{
+			self.lines.push(line.to_string());
+			return Composed::Writing;
+		}
+		let mesg = self.lines.join("\n");
+		if mesg.trim().is_empty(){
+			return Composed::Empty;
+		}
+		return Composed::Finished(mesg);
+	}
+}
 
 struct Client{
 	should_stop : bool,
-	server : Option<Peer<SeMessage>>,
-	input_rx : mpsc::Receiver<String>,
-	username : String
+	server : Option<ChatClient>,
+	poll : mio::Poll, //reports readiness of the server, and input from input thread
+	input_rx : mpsc::Receiver<String>, //input thread wakes poll with INPUT after sending
+	username : String,
+	rooms : Vec<String>, //rooms the client is in, the last one is the current room
+	tls : Option<Arc<rustls::ClientConfig>>, //servers are joined over TLS if it is set
+	encoding : Encoding, //of the messages exchanged with servers
+	last_server : Option<(String, ClMessage)>, //address and hello of the last server joined, for /reconnect
+	reconnect : Option<Reconnect>, //set while the client tries to restore a lost connection
+	compose : Option<Compose>, //set while a message of many lines is written, input goes to it then
 }
 
 impl Client{
-	pub fn new(input_rx : mpsc::Receiver<String>, username : String) -> Self{
+	pub fn new(poll : mio::Poll, input_rx : mpsc::Receiver<String>, username : String,
+		tls : Option<Arc<rustls::ClientConfig>>, encoding : Encoding) -> Self{
 		return Client{
 			should_stop : false,
 			server : None,
+			poll,
 			input_rx,
-			username
+			username,
+			rooms : Vec::new(),
+			tls,
+			encoding,
+			last_server : None,
+			reconnect : None,
+			compose : None
 		};
 	}
 
 	pub fn run(&mut self){
 		println!("For list of availible commands type /help");
+		let mut events = mio::Events::with_capacity(64);
 		while !self.should_stop{
+			//wake up in time to ping the server, or to try to reconnect
+			let mut timeout = self.server.as_ref().map(|server| server.ping_in());
+			if let Some(reconnect) = &self.reconnect{
+				let left = reconnect.next_at.saturating_duration_since(time::Instant::now());
+				timeout = Some(timeout.map_or(left, |t| t.min(left)));
+			}
+			match self.poll.poll(&mut events, timeout){
+			    Ok(_) => {}
+			    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {continue;}
+			    Err(e) => {
+					eprintln!("Failed to poll: {}", e);
+					return;
+				}
+			}
 			self.process_input();
 			self.process_messages();
 			self.reset_inactive();
+			self.retry();
 		}
 	}
 
 	pub fn reset_inactive(&mut self){
-		let server = match &mut self.server{
-		    Some(v) => {v}
-		    None => {return;}
-		};
-		//if had been silent for more than half of allowed max silence time
-		if server.silent_from.elapsed() > MAX_SILENCE / 2{
-			server.send(&ClMessage::Ping(server.token));
-			server.silent_from = time::Instant::now();
+		if let Some(server) = &mut self.server{
+			server.keep_alive();
 		}
 	}
 
 	pub fn process_messages(&mut self){
-		let mut server = match &mut self.server {
-		    Some(v) => {v}
+		let messages = match &mut self.server {
+		    Some(v) => {v.poll()}
 		    None => {return;}
 		};
-		server.get_messages();
-		let mut messages : Vec<SeMessage> = Vec::new();
-		loop{
-			let msg = match server.messages.pop_back(){
-			    Some(v) => {v}
-			    None => {break;}
-			};
-			messages.push(msg);
-		}
 		for msg in messages{
 			self.process_message(&msg);
 		}
@@ -63,7 +146,11 @@ impl Client{
 	pub fn process_message(&mut self, msg : &SeMessage){
 		match msg{
 		    SeMessage::Mesg(v) => {
-				println!("{}", v.mesg);
+				if v.room.is_empty(){
+					print_mesg("", &v.mesg);
+				}else{
+					print_mesg(&format!("[{}] ", v.room), &v.mesg);
+				}
 			}
 		    SeMessage::Info(v) => {
 				println!("--------------------");
@@ -71,21 +158,91 @@ impl Client{
 				for user in v.users.iter(){
 					println!("- {}", user);
 				}
+				println!("There are {} rooms on the server:", v.rooms.len());
+				for room in v.rooms.iter(){
+					println!("- {} ({}): {}", room.name, room.users.len(), room.users.join(", "));
+				}
 				println!("--------------------");
 			}
 		    SeMessage::UQuit(v) => {
-				println!("You was kicked for the reason: {}", v.reason);
-				self.disconnect();
+				let lost = match &self.server{
+				    Some(server) if server.is_lost() => {Some((server.username().to_string(), server.token()))}
+				    _ => {None}
+				};
+				if let Some((username, token)) = lost{
+					//there is nobody to say goodbye to
+					self.server = None;
+					self.rooms.clear();
+					println!("Connection lost, reconnecting...");
+					let next_at = time::Instant::now() + FIRST_RETRY;
+					self.reconnect = Some(Reconnect{username, token, attempts : 0, next_at});
+				}else{
+					println!("You was kicked for the reason: {}", v.reason);
+					self.disconnect();
+				}
 			}
 			SeMessage::Hello(_) => {}//handled by join()
+			SeMessage::Version(_) => {}//handled by join()
+		    SeMessage::Room(v) => {
+				self.rooms.retain(|room| *room != v.room);
+				match v.action{
+				    RoomAction::Leave => {
+						println!("You left room {}", v.room);
+					}
+				    _ => {
+						self.rooms.push(v.room.clone());
+						println!("You are now in room {}", v.room);
+					}
+				}
+			}
+		    SeMessage::Renamed(v) => {
+				println!("Your name was changed from {} to {}", v.old, v.new);
+				self.username = v.new.clone();
+				//a guest comes back under the new name
+				if let Some((_, ClMessage::Hello(hello))) = &mut self.last_server{
+					hello.username = v.new.clone();
+				}
+			}
+		    SeMessage::Error(v) => {
+				println!("Error: {}", v.reason);
+			}
+		    SeMessage::Reply(Reply::Done(v)) => {println!("{}", v)}
+		    SeMessage::Reply(Reply::Failed(v)) => {println!("Error: {}", v)}
+		    SeMessage::History(v) => {
+				println!("----- last {} messages of {} -----", v.mesgs.len(), v.room);
+				for mesg in v.mesgs.iter(){
+					print_mesg(&format!("[{}] ", v.room), mesg);
+				}
+				println!("--------------------");
+			}
+		    SeMessage::Direct(v) => {
+				let own = match &self.server{
+				    Some(server) => {v.from == server.username()}
+				    None => {false}
+				};
+				if own{
+					print_mesg(&format!("<-> to {}: ", v.to), &v.mesg);
+				}else{
+					print_mesg(&format!("<-> from {}: ", v.from), &v.mesg);
+				}
+			}
 		}
 	}
 
 	pub fn process_input(&mut self){
-		let input = match self.input_rx.try_recv(){
-		    Ok(v) => {v},
-		    Err(_) => {return;}
-		};
+		while let Ok(input) = self.input_rx.try_recv(){
+			if self.compose.is_some(){
+				self.compose_line(input);
+				continue;
+			}
+			self.process_command(input);
+			if self.should_stop{
+				return;
+			}
+		}
+	}
+
+	pub fn process_command(&mut self, input : String){
 		let mut command = input.trim().to_string();
 		let arg = match command.find(' ') {
 		    Some(pos) => {command.split_off(pos).trim().to_string()}
@@ -107,39 +264,99 @@ impl Client{
 			"/join" => {
 				self.join(arg);
 			}
+			"/login" => {
+				self.login(arg, false);
+			}
+			"/register" => {
+				self.login(arg, true);
+			}
+			"/reconnect" => {
+				self.reconnect();
+			}
 			"/say" => {
 				self.say(arg);
 			}
+			"/msg" => {
+				self.direct(arg);
+			}
+			"/compose" => {
+				self.start_compose(arg);
+			}
+			"/history" => {
+				self.request_history(arg);
+			}
 			"/name" => {
 				println!("Your name is {}", self.username);
 			}
 			"/chname" =>{
-				println!("Your name was changed from {} to {}", self.username, arg);
-				self.username = arg;
+				self.rename(arg);
+			}
+			"/kick" | "/mute" | "/unmute" | "/ban" | "/unban" | "/banlist" | "/role" | "/slow" => {
+				self.admin(&input);
+			}
+			"/create" => {
+				self.room(arg, RoomAction::Create);
+			}
+			"/enter" => {
+				self.room(arg, RoomAction::Enter);
+			}
+			"/leave" => {
+				let room = if arg.is_empty(){
+					match self.rooms.last(){
+					    Some(v) => {v.clone()}
+					    None => {
+							println!("You are not in any room");
+							return;
+						}
+					}
+				}else{
+					arg
+				};
+				self.room(room, RoomAction::Leave);
 			}
 			_ =>{println!("Unrecognized command. Try /help")}
 		}
 	}
 
-	pub fn request_server_info(&mut self){
-		let mut server = match &mut self.server {
+	//sends a command of a moderator or an admin, the server tells whether the user's role allows it
+	pub fn admin(&mut self, input : &str){
+		let command = match Command::parse(input){
+		    Ok(v) => {v}
+		    Err(e) => {
+				println!("{}", e);
+				return;
+			}
+		};
+		let server = match &mut self.server {
 			Some(v) => {v}
 			None => {
-				println!("Can't /info - not connected to a server");
+				println!("Can't /{} - not connected to a server", command.name());
 				return;
 			}
 		};
-		server.send(&ClMessage::IWantInfo(server.token));
+		if !server.has_capability("admin"){
+			println!("The server doesn't support moderation");
+			return;
+		}
+		if let Err(e) = server.admin(command){
+			println!("Couldn't send: {}", e);
+		}
 	}
 
-	pub fn join(&mut self, arg: String){
-		match self.server{
-		    Some(_) => {
-				println!("Already connected to a server");
+	pub fn request_server_info(&mut self){
+		let server = match &mut self.server {
+			Some(v) => {v}
+			None => {
+				println!("Can't /info - not connected to a server");
 				return;
 			}
-		    None => {}
 		};
+		if let Err(e) = server.info(){
+			println!("Couldn't send: {}", e);
+		}
+	}
+
+	pub fn join(&mut self, arg: String){
 		let mut iter = arg.split_whitespace();
 		let addr = match iter.next(){
 		    Some(v) => {v}
@@ -148,56 +365,174 @@ impl Client{
 				return;
 			}
 		};
-		let stream = match std::net::TcpStream::connect(&addr){
-		    Ok(v) => {v}
-		    Err(e) => {
-				eprintln!("Couldn't connect: {:?}", e);
+		let hello = ClMessage::Hello(ClHello{username : self.username.clone()});
+		self.connect(addr, &hello);
+	}
+
+	//joins with a registered account, registering it first if register is true
+	pub fn login(&mut self, arg: String, register: bool){
+		let mut iter = arg.split_whitespace();
+		let (addr, password) = match (iter.next(), iter.next()){
+		    (Some(addr), Some(password)) => {(addr, password)}
+		    _ => {
+				eprintln!("Usage: /login <adress> <password> or /register <adress> <password>");
 				return;
 			}
 		};
-		let mut peer : Peer<SeMessage> = Peer::new(&0u64, stream);
-		peer.username = self.username.clone();
-		let hello = ClMessage::Hello(ClHello{username : self.username.clone()});
-		peer.send(&hello);
-		let started_at = std::time::Instant::now();
-		println!("Connecting...");
-		loop{ //loop till something arrives
-			peer.get_messages();
-			//wait some time for server to respond
-			if std::time::Duration::from_secs(10) < started_at.elapsed(){
-				eprintln!("Timed out");
+		let credentials = ClCredentials{username : self.username.clone(), password : password.to_string()};
+		let hello = if register{
+			ClMessage::Register(credentials)
+		}else{
+			ClMessage::Login(credentials)
+		};
+		self.connect(addr, &hello);
+	}
+
+	//joins the last server once more, the same way as before
+	//if a lost connection is being restored, the next try is made right away
+	pub fn reconnect(&mut self){
+		if let Some(reconnect) = &mut self.reconnect{
+			reconnect.attempts = 0;
+			reconnect.next_at = time::Instant::now();
+			return;
+		}
+		let (addr, hello) = match &self.last_server{
+		    Some(v) => {v.clone()}
+		    None => {
+				println!("Can't /reconnect - no server was joined yet");
+				return;
+			}
+		};
+		self.connect(&addr, &hello);
+	}
+
+	//tries to restore a lost connection once it is time to,
+	//resuming the session if the server still keeps it, and joining anew otherwise
+	pub fn retry(&mut self){
+		let (username, token) = match &self.reconnect{
+		    Some(r) if r.next_at <= time::Instant::now() => {(r.username.clone(), r.token)}
+		    _ => {return;}
+		};
+		let (addr, hello) = match &self.last_server{
+		    Some(v) => {v.clone()}
+		    None => {
+				self.reconnect = None;
 				return;
 			}
-			let response = match peer.messages.pop_back(){
-			    Some(v) => {v}
-			    None => {continue;}
-			};
-			match response{
-			    SeMessage::Hello(msg) => {
-					peer.token = msg.token;
-					break;
+		};
+		let resume = ClMessage::Resume(token);
+		match ChatClient::connect_encoded(&addr, &username, &resume, self.tls.as_ref(), self.encoding){
+		    Ok(v) => {
+				self.reconnect = None;
+				if self.attach(v){
+					println!("Reconnected");
+				}
+			}
+		    Err(ConnectError::Refused(reason)) => {
+				//the server is up, but has let the session go
+				println!("Couldn't resume the session: {}", reason);
+				self.reconnect = None;
+				self.connect(&addr, &hello);
+			}
+		    Err(e) => {
+				if let Some(r) = &mut self.reconnect{
+					r.attempts += 1;
+					if r.attempts >= MAX_RECONNECTS{
+						println!("Couldn't reconnect: {}. Type /reconnect to try again", e);
+						self.reconnect = None;
+					}else{
+						let delay = (FIRST_RETRY * 2u32.pow(r.attempts)).min(MAX_RETRY);
+						println!("Couldn't reconnect: {}. Next try in {}s", e, delay.as_secs());
+						r.next_at = time::Instant::now() + delay;
+					}
 				}
-				SeMessage::UQuit(mesg) =>{
-					eprintln!("Server refused in connection for the reason: {}", mesg.reason);
+			}
+		}
+	}
+
+	//connects to a server and greets it with hello, which is either of Hello, Register or Login
+	fn connect(&mut self, addr: &str, hello: &ClMessage){
+		if self.server.is_some(){
+			println!("Already connected to a server");
+			return;
+		}
+		self.reconnect = None;
+		println!("Connecting...");
+		match ChatClient::connect_encoded(addr, &self.username, hello, self.tls.as_ref(), self.encoding){
+		    Ok(v) => {
+				if !self.attach(v){
 					return;
 				}
-			    _ => {
-					eprintln!("Error: Expected hello from server, got something else");
-					return;
+				println!("Connected");
+				//an account is registered once, then it is logged into
+				let hello = match hello{
+				    ClMessage::Register(credentials) => {ClMessage::Login(credentials.clone())}
+				    hello => {hello.clone()}
+				};
+				self.last_server = Some((addr.to_string(), hello));
+			}
+		    Err(e) => {eprintln!("{}", e)}
+		}
+	}
+
+	//starts watching the connection to a server
+	fn attach(&mut self, mut server : ChatClient) -> bool{
+		let interests = mio::Interest::READABLE | mio::Interest::WRITABLE;
+		if let Err(e) = self.poll.registry().register(&mut server, SERVER, interests){
+			eprintln!("Couldn't watch the connection: {}", e);
+			return false;
+		}
+		self.server = Some(server);
+		return true;
+	}
+
+	//asks the server for another username, or just changes it if not connected
+	pub fn rename(&mut self, username : String){
+		if username.is_empty(){
+			println!("No username provided");
+			return;
+		}
+		match &mut self.server{
+		    Some(server) => {
+				if let Err(e) = server.rename(&username){
+					println!("Couldn't send: {}", e);
 				}
 			}
+		    None => {
+				println!("Your name was changed from {} to {}", self.username, username);
+				self.username = username;
+			}
+		}
+	}
+
+	pub fn room(&mut self, room : String, action : RoomAction){
+		let server = match &mut self.server {
+		    Some(v) => {v}
+		    None => {
+				println!("Not connected to a server");
+				return;
+			}
+		};
+		if room.is_empty(){
+			println!("No room name provided");
+			return;
+		}
+		if let Err(e) = server.room(&room, action){
+			println!("Couldn't send: {}", e);
 		}
-		println!("Connected");
-		self.server = Some(peer);
 	}
 
 	pub fn disconnect(&mut self){
-		match &mut self.server {
+		match self.server.take() {
 			Some(server) => {
-				server.send(&ClMessage::IQuit(server.token));
-				self.server = None;
+				server.quit();
+				self.rooms.clear();
 				println!("Disconnected");
 			}
+			None if self.reconnect.is_some() => {
+				self.reconnect = None;
+				println!("Stopped reconnecting");
+			}
 			None => {
 				println!("Not connected to a server");
 			}
@@ -210,55 +545,302 @@ impl Client{
 	}
 
 	pub fn say(&mut self, arg: String){
-		let mut server = match &mut self.server {
+		let server = match &mut self.server {
 		    Some(v) => {v}
 		    None => {
 				println!("Can't /say - not connected to a server");
 				return;
 			}
 		};
-		let mesg = ClMesg{
-				username : server.username.clone(),
-				token : server.token,
-				mesg : arg
+		let room = match self.rooms.last(){
+		    Some(v) => {v.clone()}
+		    None => {
+				println!("Can't /say - you are not in any room");
+				return;
+			}
+		};
+		if let Err(e) = server.say(&room, &arg){
+			println!("Couldn't send: {}", e);
+		}
+	}
+
+	pub fn start_compose(&mut self, arg: String){
+		if self.server.is_none(){
+			println!("Can't /compose - not connected to a server");
+			return;
+		}
+		if arg.is_empty() && self.rooms.is_empty(){
+			println!("Can't /compose - you are not in any room");
+			return;
+		}
+		let to = if arg.is_empty() {None} else {Some(arg)};
+		println!("Write your message, end it with a line of a single {}, or cancel it with /cancel", END_OF_MESSAGE);
+		self.compose = Some(Compose::new(to));
+	}
+
+	//takes a line of the message being composed, and sends the message once it is finished
+	pub fn compose_line(&mut self, input : String){
+		let composed = match &mut self.compose{
+		    Some(v) => {v.add(&input)}
+		    None => {return;}
+		};
+		let mesg = match composed{
+		    Composed::Writing => {return;}
+		    Composed::Cancelled => {
+				self.compose = None;
+				println!("The message was dropped");
+				return;
+			}
+		    Composed::Empty => {
+				self.compose = None;
+				println!("The message is empty, nothing was sent");
+				return;
+			}
+		    Composed::Finished(v) => {v}
+		};
+		let compose = match self.compose.take(){
+		    Some(v) => {v}
+		    None => {return;}
+		};
+		let server = match &mut self.server {
+		    Some(v) => {v}
+		    None => {
+				println!("Can't send - not connected to a server");
+				return;
+			}
+		};
+		let sent = match (&compose.to, self.rooms.last()){
+		    (Some(to), _) => {server.direct(to, &mesg)}
+		    (None, Some(room)) => {server.say(room, &mesg)}
+		    (None, None) => {
+				println!("Can't send - you are not in any room");
+				return;
+			}
+		};
+		if let Err(e) = sent{
+			println!("Couldn't send: {}", e);
+		}
+	}
+
+	pub fn request_history(&mut self, arg: String){
+		let server = match &mut self.server {
+		    Some(v) => {v}
+		    None => {
+				println!("Can't /history - not connected to a server");
+				return;
+			}
+		};
+		let room = match self.rooms.last(){
+		    Some(v) => {v.clone()}
+		    None => {
+				println!("Can't /history - you are not in any room");
+				return;
+			}
+		};
+		let count = if arg.is_empty(){
+			DEFAULT_HISTORY
+		}else{
+			match arg.parse::<usize>(){
+			    Ok(v) => {v}
+			    Err(_) => {
+					println!("Usage: /history [count]");
+					return;
+				}
+			}
+		};
+		if let Err(e) = server.history(&room, count){
+			println!("Couldn't send: {}", e);
+		}
+	}
+
+	pub fn direct(&mut self, arg: String){
+		let server = match &mut self.server {
+		    Some(v) => {v}
+		    None => {
+				println!("Can't /msg - not connected to a server");
+				return;
+			}
+		};
+		let mut arg = arg;
+		let mesg = match arg.find(' '){
+		    Some(pos) => {arg.split_off(pos).trim().to_string()}
+		    None => {
+				println!("Usage: /msg <user> <message>");
+				return;
+			}
 		};
-		server.send(&ClMessage::Mesg(mesg));
+		if let Err(e) = server.direct(&arg, &mesg){
+			println!("Couldn't send: {}", e);
+		}
 	}
 
 	pub fn print_help(&self){
 		println!("--------------------
 A list of availible commands:
 /help - displays help on commands
-/join <adress> <username> - joins a server at <adress> with <username>
+/join <adress> - joins a server at <adress> as a guest
+/login <adress> <password> - joins a server at <adress> with your registered username
+/register <adress> <password> - registers your username at <adress> with <password> and joins
+/reconnect - joins the last server once more, or tries to restore a lost connection right away
 /disconnect - disconnects from a server
-/say <message> - sends a message to the chat
+/say <message> - sends a message to the current room
+/msg <user> <message> - sends a private message to <user>
+/compose [user] - writes a message of many lines, to the current room or to <user>, ended by a line of a single .
+/history [count] - prints last messages of the current room
+/create <room> - creates a room and enters it
+/enter <room> - enters a room, or makes it current if you are already in it
+/leave [room] - leaves a room, the current one by default
 /exit - exits the program
-/info - prints information about server
+/info - prints information about server, its users and rooms
 /name - prints your username
 /chname <name> - changes your username to <name>
---------------------");
+Moderators and admins also have:");
+		for line in admin::HELP.lines().filter(|line| !line.starts_with("say") && !line.starts_with("stop") && !line.starts_with("register")){
+			println!("/{}", line);
+		}
+		println!("--------------------");
 	}
 
-	pub fn get_input(tx : mpsc::Sender<String>){
+	//reads commands from stdin, the client is woken up by waker to process them
+	pub fn get_input(tx : mpsc::Sender<String>, waker : mio::Waker){
 		loop{
 			let mut command = String::new();
-			std::io::stdin().read_line(&mut command);
-			match tx.send(command){
-			    Ok(_) => {continue;}
-			    Err(_) => {return;}
+			match std::io::stdin().read_line(&mut command){
+			    Ok(0) => {return;} //stdin is closed, there will be no more commands
+			    Ok(_) => {}
+			    Err(_) => {continue;}
+			}
+			if tx.send(command).is_err() || waker.wake().is_err(){
+				return;
+			}
+		}
+	}
+}
+
+//prints a message after the prefix, with its other lines lined up under the first
+fn print_mesg(prefix : &str, mesg : &str){
+	for line in lined_up(prefix, mesg){
+		println!("{}", line);
+	}
+}
+
+//the lines of a message as they are printed, the first one after the prefix and the others under it
+fn lined_up(prefix : &str, mesg : &str) -> Vec<String>{
+	let indent = " ".repeat(prefix.chars().count());
+	let mut lines = Vec::new();
+	for (n, line) in mesg.split('\n').enumerate(){
+		let line = line.trim_end_matches('\r');
+		if n == 0{
+			lines.push(format!("{}{}", prefix, line));
+		}else{
+			lines.push(format!("{}{}", indent, line));
+		}
+	}
+	return lines;
+}
+
+fn print_usage(){
+	println!("Usage: client [--ca <file> | --pin <fingerprint>] [--json]
+--ca <file> - joins servers over TLS, trusting certificates signed by CAs from the PEM file
+--pin <fingerprint> - joins servers over TLS, trusting only the certificate with the sha-256 fingerprint
+--json - talks to servers in JSON instead of bincode, which is handy to watch the traffic");
+}
+
+//loads TLS configuration if it was asked for in the command-line arguments, and tells the encoding to speak
+fn parse_args() -> Result<(Option<Arc<rustls::ClientConfig>>, Encoding), String>{
+	let mut config = None;
+	let mut encoding = Encoding::Bincode;
+	let mut args = std::env::args().skip(1);
+	while let Some(arg) = args.next(){
+		let value = match arg.as_str(){
+		    "--help" => {
+				print_usage();
+				std::process::exit(0);
+			}
+		    "--json" => {
+				encoding = Encoding::Json;
+				continue;
+			}
+		    "--ca" | "--pin" => {
+				match args.next(){
+				    Some(v) => {v}
+				    None => {return Err(format!("{} needs a value", arg));}
+				}
 			}
+		    _ => {return Err(format!("Unknown argument: {}", arg));}
+		};
+		if config.is_some(){
+			return Err("Only one of --ca and --pin may be given".to_string());
+		}
+		if arg == "--ca"{
+			config = Some(tls::client_config_ca(&value)?);
+		}else{
+			config = Some(tls::client_config_pinned(&value)?);
 		}
 	}
+	return Ok((config, encoding));
 }
 
 fn main(){
+	let (tls, encoding) = match parse_args(){
+	    Ok(v) => {v}
+	    Err(e) => {
+			eprintln!("{}", e);
+			print_usage();
+			std::process::exit(1);
+		}
+	};
 	let mut username = String::new();
 	println!("Enter your name: ");
-	std::io::stdin().read_line(&mut username);
+	std::io::stdin().read_line(&mut username).expect("Failed to read the username");
 	username = username.trim().to_string();
 
+	let poll = mio::Poll::new().expect("Failed to create poll");
+	let waker = mio::Waker::new(poll.registry(), INPUT).expect("Failed to create waker");
 	let (tx, rx) = mpsc::channel();
-	let mut client = Client::new(rx, username);
-	let join_handle = std::thread::spawn(move || {Client::get_input(tx);});
+	let mut client = Client::new(poll, rx, username, tls, encoding);
+	std::thread::spawn(move || {Client::get_input(tx, waker);});
 	client.run();
 }
+
+#[cfg(test)]
+mod tests{
+	use super::*;
+
+	fn compose(lines : &[&str]) -> Vec<Composed>{
+		let mut compose = Compose::new(None);
+		return lines.iter().map(|line| compose.add(line)).collect();
+	}
+
+	#[test]
+	fn message_ends_at_the_marker(){
+		let composed = compose(&["roses are red\n", "  violets are blue\r\n", "", "the end", ".\n"]);
+		assert_eq!(composed[..4], [Composed::Writing, Composed::Writing, Composed::Writing, Composed::Writing]);
+		assert_eq!(composed[4], Composed::Finished("roses are red\n  violets are blue\n\nthe end".to_string()));
+	}
+
+	#[test]
+	fn marker_is_only_a_line_of_its_own(){
+		let composed = compose(&[". ", " .", "..", "."]);
+		assert_eq!(composed[3], Composed::Finished(". \n .\n..".to_string()));
+	}
+
+	#[test]
+	fn message_may_be_cancelled(){
+		assert_eq!(compose(&["first", "/cancel"])[1], Composed::Cancelled);
+		assert_eq!(compose(&["/cancel\n"])[0], Composed::Cancelled);
+	}
+
+	#[test]
+	fn empty_message_is_not_sent(){
+		assert_eq!(compose(&["."])[0], Composed::Empty);
+		assert_eq!(compose(&["", "   ", "."])[2], Composed::Empty);
+	}
+
+	#[test]
+	fn lines_are_lined_up_under_the_first(){
+		assert_eq!(lined_up("[lobby] ", "one\r\ntwo"), ["[lobby] one", "        two"]);
+		assert_eq!(lined_up("", "one"), ["one"]);
+		assert_eq!(lined_up("<-> from bób: ", "a\n\nb"), ["<-> from bób: a", "              ", "              b"]);
+	}
+}
@@ -0,0 +1,245 @@
+use std::fs;
+use std::net::ToSocketAddrs;
+use std::time::Duration;
+use chat::net::{MAX_LINE, MAX_MESSAGE_LEN, MAX_SILENCE};
+use crate::log::Level;
+
+//settings as they are written in the configuration file or given on the command line,
+//anything left out takes its default
+//a file looks like:
+//listen = "0.0.0.0:8000"
+//ws_listen = "0.0.0.0:8001"
+//max_silence = 10 #seconds
+//admins = ["alice"]
+#[derive(serde::Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Settings{
+	pub listen : Option<String>,
+	pub ws_listen : Option<String>,
+	pub cert : Option<String>,
+	pub key : Option<String>,
+	pub max_silence : Option<u64>,
+	pub resume_grace : Option<u64>,
+	pub max_users : Option<usize>,
+	pub max_message_len : Option<usize>,
+	pub motd : Option<String>,
+	pub history_dir : Option<String>,
+	pub accounts_file : Option<String>,
+	pub log_file : Option<String>,
+	pub admins : Option<Vec<String>>,
+	pub daemon : Option<bool>,
+	pub admin_socket : Option<String>,
+	pub owner : Option<String>,
+	pub roles_file : Option<String>,
+	pub audit_log : Option<String>,
+	pub bans_file : Option<String>,
+	pub slow_mode : Option<u64>,
+	pub rate_limit : Option<u32>,
+	pub rate_burst : Option<u32>,
+	pub ip_rate_limit : Option<u32>,
+	pub ip_rate_burst : Option<u32>,
+	pub flood_kick : Option<u32>,
+	pub max_connections : Option<usize>,
+	pub max_connections_per_ip : Option<usize>,
+	pub handshake_timeout : Option<u64>,
+	pub log_level : Option<String>,
+	pub log_max_size : Option<u64>,
+	pub log_keep : Option<usize>,
+	pub log_bodies : Option<bool>,
+	pub metrics_listen : Option<String>
+}
+
+impl Settings{
+	pub fn load(path : &str) -> Result<Self, String>{
+		let content = match fs::read_to_string(path){
+		    Ok(v) => {v}
+		    Err(e) => {return Err(format!("Couldn't read {}: {}", path, e));}
+		};
+		match toml::from_str(&content){
+		    Ok(v) => {return Ok(v);}
+		    Err(e) => {return Err(format!("Bad configuration in {}: {}", path, e));}
+		}
+	}
+
+	//the settings, with the ones not given taken from other
+	pub fn or(self, other : Settings) -> Settings{
+		return Settings{
+			listen : self.listen.or(other.listen),
+			ws_listen : self.ws_listen.or(other.ws_listen),
+			cert : self.cert.or(other.cert),
+			key : self.key.or(other.key),
+			max_silence : self.max_silence.or(other.max_silence),
+			resume_grace : self.resume_grace.or(other.resume_grace),
+			max_users : self.max_users.or(other.max_users),
+			max_message_len : self.max_message_len.or(other.max_message_len),
+			motd : self.motd.or(other.motd),
+			history_dir : self.history_dir.or(other.history_dir),
+			accounts_file : self.accounts_file.or(other.accounts_file),
+			log_file : self.log_file.or(other.log_file),
+			admins : self.admins.or(other.admins),
+			daemon : self.daemon.or(other.daemon),
+			admin_socket : self.admin_socket.or(other.admin_socket),
+			owner : self.owner.or(other.owner),
+			roles_file : self.roles_file.or(other.roles_file),
+			audit_log : self.audit_log.or(other.audit_log),
+			bans_file : self.bans_file.or(other.bans_file),
+			slow_mode : self.slow_mode.or(other.slow_mode),
+			rate_limit : self.rate_limit.or(other.rate_limit),
+			rate_burst : self.rate_burst.or(other.rate_burst),
+			ip_rate_limit : self.ip_rate_limit.or(other.ip_rate_limit),
+			ip_rate_burst : self.ip_rate_burst.or(other.ip_rate_burst),
+			flood_kick : self.flood_kick.or(other.flood_kick),
+			max_connections : self.max_connections.or(other.max_connections),
+			max_connections_per_ip : self.max_connections_per_ip.or(other.max_connections_per_ip),
+			handshake_timeout : self.handshake_timeout.or(other.handshake_timeout),
+			log_level : self.log_level.or(other.log_level),
+			log_max_size : self.log_max_size.or(other.log_max_size),
+			log_keep : self.log_keep.or(other.log_keep),
+			log_bodies : self.log_bodies.or(other.log_bodies),
+			metrics_listen : self.metrics_listen.or(other.metrics_listen)
+		};
+	}
+
+	//checks the settings and fills in the defaults
+	pub fn validate(self) -> Result<Config, String>{
+		if let Some(addr) = &self.listen{
+			check_addr("listen", addr)?;
+		}
+		let daemon = self.daemon.unwrap_or(false);
+		if daemon && self.listen.is_none(){
+			return Err("listen is needed in daemon mode, since there is no terminal to ask for it".to_string());
+		}
+		if let Some(addr) = &self.ws_listen{
+			check_addr("ws_listen", addr)?;
+		}
+		if let Some(addr) = &self.metrics_listen{
+			check_addr("metrics_listen", addr)?;
+		}
+		if self.cert.is_some() != self.key.is_some(){
+			return Err("Both cert and key are needed for TLS".to_string());
+		}
+		//clients ping at half of MAX_SILENCE, a server which waits less would kick them
+		let max_silence = self.max_silence.unwrap_or(MAX_SILENCE.as_secs());
+		if max_silence <= (MAX_SILENCE / 2).as_secs(){
+			return Err(format!("max_silence must be more than {} seconds, which is how often clients ping",
+				(MAX_SILENCE / 2).as_secs()));
+		}
+		let max_users = self.max_users.unwrap_or(1000);
+		if max_users == 0{
+			return Err("max_users must be at least 1".to_string());
+		}
+		let max_message_len = self.max_message_len.unwrap_or(4096);
+		//so that a message of 4-byte characters still fits into a packet
+		if max_message_len == 0 || max_message_len > MAX_MESSAGE_LEN{
+			return Err(format!("max_message_len must be between 1 and {}", MAX_MESSAGE_LEN));
+		}
+		if let Some(motd) = &self.motd{
+			if motd.len() > MAX_LINE / 2{
+				return Err(format!("motd is longer than {} bytes", MAX_LINE / 2));
+			}
+		}
+		let max_connections = self.max_connections.unwrap_or(2000);
+		let max_connections_per_ip = self.max_connections_per_ip.unwrap_or(50);
+		if max_connections == 0 || max_connections_per_ip == 0{
+			return Err("max_connections and max_connections_per_ip must be at least 1".to_string());
+		}
+		let handshake_timeout = self.handshake_timeout.unwrap_or(5);
+		if handshake_timeout == 0{
+			return Err("handshake_timeout must be at least 1 second".to_string());
+		}
+		let rate_burst = self.rate_burst.unwrap_or(20);
+		let ip_rate_burst = self.ip_rate_burst.unwrap_or(100);
+		if rate_burst == 0 || ip_rate_burst == 0{
+			return Err("rate_burst and ip_rate_burst must be at least 1".to_string());
+		}
+		let log_level = match &self.log_level{
+		    Some(level) => {Level::parse(level)?}
+		    None => {Level::Info}
+		};
+		let admins = self.admins.unwrap_or_default();
+		for admin in admins.iter().chain(self.owner.iter()){
+			if admin.is_empty() || !admin.chars().all(char::is_alphanumeric){
+				return Err(format!("{:?} isn't a valid username for an admin or the owner", admin));
+			}
+		}
+		return Ok(Config{
+			listen : self.listen,
+			ws_listen : self.ws_listen,
+			cert : self.cert,
+			key : self.key,
+			max_silence : Duration::from_secs(max_silence),
+			resume_grace : Duration::from_secs(self.resume_grace.unwrap_or(60)),
+			max_users,
+			max_message_len,
+			motd : self.motd.filter(|motd| !motd.is_empty()),
+			history_dir : self.history_dir.unwrap_or_else(|| "history".to_string()),
+			accounts_file : self.accounts_file.unwrap_or_else(|| "users.db".to_string()),
+			log_file : self.log_file,
+			admins,
+			daemon,
+			admin_socket : self.admin_socket.filter(|path| !path.is_empty()),
+			owner : self.owner,
+			roles_file : self.roles_file.unwrap_or_else(|| "roles.db".to_string()),
+			audit_log : self.audit_log.unwrap_or_else(|| "audit.log".to_string()),
+			bans_file : self.bans_file.unwrap_or_else(|| "bans.db".to_string()),
+			slow_mode : Duration::from_secs(self.slow_mode.unwrap_or(0)),
+			rate_limit : self.rate_limit.unwrap_or(10),
+			rate_burst,
+			ip_rate_limit : self.ip_rate_limit.unwrap_or(50),
+			ip_rate_burst,
+			flood_kick : self.flood_kick.unwrap_or(20),
+			max_connections,
+			max_connections_per_ip,
+			handshake_timeout : Duration::from_secs(handshake_timeout),
+			log_level,
+			log_max_size : self.log_max_size.unwrap_or(10 * 1024 * 1024),
+			log_keep : self.log_keep.unwrap_or(5),
+			log_bodies : self.log_bodies.unwrap_or(true),
+			metrics_listen : self.metrics_listen
+		});
+	}
+}
+
+fn check_addr(key : &str, addr : &str) -> Result<(), String>{
+	match addr.to_socket_addrs(){
+	    Ok(_) => {return Ok(());}
+	    Err(e) => {return Err(format!("{} isn't a valid address ({}): {}", key, addr, e));}
+	}
+}
+
+//the configuration of a server, which has been checked
+pub struct Config{
+	pub listen : Option<String>, //asked for on stdin if it isn't given
+	pub ws_listen : Option<String>, //WebSocket clients are served if it is given
+	pub cert : Option<String>, //PEM files of the certificate and its key, for TLS
+	pub key : Option<String>,
+	pub max_silence : Duration, //how long a client may send nothing before it is kicked
+	pub resume_grace : Duration, //how long a lost session is kept
+	pub max_users : usize,
+	pub max_message_len : usize, //in characters
+	pub motd : Option<String>, //sent to every user who joins
+	pub history_dir : String,
+	pub accounts_file : String,
+	pub log_file : Option<String>, //errors are written to stderr if it isn't given
+	pub admins : Vec<String>, //users who are admins whatever the roles file says
+	pub daemon : bool, //the server doesn't read commands from stdin, they come through the admin socket
+	pub admin_socket : Option<String>, //the path of the Unix socket chatctl talks to
+	pub owner : Option<String>, //the user who is the owner whatever the roles file says
+	pub roles_file : String, //where the roles given by commands are kept
+	pub audit_log : String, //where privileged commands are recorded
+	pub bans_file : String, //where the bans of users and addresses are kept
+	pub slow_mode : Duration, //how often each user may post in a room which isn't set otherwise, zero for any time
+	pub rate_limit : u32, //messages a second a connection may send, 0 for no limit
+	pub rate_burst : u32, //messages a connection may send at once
+	pub ip_rate_limit : u32, //the same, for all the connections from an address together
+	pub ip_rate_burst : u32,
+	pub flood_kick : u32, //messages over the limit in a row before the connection is closed, 0 never to close it
+	pub max_connections : usize, //of clients, whether they have joined or not
+	pub max_connections_per_ip : usize,
+	pub handshake_timeout : Duration, //how long a client may take to join after connecting
+	pub log_level : Level, //of the events written to the log file
+	pub log_max_size : u64, //in bytes, the log file is rotated past it, never if it is 0
+	pub log_keep : usize, //how many rotated log files are kept
+	pub log_bodies : bool, //whether what users post is written to the log, or only that they have posted
+	pub metrics_listen : Option<String> //metrics are served over HTTP on it if it is given
+}
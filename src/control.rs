@@ -0,0 +1,115 @@
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use mio::net::{UnixListener, UnixStream};
+use chat::admin::{Command, Reply};
+use chat::net::Error;
+use chat::JsonBuilder;
+
+//the admin socket, on which chatctl sends the commands otherwise typed into the server's terminal
+//only the user running the server may connect to it, the socket file is removed when it is dropped
+pub struct Control{
+	pub listener : UnixListener,
+	path : PathBuf
+}
+
+impl Control{
+	pub fn bind(path : &str) -> Result<Self, String>{
+		//a socket left by a server which hasn't stopped cleanly, but not one a running server listens on
+		if std::os::unix::net::UnixStream::connect(path).is_ok(){
+			return Err(format!("Another server is listening on {}", path));
+		}
+		//only a socket is replaced, a wrong setting mustn't cost a file
+		match fs::symlink_metadata(path){
+		    Ok(meta) if meta.file_type().is_socket() => {
+				if let Err(e) = fs::remove_file(path){
+					return Err(format!("Couldn't remove the old socket {}: {}", path, e));
+				}
+			}
+		    Ok(_) => {return Err(format!("{} exists and isn't a socket", path));}
+		    Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+		    Err(e) => {return Err(format!("Couldn't look at {}: {}", path, e));}
+		}
+		//the socket is made in a directory only the server's user may enter, and moved into place
+		//once it is restricted, so that no one can connect to it in between
+		let target = PathBuf::from(path);
+		let file_name = match target.file_name(){
+		    Some(v) => {v.to_string_lossy().to_string()}
+		    None => {return Err(format!("{} isn't a path to a file", path));}
+		};
+		let private = target.with_file_name(format!(".{}.{}", file_name, std::process::id()));
+		if let Err(e) = fs::DirBuilder::new().mode(0o700).create(&private){
+			return Err(format!("Couldn't create {}: {}", private.display(), e));
+		}
+		let result = bind_restricted(&private.join(&file_name), &target);
+		let _ = fs::remove_dir(&private);
+		let listener = result.map_err(|e| format!("Couldn't bind to {}: {}", path, e))?;
+		return Ok(Control{listener, path : target});
+	}
+}
+
+//binds at the temporary path, and moves the socket to the path once only its owner may use it
+fn bind_restricted(temporary : &Path, path : &Path) -> io::Result<UnixListener>{
+	let listener = UnixListener::bind(temporary)?;
+	let moved = fs::set_permissions(temporary, fs::Permissions::from_mode(0o600)).and_then(|_| fs::rename(temporary, path));
+	if let Err(e) = moved{
+		let _ = fs::remove_file(temporary);
+		return Err(e);
+	}
+	return Ok(listener);
+}
+
+impl Drop for Control{
+	fn drop(&mut self){
+		let _ = fs::remove_file(&self.path);
+	}
+}
+
+//a connection of chatctl, which sends commands as lines of JSON and gets a reply to each
+pub struct AdminConn{
+	pub stream : UnixStream,
+	commands : JsonBuilder,
+	out_buff : Vec<u8>
+}
+
+impl AdminConn{
+	pub fn new(stream : UnixStream) -> Self{
+		return AdminConn{stream, commands : JsonBuilder::new(), out_buff : Vec::new()};
+	}
+
+	//reads the commands which have arrived, an error means the connection is to be closed
+	pub fn read(&mut self) -> Result<Vec<Command>, Error>{
+		let mut commands = Vec::new();
+		let mut buff = [0u8; 4096];
+		loop{
+			let n = match self.stream.read(&mut buff){
+			    Ok(0) => {return Err(Error::Closed);}
+			    Ok(n) => n,
+			    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {return Ok(commands);}
+			    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {continue;}
+			    Err(_) => {return Err(Error::Closed);}
+			};
+			commands.extend(self.commands.eat::<Command>(&buff[..n])?);
+		}
+	}
+
+	pub fn reply(&mut self, reply : &Reply){
+		let mut line = serde_json::to_vec(reply).expect("Failed to serialize");
+		line.push(b'\n');
+		self.out_buff.extend_from_slice(&line);
+		self.flush();
+	}
+
+	pub fn flush(&mut self){
+		while !self.out_buff.is_empty(){
+			match self.stream.write(&self.out_buff){
+			    Ok(0) => {break;}
+			    Ok(n) => {self.out_buff.drain(..n);}
+			    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {continue;}
+			    Err(_) => {break;}
+			}
+		}
+	}
+}
@@ -0,0 +1,57 @@
+use std::sync::{mpsc, Arc};
+use chat::net::Token;
+use crate::accounts;
+
+//a password to be hashed or checked, for the peer of the token
+pub enum Job{
+	Check{token : Token, username : String, password : String, hash : String},
+	Hash{token : Token, username : String, password : String}
+}
+
+pub enum Done{
+	Checked{token : Token, username : String, matches : bool},
+	Hashed{token : Token, username : String, hash : Result<String, String>}
+}
+
+//hashes passwords on a thread of its own, Argon2 takes long enough to hold up every other peer if it ran in the loop
+//the thread wakes the server's poll after each job
+pub struct Hasher{
+	jobs : mpsc::Sender<Job>,
+	done : mpsc::Receiver<Done>
+}
+
+impl Hasher{
+	pub fn start(waker : Arc<mio::Waker>) -> Self{
+		let (jobs, job_rx) = mpsc::channel();
+		let (done_tx, done) = mpsc::channel();
+		std::thread::spawn(move || {work(job_rx, done_tx, waker)});
+		return Hasher{jobs, done};
+	}
+
+	pub fn send(&self, job : Job){
+		if self.jobs.send(job).is_err(){
+			log!("The password hasher has stopped");
+		}
+	}
+
+	//the jobs which have been done since the last call
+	pub fn finished(&self) -> Vec<Done>{
+		return self.done.try_iter().collect();
+	}
+}
+
+fn work(jobs : mpsc::Receiver<Job>, done : mpsc::Sender<Done>, waker : Arc<mio::Waker>){
+	for job in jobs{
+		let result = match job{
+		    Job::Check{token, username, password, hash} => {
+				Done::Checked{token, username, matches : accounts::verify(&hash, &password)}
+			}
+		    Job::Hash{token, username, password} => {
+				Done::Hashed{token, username, hash : accounts::hash(&password)}
+			}
+		};
+		if done.send(result).is_err() || waker.wake().is_err(){
+			return;
+		}
+	}
+}
@@ -0,0 +1,113 @@
+use std::fs;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::path::PathBuf;
+
+//how much of a history file is read at once, going back from its end
+const BLOCK : u64 = 64 * 1024;
+
+//append-only on-disk log of messages, one file per room
+//every message takes one line, line breaks and backslashes inside of it are escaped
+pub struct History{
+	dir : PathBuf
+}
+
+impl History{
+	pub fn new(dir : &str) -> Self{
+		if let Err(e) = fs::create_dir_all(dir){
+			log!("Couldn't create history directory {}: {}", dir, e);
+		}
+		return History{dir : PathBuf::from(dir)};
+	}
+
+	//room names are alphanumeric, so they are safe to use as file names
+	fn path(&self, room : &str) -> PathBuf{
+		return self.dir.join(format!("{}.log", room));
+	}
+
+	pub fn append(&self, room : &str, mesg : &str){
+		let file = fs::OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(self.path(room));
+		let result = file.and_then(|mut file| writeln!(file, "{}", escape(mesg)));
+		if let Err(e) = result{
+			log!("Couldn't write history of room {}: {}", room, e);
+		}
+	}
+
+	//returns up to count last messages of the room, oldest first
+	//the file is read from its end, as far back as the messages go
+	pub fn last(&self, room : &str, count : usize) -> Vec<String>{
+		let mut file = match fs::File::open(self.path(room)){
+		    Ok(v) => {v}
+		    Err(_) => {return Vec::new();} //nothing was ever said there
+		};
+		let tail = match read_tail(&mut file, count){
+		    Ok(v) => {v}
+		    Err(e) => {
+				log!("Couldn't read history of room {}: {}", room, e);
+				return Vec::new();
+			}
+		};
+		let content = String::from_utf8_lossy(&tail);
+		let lines : Vec<&str> = content.lines().collect();
+		let skip = lines.len().saturating_sub(count);
+		return lines[skip..].iter().map(|line| unescape(line)).collect();
+	}
+}
+
+//the end of the file holding the last count lines, whole, every line ends with a line break
+fn read_tail(file : &mut fs::File, count : usize) -> std::io::Result<Vec<u8>>{
+	let mut start = file.seek(SeekFrom::End(0))?;
+	let mut tail : Vec<u8> = Vec::new();
+	let mut breaks = 0;
+	//one more line break than lines, for the first line to be whole
+	while start > 0 && breaks <= count{
+		let block_start = start.saturating_sub(BLOCK);
+		let mut block = vec![0; (start - block_start) as usize];
+		file.seek(SeekFrom::Start(block_start))?;
+		file.read_exact(&mut block)?;
+		breaks += block.iter().filter(|b| **b == b'\n').count();
+		block.extend_from_slice(&tail);
+		tail = block;
+		start = block_start;
+	}
+	if start > 0{
+		//the first line is only a part of one
+		let first_break = tail.iter().position(|b| *b == b'\n').map_or(tail.len(), |pos| pos + 1);
+		tail.drain(..first_break);
+	}
+	return Ok(tail);
+}
+
+fn escape(mesg : &str) -> String{
+	let mut escaped = String::with_capacity(mesg.len());
+	for c in mesg.chars(){
+		match c{
+		    '\\' => {escaped.push_str("\\\\")}
+		    '\n' => {escaped.push_str("\\n")}
+		    '\r' => {escaped.push_str("\\r")}
+		    _ => {escaped.push(c)}
+		}
+	}
+	return escaped;
+}
+
+fn unescape(line : &str) -> String{
+	let mut mesg = String::with_capacity(line.len());
+	let mut chars = line.chars();
+	while let Some(c) = chars.next(){
+		if c != '\\'{
+			mesg.push(c);
+			continue;
+		}
+		match chars.next(){
+		    Some('n') => {mesg.push('\n')}
+		    Some('r') => {mesg.push('\r')}
+		    Some(c) => {mesg.push(c)}
+		    None => {}
+		}
+	}
+	return mesg;
+}
@@ -0,0 +1,11 @@
+#![allow(clippy::needless_return, non_camel_case_types)]
+//the chat protocol, and a client for it to embed into bots and tools
+
+pub mod net;
+pub mod tls;
+pub mod ws;
+pub mod admin;
+mod chat_client;
+
+pub use net::{ClMessage, SeMessage, Encoding, MesgBuilder, JsonBuilder, Peer};
+pub use chat_client::{ChatClient, ConnectError, Events};
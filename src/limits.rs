@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Instant;
+use chat::net::Token;
+
+//a token bucket: it holds up to burst tokens and gains rate of them a second, each message takes one
+struct Bucket{
+	tokens : f64,
+	last : Instant //when tokens were last added
+}
+
+impl Bucket{
+	fn new(burst : u32) -> Self{
+		return Bucket{tokens : burst as f64, last : Instant::now()};
+	}
+
+	fn refill(&mut self, rate : f64, burst : u32){
+		let now = Instant::now();
+		let gained = now.duration_since(self.last).as_secs_f64() * rate;
+		self.tokens = (self.tokens + gained).min(burst as f64);
+		self.last = now;
+	}
+
+	fn is_full(&self, burst : u32) -> bool{
+		return self.tokens >= burst as f64;
+	}
+}
+
+//what is to be done with a message
+#[derive(Debug, PartialEq)]
+pub enum Verdict{
+	Pass,
+	Warn, //the first message over the limit, dropped, the peer is told to slow down
+	Drop, //further ones
+	Disconnect //the peer has gone on flooding for too long
+}
+
+//how fast each connection, and all the connections from one address together, may send
+//a limit of 0 lets any number of messages through
+pub struct Limits{
+	rate : u32,
+	burst : u32,
+	ip_rate : u32,
+	ip_burst : u32,
+	max_dropped : u32, //messages dropped in a row before the peer is disconnected
+	peers : HashMap<Token, (Bucket, u32)>, //with how many messages were dropped since the bucket was last full
+	addresses : HashMap<IpAddr, Bucket>
+}
+
+impl Limits{
+	pub fn new(rate : u32, burst : u32, ip_rate : u32, ip_burst : u32, max_dropped : u32) -> Self{
+		return Limits{rate, burst, ip_rate, ip_burst, max_dropped, peers : HashMap::new(), addresses : HashMap::new()};
+	}
+
+	//takes a token for a message of the peer, and for its address if the address is known
+	pub fn check(&mut self, token : Token, addr : Option<IpAddr>) -> Verdict{
+		let (rate, burst, ip_rate, ip_burst) = (self.rate, self.burst, self.ip_rate, self.ip_burst);
+		let (peer, dropped) = self.peers.entry(token).or_insert_with(|| (Bucket::new(burst), 0));
+		peer.refill(rate as f64, burst);
+		let mut address = match addr{
+		    Some(addr) if ip_rate > 0 => {
+				let bucket = self.addresses.entry(addr).or_insert_with(|| Bucket::new(ip_burst));
+				bucket.refill(ip_rate as f64, ip_burst);
+				Some(bucket)
+			}
+		    _ => {None}
+		};
+		//the peer has calmed down once it has let its buckets fill up
+		if peer.is_full(burst) && address.as_ref().is_none_or(|bucket| bucket.is_full(ip_burst)){
+			*dropped = 0;
+		}
+		let peer_allows = rate == 0 || peer.tokens >= 1.0;
+		let address_allows = address.as_ref().is_none_or(|bucket| bucket.tokens >= 1.0);
+		if peer_allows && address_allows{
+			if rate > 0{
+				peer.tokens -= 1.0;
+			}
+			if let Some(bucket) = &mut address{
+				bucket.tokens -= 1.0;
+			}
+			return Verdict::Pass;
+		}
+		*dropped += 1;
+		if self.max_dropped > 0 && *dropped >= self.max_dropped{
+			return Verdict::Disconnect;
+		}
+		if *dropped == 1{
+			return Verdict::Warn;
+		}
+		return Verdict::Drop;
+	}
+
+	pub fn forget(&mut self, token : Token){
+		self.peers.remove(&token);
+	}
+
+	//lets go of the addresses which have had time to fill their buckets, they would start with full ones anyway
+	pub fn forget_idle(&mut self){
+		let (ip_rate, ip_burst) = (self.ip_rate, self.ip_burst);
+		self.addresses.retain(|_, bucket| {
+			bucket.refill(ip_rate as f64, ip_burst);
+			return !bucket.is_full(ip_burst);
+		});
+	}
+}
+
+//failed logins to a user from an address, or from an address to any users, which may be had in a row,
+//and how fast they may be tried again after them
+const LOGIN_BURST : u32 = 5;
+const LOGIN_RATE : f64 = 1.0 / 30.0;
+const IP_LOGIN_BURST : u32 = 20;
+const IP_LOGIN_RATE : f64 = 1.0 / 10.0;
+//accounts an address may register in a row, and how fast it may register more after them
+const SIGN_UP_BURST : u32 = 5;
+const SIGN_UP_RATE : f64 = 1.0 / 60.0;
+
+//how often passwords may be guessed, for each user from each address, and from each address whichever users it tries,
+//and how often an address may register accounts
+//a user is only held back on the addresses which have guessed, so that anyone can't lock anyone else out
+//only failed logins take tokens, so users who know their passwords aren't held back
+pub struct LoginLimits{
+	users : HashMap<(Option<IpAddr>, String), Bucket>,
+	addresses : HashMap<IpAddr, Bucket>,
+	sign_ups : HashMap<IpAddr, Bucket>
+}
+
+impl LoginLimits{
+	pub fn new() -> Self{
+		return LoginLimits{users : HashMap::new(), addresses : HashMap::new(), sign_ups : HashMap::new()};
+	}
+
+	//whether a login to the user from the address may be tried now
+	pub fn allows(&mut self, username : &str, addr : Option<IpAddr>) -> bool{
+		if let Some(bucket) = self.users.get_mut(&(addr, username.to_string())){
+			bucket.refill(LOGIN_RATE, LOGIN_BURST);
+			if bucket.tokens < 1.0{
+				return false;
+			}
+		}
+		if let Some(bucket) = addr.and_then(|addr| self.addresses.get_mut(&addr)){
+			bucket.refill(IP_LOGIN_RATE, IP_LOGIN_BURST);
+			if bucket.tokens < 1.0{
+				return false;
+			}
+		}
+		return true;
+	}
+
+	pub fn failed(&mut self, username : &str, addr : Option<IpAddr>){
+		let user = self.users.entry((addr, username.to_string())).or_insert_with(|| Bucket::new(LOGIN_BURST));
+		user.refill(LOGIN_RATE, LOGIN_BURST);
+		user.tokens -= 1.0;
+		if let Some(addr) = addr{
+			let address = self.addresses.entry(addr).or_insert_with(|| Bucket::new(IP_LOGIN_BURST));
+			address.refill(IP_LOGIN_RATE, IP_LOGIN_BURST);
+			address.tokens -= 1.0;
+		}
+	}
+
+	//takes a registration from the address if it may register one more account now
+	pub fn sign_up(&mut self, addr : Option<IpAddr>) -> bool{
+		let addr = match addr{
+		    Some(v) => {v}
+		    None => {return true;}
+		};
+		let bucket = self.sign_ups.entry(addr).or_insert_with(|| Bucket::new(SIGN_UP_BURST));
+		bucket.refill(SIGN_UP_RATE, SIGN_UP_BURST);
+		if bucket.tokens < 1.0{
+			return false;
+		}
+		bucket.tokens -= 1.0;
+		return true;
+	}
+
+	//lets go of the users and addresses which have had time to fill their buckets
+	pub fn forget_idle(&mut self){
+		self.users.retain(|_, bucket| {
+			bucket.refill(LOGIN_RATE, LOGIN_BURST);
+			return !bucket.is_full(LOGIN_BURST);
+		});
+		self.addresses.retain(|_, bucket| {
+			bucket.refill(IP_LOGIN_RATE, IP_LOGIN_BURST);
+			return !bucket.is_full(IP_LOGIN_BURST);
+		});
+		self.sign_ups.retain(|_, bucket| {
+			bucket.refill(SIGN_UP_RATE, SIGN_UP_BURST);
+			return !bucket.is_full(SIGN_UP_BURST);
+		});
+	}
+}
+
+//the connections counted against a limit, kept up as they come and go,
+//so that a new connection is checked without going over all the others
+pub struct Conns{
+	addrs : HashMap<Token, Option<IpAddr>>, //of each connection counted, taken when it came
+	from : HashMap<IpAddr, usize> //how many are counted from each address
+}
+
+impl Conns{
+	pub fn new() -> Self{
+		return Conns{addrs : HashMap::new(), from : HashMap::new()};
+	}
+
+	pub fn total(&self) -> usize{
+		return self.addrs.len();
+	}
+
+	pub fn from(&self, addr : IpAddr) -> usize{
+		return self.from.get(&addr).copied().unwrap_or(0);
+	}
+
+	pub fn addr_of(&self, token : Token) -> Option<IpAddr>{
+		return self.addrs.get(&token).copied().flatten();
+	}
+
+	pub fn add(&mut self, token : Token, addr : Option<IpAddr>){
+		if let Some(old) = self.addrs.insert(token, addr){
+			self.uncount(old);
+		}
+		if let Some(addr) = addr{
+			*self.from.entry(addr).or_insert(0) += 1;
+		}
+	}
+
+	pub fn remove(&mut self, token : Token){
+		if let Some(addr) = self.addrs.remove(&token){
+			self.uncount(addr);
+		}
+	}
+
+	//the connection is known under another token, as when it resumes a session
+	pub fn rename(&mut self, token : Token, new : Token){
+		if let Some(addr) = self.addrs.remove(&token){
+			self.add(new, addr);
+		}
+	}
+
+	fn uncount(&mut self, addr : Option<IpAddr>){
+		let addr = match addr{
+		    Some(v) => {v}
+		    None => {return;}
+		};
+		if let Some(count) = self.from.get_mut(&addr){
+			*count -= 1;
+			if *count == 0{
+				self.from.remove(&addr);
+			}
+		}
+	}
+}
@@ -0,0 +1,150 @@
+use std::fmt;
+use std::fs;
+use std::io::prelude::*;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+//what happens on the server, as JSON lines, one event per line:
+//{"time":1700000000.123,"level":"info","event":"join","token":42,"addr":"10.0.0.1:50000","username":"alice"}
+//events go to the log file if one is opened, warnings and errors go to stderr otherwise
+//the file is rotated when it grows too large: server.log is renamed to server.log.1, server.log.1 to server.log.2...
+static LOG : Mutex<Log> = Mutex::new(Log{file : None, path : None, size : 0, max_size : 0, keep : 0, level : Level::Warn});
+
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Level{
+	Debug,
+	Info,
+	Warn,
+	Error
+}
+
+impl Level{
+	pub fn parse(name : &str) -> Result<Self, String>{
+		match name{
+		    "debug" => {return Ok(Level::Debug);}
+		    "info" => {return Ok(Level::Info);}
+		    "warn" => {return Ok(Level::Warn);}
+		    "error" => {return Ok(Level::Error);}
+		    _ => {return Err(format!("No such log level: {}, try debug, info, warn or error", name));}
+		}
+	}
+}
+
+impl fmt::Display for Level{
+	fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result{
+		match self{
+		    Level::Debug => {write!(f, "debug")}
+		    Level::Info => {write!(f, "info")}
+		    Level::Warn => {write!(f, "warn")}
+		    Level::Error => {write!(f, "error")}
+		}
+	}
+}
+
+struct Log{
+	file : Option<fs::File>,
+	path : Option<PathBuf>,
+	size : u64, //of the file so far
+	max_size : u64, //the file is rotated once it would grow past it, never if it is 0
+	keep : usize, //how many rotated files are kept
+	level : Level //events below it are left out
+}
+
+impl Log{
+	fn rotate(&mut self) -> std::io::Result<()>{
+		let path = match &self.path{
+		    Some(v) => {v.clone()}
+		    None => {return Ok(());}
+		};
+		self.file = None;
+		let numbered = |n : usize| {
+			let mut name = path.clone().into_os_string();
+			name.push(format!(".{}", n));
+			return PathBuf::from(name);
+		};
+		if self.keep == 0{
+			fs::remove_file(&path)?;
+		}else{
+			for n in (1..self.keep).rev(){
+				if numbered(n).exists(){
+					fs::rename(numbered(n), numbered(n + 1))?;
+				}
+			}
+			fs::rename(&path, numbered(1))?;
+		}
+		self.file = Some(fs::OpenOptions::new().create(true).append(true).open(&path)?);
+		self.size = 0;
+		return Ok(());
+	}
+
+	fn write(&mut self, line : &str){
+		if self.max_size > 0 && self.size > 0 && self.size + line.len() as u64 + 1 > self.max_size{
+			if let Err(e) = self.rotate(){
+				eprintln!("Couldn't rotate the log: {}", e);
+			}
+		}
+		match &mut self.file{
+		    Some(file) => {
+				if writeln!(file, "{}", line).is_ok(){
+					self.size += line.len() as u64 + 1;
+				}
+			}
+		    None => {eprintln!("{}", line);}
+		}
+	}
+}
+
+//the log is appended to, and is opened once, before anything is logged
+pub fn open(path : &str, level : Level, max_size : u64, keep : usize) -> Result<(), String>{
+	let file = match fs::OpenOptions::new().create(true).append(true).open(path){
+	    Ok(v) => {v}
+	    Err(e) => {return Err(format!("Couldn't open log file {}: {}", path, e));}
+	};
+	let mut log = lock();
+	if log.file.is_some(){
+		return Err("Log file is already open".to_string());
+	}
+	log.size = file.metadata().map(|m| m.len()).unwrap_or(0);
+	log.file = Some(file);
+	log.path = Some(PathBuf::from(path));
+	log.max_size = max_size;
+	log.keep = keep;
+	log.level = level;
+	return Ok(());
+}
+
+fn lock() -> std::sync::MutexGuard<'static, Log>{
+	match LOG.lock(){
+	    Ok(v) => {return v;}
+	    Err(poisoned) => {return poisoned.into_inner();}
+	}
+}
+
+//records an event, with its details in fields, which is a JSON object
+pub fn event(level : Level, event : &str, fields : serde_json::Value){
+	let mut log = lock();
+	if level < log.level{
+		return;
+	}
+	let time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|t| t.as_secs_f64()).unwrap_or(0.0);
+	let mut line = format!("{{\"time\":{:.3},\"level\":\"{}\",\"event\":{}", time, level, serde_json::Value::from(event));
+	if let serde_json::Value::Object(fields) = fields{
+		for (key, value) in fields{
+			line += &format!(",{}:{}", serde_json::Value::from(key), value);
+		}
+	}
+	line.push('}');
+	log.write(&line);
+}
+
+pub fn write(message : fmt::Arguments<'_>){
+	event(Level::Error, "error", serde_json::json!({"message" : message.to_string()}));
+}
+
+//like eprintln, but to the log, as an error
+macro_rules! log{
+	($($arg : tt)*) => {
+		crate::log::write(format_args!($($arg)*))
+	};
+}
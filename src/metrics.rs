@@ -0,0 +1,183 @@
+use std::fmt::Write as _;
+use std::io;
+use std::io::prelude::*;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use mio::net::TcpStream;
+use chat::net::{Error, Traffic};
+
+//the longest request a metrics connection may send, anything past it is not HTTP a scraper would send
+const MAX_REQUEST : usize = 8 * 1024;
+
+//the most metrics connections open at once, a scraper needs one at a time
+pub const MAX_CONNS : usize = 16;
+//how long a metrics connection is kept, from when it is made, to ask and to take the answer
+pub const CONN_TIMEOUT : Duration = Duration::from_secs(5);
+
+//upper bounds of the buckets of the loop latency histogram, in seconds
+const LOOP_BUCKETS : [f64; 6] = [0.0001, 0.0005, 0.001, 0.005, 0.01, 0.1];
+
+//how loaded the server is, shown in the text format of Prometheus on the metrics listener:
+//# TYPE chat_peers gauge
+//chat_peers{state="chatting"} 2
+pub struct Metrics{
+	pub broadcasts : u64, //messages sent to everyone, or to everyone in a room
+	pub kicks : u64,
+	pub timeouts : u64, //of users who were silent too long, and of clients which didn't join in time
+	pub traffic : Arc<Traffic>, //of the peers of the server, each of which holds it
+	loops : [u64; LOOP_BUCKETS.len()], //how many rounds of the event loop took up to each bound
+	loop_count : u64,
+	loop_seconds : f64,
+	started_at : Instant
+}
+
+//what the server has at the moment it is asked
+pub struct Gauges{
+	pub awaiting_auth : usize,
+	pub chatting : usize,
+	pub quitting : usize,
+	pub suspended : usize,
+	pub rooms : usize
+}
+
+impl Metrics{
+	pub fn new() -> Self{
+		return Metrics{
+			broadcasts : 0,
+			kicks : 0,
+			timeouts : 0,
+			traffic : Arc::new(Traffic::default()),
+			loops : [0; LOOP_BUCKETS.len()],
+			loop_count : 0,
+			loop_seconds : 0.0,
+			started_at : Instant::now()
+		};
+	}
+
+	//records how long a round of the event loop took to handle what poll returned
+	pub fn observe_loop(&mut self, took : Duration){
+		let seconds = took.as_secs_f64();
+		for (bound, count) in LOOP_BUCKETS.iter().zip(self.loops.iter_mut()){
+			if seconds <= *bound{
+				*count += 1;
+			}
+		}
+		self.loop_count += 1;
+		self.loop_seconds += seconds;
+	}
+
+	pub fn render(&self, gauges : &Gauges) -> String{
+		let mut out = String::new();
+		let traffic = |counter : &std::sync::atomic::AtomicU64| counter.load(Ordering::Relaxed) as f64;
+		metric(&mut out, "chat_peers", "gauge", "Connected peers by state", &[
+			("state=\"awaiting_auth\"", gauges.awaiting_auth as f64),
+			("state=\"chatting\"", gauges.chatting as f64),
+			("state=\"quitting\"", gauges.quitting as f64)]);
+		metric(&mut out, "chat_suspended_sessions", "gauge", "Users whose connection is lost, waiting to resume", &[("", gauges.suspended as f64)]);
+		metric(&mut out, "chat_rooms", "gauge", "Rooms, the lobby included", &[("", gauges.rooms as f64)]);
+		metric(&mut out, "chat_messages_received_total", "counter", "Messages received from clients", &[("", traffic(&self.traffic.messages_in))]);
+		metric(&mut out, "chat_messages_sent_total", "counter", "Messages sent to clients", &[("", traffic(&self.traffic.messages_out))]);
+		metric(&mut out, "chat_broadcasts_total", "counter", "Messages sent to everyone or to a room", &[("", self.broadcasts as f64)]);
+		metric(&mut out, "chat_bytes_received_total", "counter", "Bytes received from clients", &[("", traffic(&self.traffic.bytes_in))]);
+		metric(&mut out, "chat_bytes_sent_total", "counter", "Bytes sent to clients", &[("", traffic(&self.traffic.bytes_out))]);
+		metric(&mut out, "chat_kicks_total", "counter", "Users kicked by admins", &[("", self.kicks as f64)]);
+		metric(&mut out, "chat_timeouts_total", "counter", "Clients dropped for silence or for not joining in time", &[("", self.timeouts as f64)]);
+		metric(&mut out, "chat_decode_failures_total", "counter", "Connections dropped for sending something else than messages",
+			&[("", traffic(&self.traffic.decode_failures))]);
+		metric(&mut out, "chat_uptime_seconds", "gauge", "Time since the server started", &[("", self.started_at.elapsed().as_secs_f64())]);
+
+		let _ = writeln!(out, "# HELP chat_loop_seconds Time a round of the event loop takes");
+		let _ = writeln!(out, "# TYPE chat_loop_seconds histogram");
+		for (bound, count) in LOOP_BUCKETS.iter().zip(self.loops.iter()){
+			let _ = writeln!(out, "chat_loop_seconds_bucket{{le=\"{}\"}} {}", bound, count);
+		}
+		let _ = writeln!(out, "chat_loop_seconds_bucket{{le=\"+Inf\"}} {}", self.loop_count);
+		let _ = writeln!(out, "chat_loop_seconds_sum {}", self.loop_seconds);
+		let _ = writeln!(out, "chat_loop_seconds_count {}", self.loop_count);
+		return out;
+	}
+}
+
+//writes a metric with its samples, each with its labels
+fn metric(out : &mut String, name : &str, kind : &str, help : &str, samples : &[(&str, f64)]){
+	let _ = writeln!(out, "# HELP {} {}", name, help);
+	let _ = writeln!(out, "# TYPE {} {}", name, kind);
+	for (labels, value) in samples{
+		if labels.is_empty(){
+			let _ = writeln!(out, "{} {}", name, value);
+		}else{
+			let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+		}
+	}
+}
+
+//a connection to the metrics listener, which sends one HTTP request and is answered and closed
+pub struct HttpConn{
+	pub stream : TcpStream,
+	pub connected_at : Instant,
+	request : Vec<u8>,
+	answered : bool,
+	out_buff : Vec<u8>
+}
+
+impl HttpConn{
+	pub fn new(stream : TcpStream) -> Self{
+		return HttpConn{stream, connected_at : Instant::now(), request : Vec::new(), answered : false, out_buff : Vec::new()};
+	}
+
+	//reads what has arrived, giving the path asked for once the headers are whole
+	pub fn read(&mut self) -> Result<Option<String>, Error>{
+		let mut buff = [0u8; 1024];
+		loop{
+			let n = match self.stream.read(&mut buff){
+			    Ok(0) => {return Err(Error::Closed);}
+			    Ok(n) => n,
+			    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {break;}
+			    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {continue;}
+			    Err(_) => {return Err(Error::Closed);}
+			};
+			self.request.extend_from_slice(&buff[..n]);
+			if self.request.len() > MAX_REQUEST{
+				return Err(Error::TooLarge(self.request.len()));
+			}
+		}
+		if !self.request.windows(4).any(|w| w == b"\r\n\r\n"){
+			return Ok(None);
+		}
+		let request = String::from_utf8_lossy(&self.request);
+		let mut words = request.split_whitespace();
+		match (words.next(), words.next()){
+		    (Some("GET"), Some(path)) => {return Ok(Some(path.to_string()));}
+		    _ => {return Err(Error::Malformed("Not a GET request".to_string()));}
+		}
+	}
+
+	pub fn respond(&mut self, status : &str, body : &str){
+		let response = format!("HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+			status, body.len(), body);
+		self.out_buff.extend_from_slice(response.as_bytes());
+		self.answered = true;
+		self.flush();
+	}
+
+	pub fn flush(&mut self){
+		while !self.out_buff.is_empty(){
+			match self.stream.write(&self.out_buff){
+			    Ok(0) => {break;}
+			    Ok(n) => {self.out_buff.drain(..n);}
+			    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {continue;}
+			    Err(_) => {break;}
+			}
+		}
+	}
+
+	pub fn is_answered(&self) -> bool{
+		return self.answered;
+	}
+
+	//whether the response has been written out, and the connection may be closed
+	pub fn is_done(&self) -> bool{
+		return self.answered && self.out_buff.is_empty();
+	}
+}
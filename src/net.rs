@@ -1,9 +1,40 @@
+use std::fmt;
 use std::time;
+use std::io;
 use std::io::prelude::*;
-use std::net::TcpListener;
-use std::net::TcpStream;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use bincode::Options;
+use mio::net::TcpStream;
 
-pub struct Error{}
+//what can go wrong with a connection or with the packets going through it
+pub enum Error{
+	Io(String), //the connection couldn't be made
+	Malformed(String), //the bytes received aren't a packet of the protocol, with what is wrong with them
+	TooLarge(usize), //a message is too large to fit into a packet, with its size
+	Serialize(String), //a message couldn't be serialized
+	Backlog, //more than MAX_BACKLOG is waiting to be sent, the other side doesn't read what it is sent
+	Closed //the other side has closed or reset the connection
+}
+
+impl fmt::Display for Error{
+	fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result{
+		match self{
+		    Error::Io(e) => {write!(f, "Connection failed: {}", e)}
+		    Error::Malformed(e) => {write!(f, "Malformed packet: {}", e)}
+		    Error::TooLarge(size) => {write!(f, "A message of {} bytes is too large to send", size)}
+		    Error::Serialize(e) => {write!(f, "Failed to serialize: {}", e)}
+		    Error::Backlog => {write!(f, "Too much is waiting to be sent")}
+		    Error::Closed => {write!(f, "Connection closed")}
+		}
+	}
+}
+
+impl fmt::Debug for Error{
+	fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result{
+		return fmt::Display::fmt(self, f);
+	}
+}
 
 pub const MAX_SILENCE : time::Duration = time::Duration::from_secs(10);
 pub fn invalid_tok(){
@@ -11,64 +42,279 @@ pub fn invalid_tok(){
 }
 
 pub type Token = u64;
+
+//totals of what the peers sharing it have sent and received, which a server shows in its metrics
+#[derive(Default)]
+pub struct Traffic{
+	pub bytes_in : AtomicU64,
+	pub bytes_out : AtomicU64,
+	pub messages_in : AtomicU64,
+	pub messages_out : AtomicU64,
+	pub decode_failures : AtomicU64 //of connections which have sent something else than messages
+}
+
+//adds to a counter of the traffic, if the peer counts its traffic anywhere
+fn count(traffic : &Option<Arc<Traffic>>, counter : fn(&Traffic) -> &AtomicU64, n : usize){
+	if let Some(traffic) = traffic{
+		counter(traffic).fetch_add(n as u64, Ordering::Relaxed);
+	}
+}
 pub type pck_size_t = u16;
 
-#[derive(serde::Deserialize, serde::Serialize)]
+//the version of the protocol this crate speaks, and the oldest one a server still serves, the one before it
+pub const PROTOCOL_VERSION : u16 = 3;
+pub const OLDEST_PROTOCOL_VERSION : u16 = PROTOCOL_VERSION - 1;
+//the version of a peer which hasn't told its own, the protocol before the versions were exchanged
+pub const UNVERSIONED : u16 = 1;
+//features of the protocol this crate supports, both sides tell theirs to each other
+pub const CAPABILITIES : &[&str] = &["rooms", "direct", "history", "accounts", "resume", "rename", "admin"];
+
+//the longest line a peer speaking JSON may send, JSON takes more room than bincode for the same message
+pub const MAX_LINE : usize = 1 << 20;
+//the most bytes which may wait to be sent to a peer, a message is only sent past it if nothing waits,
+//so that any message fits
+pub const MAX_BACKLOG : usize = 1 << 20;
+
+//the longest username or room name, in bytes, so that the names sent along with a message are bounded
+pub const MAX_NAME_LEN : usize = 64;
+//what a message takes in a packet besides its text, at most: the variant, the token, the room and the usernames
+//with their lengths, and the ": " after the sender's name
+pub const MESSAGE_OVERHEAD : usize = 4 + 8 + 3 * (8 + MAX_NAME_LEN) + 2;
+//the longest message, in characters, which fits into a packet even if all of its characters take 4 bytes
+pub const MAX_MESSAGE_LEN : usize = (pck_size_t::MAX as usize - MESSAGE_OVERHEAD) / 4;
+
+//every user enters this room upon joining, and it is never removed
+pub const LOBBY : &str = "lobby";
+//the most of past messages a server sends in response to a single request
+pub const MAX_HISTORY : usize = 100;
+
+//a command of a privileged user, see admin::Command for what it may be
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct ClAdmin{
+	pub token : Token,
+	pub command : crate::admin::Command
+}
+
+//versions of the protocol the client speaks, from oldest to version, and its capabilities
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct ClVersion{
+	pub version : u16,
+	pub oldest : u16,
+	pub capabilities : Vec<String>
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct ClHello{
 	pub username : String,
 }
 
-#[derive(serde::Deserialize, serde::Serialize)]
+//used instead of ClHello to register a new account or to log into an existing one
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct ClCredentials{
+	pub username : String,
+	pub password : String
+}
+
+//the version the server has chosen for the connection, and the server's capabilities
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct SeVersion{
+	pub version : u16,
+	pub capabilities : Vec<String>
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct SeHello{
 	pub token : Token,
 }
 
-#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct ClMesg{
-	pub username : String,
 	pub token : Token,
+	pub room : String,
 	pub mesg : String
 }
 
-#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct ClDirect{
+	pub token : Token,
+	pub to : String, //username of the recipient
+	pub mesg : String
+}
+
+//request of the last count messages posted to a room
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct ClHistory{
+	pub token : Token,
+	pub room : String,
+	pub count : usize
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, PartialEq)]
+pub enum RoomAction{
+	Create,//creates a new room and enters it
+	Enter,//enters an existing room
+	Leave
+}
+
+//request to change the username of the client
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct ClRename{
+	pub token : Token,
+	pub username : String
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct ClRoom{
+	pub token : Token,
+	pub room : String,
+	pub action : RoomAction
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct SeMesg{
+	pub room : String, //empty for messages addressed to the whole server
 	pub mesg : String
 }
 
-#[derive(serde::Deserialize, serde::Serialize)]
-pub struct SeInfo{
+//a private message, sent to both the recipient and the sender
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct SeDirect{
+	pub from : String,
+	pub to : String,
+	pub mesg : String
+}
+
+//confirms to a client that it has entered or left a room
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct SeRoom{
+	pub room : String,
+	pub action : RoomAction
+}
+
+//confirms to a client that its username has changed
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct SeRenamed{
+	pub old : String,
+	pub new : String
+}
+
+//past messages of a room, oldest first
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct SeHistory{
+	pub room : String,
+	pub mesgs : Vec<String>
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct RoomInfo{
+	pub name : String,
 	pub users : Vec<String>
 }
 
-#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct SeInfo{
+	pub users : Vec<String>,
+	pub rooms : Vec<RoomInfo>
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
+pub struct SeError{
+	pub reason : String
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub struct SeUQuit{
 	pub reason : String
 }
 
 //overall protocol works like this:
-//a client sends ClMessage::Hello
+//a client first sends ClMessage::Version with the versions of the protocol it speaks and its capabilities,
+//server responds with SeMessage::Version with the version chosen for the connection, the newest both speak,
+//or with SeMessage::UQuit if there is none, a client which starts with a hello speaks version 1,
+//which is older than any a server serves, and is refused
+//then a client sends ClMessage::Hello, or ClMessage::Register/ClMessage::Login to use a registered account
+//names of registered accounts are only given to clients which know the password
 //server responds with SeMessage::Hello if it allows joining, or with SeMessage::UQuit if id doesn't
-//a client now may chat via ClMessage::Mesg packets
+//right after the hello server sends SeMessage::History with the recent messages of the LOBBY
+//a client is placed into the LOBBY room, and may enter or leave other rooms via ClMessage::Room
+//a client now may chat via ClMessage::Mesg packets, which are delivered to the members of the room only
+//requests carry the token the client got in the hello, and are refused if it isn't the one of their connection,
+//the server takes who the user is from the connection only
+//or message a single user via ClMessage::Direct
+//a client may change its username with ClMessage::Rename, which the server confirms with SeMessage::Renamed
+//a privileged user may moderate with ClMessage::Admin, which the server answers with SeMessage::Reply,
+//what one may do is told by the role the server has given to the username
 //if a client wants to leave, it sends ClMessage::IQuit
+//if the connection is lost, the server keeps the user's name, rooms and messages for a while,
+//a client takes them back by greeting the server with ClMessage::Resume and the token of the lost session,
+//then it gets SeMessage::Hello, SeMessage::Room for every room it is in, and the messages it has missed
 //if a server wants to remove client, it does so but sends SeMessage::UQuit first
 
+//messages go either in bincode, each one after its size as u16, or in JSON, each one on its own line,
+//which makes it possible to talk to a server with netcat or from other languages:
+//{"Hello":{"username":"bob"}}
+//a server serves both at once, telling which one a client speaks from the first bytes it sends
+//over WebSocket each frame holds one message, JSON in text frames and bincode without the size in binary ones
+
+//new messages are only ever appended to the enums, so that the old ones are serialized as before,
+//and each one is marked with the version which has brought it in since(),
+//a peer is never sent a message newer than the version of its connection
+
 //messages that a client might send
-#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub enum ClMessage{
 	Hello(ClHello),//client first connects to server and sends this
 	Mesg(ClMesg),//client sends this if wants to post
 	IWantInfo(Token),//request from client of information about server
 	IQuit(Token),//client notifies that he leaves
-	Ping(Token)//sent to ensure the server that the client is here
+	Ping(Token),//sent to ensure the server that the client is here
+	Room(ClRoom),//client creates, enters or leaves a room
+	Direct(ClDirect),//client sends a private message to another user
+	History(ClHistory),//client asks for past messages of a room
+	Register(ClCredentials),//like hello, but registers the username with a password first
+	Login(ClCredentials),//like hello, but for a registered username
+	Resume(Token),//like hello, but takes back the session of a lost connection with its token
+	Rename(ClRename),//client wants another username
+	Version(ClVersion),//client tells which versions of the protocol it speaks, before the hello
+	Admin(ClAdmin)//a privileged user gives a command
+}
+
+impl ClMessage{
+	//the version of the protocol which has brought the message in
+	pub fn since(&self) -> u16{
+		match self{
+		    ClMessage::Version(_) => {2}
+		    ClMessage::Admin(_) => {3}
+		    _ => {1}
+		}
+	}
 }
 
 //messages that a server sends
-#[derive(serde::Deserialize, serde::Serialize)]
+#[derive(serde::Deserialize, serde::Serialize, Clone)]
 pub enum SeMessage{
 	Hello(SeHello),//sent after client hello
 	Mesg(SeMesg),//a message to the client
 	Info(SeInfo),//information about server
 	UQuit(SeUQuit),//sent upon kicking from chat or if authentithication is refused
+	Room(SeRoom),//sent when the client has entered or left a room
+	Error(SeError),//sent when a request of the client can't be fulfilled
+	Direct(SeDirect),//a private message
+	History(SeHistory),//past messages of a room
+	Renamed(SeRenamed),//sent when the client's username has changed
+	Version(SeVersion),//answer to ClMessage::Version
+	Reply(crate::admin::Reply)//answer to ClMessage::Admin
+}
+
+impl SeMessage{
+	//the version of the protocol which has brought the message in
+	pub fn since(&self) -> u16{
+		match self{
+		    SeMessage::Version(_) => {2}
+		    SeMessage::Reply(_) => {3}
+		    _ => {1}
+		}
+	}
 }
 
 pub enum PeerState{
@@ -77,6 +323,103 @@ pub enum PeerState{
 	Quitting
 }
 
+//how messages are put on the wire, chosen per connection
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Encoding{
+	Bincode, //packets of the size as u16 followed by the message in bincode
+	Json //a message in JSON per line
+}
+
+//the byte order mark some editors and libraries put before UTF-8 text
+const BOM : &[u8] = b"\xEF\xBB\xBF";
+
+//a bincode client starts with the size of its first packet as u16 and the variant of the message as u32,
+//whose upper bytes are zero, while JSON text never holds a zero byte
+const SNIFFED : usize = 6;
+
+//tells the encoding from the first bytes of a connection, None if it takes more of them
+//a JSON client starts with an object, maybe after whitespace and a BOM, while a bincode one starts with a size,
+//which looks like the start of an object for a few sizes above 2000 bytes, like 0x227B, which is `{"`,
+//so the zero bytes of the variant that follows the size tell the two apart
+fn sniff(bytes : &[u8]) -> Option<Encoding>{
+	let head = &bytes[..bytes.len().min(SNIFFED)];
+	if head.contains(&0){
+		return Some(Encoding::Bincode);
+	}
+	if BOM.starts_with(bytes){
+		return None;
+	}
+	let text = bytes.strip_prefix(BOM).unwrap_or(bytes);
+	match text.iter().find(|b| !b.is_ascii_whitespace()){
+	    Some(b'{') | None if bytes.len() >= SNIFFED => {return Some(Encoding::Json);}
+	    Some(b'{') | None => {return None;}
+	    Some(_) => {return Some(Encoding::Bincode);}
+	}
+}
+
+//splits newline-delimited JSON into messages, like MesgBuilder does for bincode
+pub struct JsonBuilder{
+	line : Vec<u8> //the line which hasn't ended yet
+}
+
+impl Default for JsonBuilder{
+	fn default() -> Self{
+		return JsonBuilder::new();
+	}
+}
+
+impl JsonBuilder{
+	pub fn new() -> Self{
+		return JsonBuilder{line : Vec::new()};
+	}
+
+	//eats a slice of u8, and returns messages of the lines it has completed, empty lines are skipped
+	//after an error the builder is not to be fed anymore, as with MesgBuilder
+	pub fn eat<RE_T>(&mut self, slice : &[u8]) -> Result<Vec<RE_T>, Error>
+	where RE_T: for<'a> serde::Deserialize<'a>
+	{
+		let mut mesgs : Vec<RE_T> = Vec::new();
+		let mut rest = slice;
+		while let Some(pos) = rest.iter().position(|b| *b == b'\n'){
+			self.line.extend_from_slice(&rest[..pos]);
+			rest = &rest[pos + 1..];
+			let line = std::mem::take(&mut self.line);
+			if line.len() > MAX_LINE{
+				return Err(Error::Malformed("Line is too long".to_string()));
+			}
+			let line = line.trim_ascii();
+			if line.is_empty(){
+				continue;
+			}
+			match serde_json::from_slice(line){
+			    Ok(v) => {mesgs.push(v);}
+			    Err(e) => {return Err(Error::Malformed(e.to_string()));}
+			}
+		}
+		self.line.extend_from_slice(rest);
+		if self.line.len() > MAX_LINE{
+			return Err(Error::Malformed("Line is too long".to_string()));
+		}
+		return Ok(mesgs);
+	}
+}
+
+//decodes the bytes of a peer
+enum Decoder{
+	Sniffing(Vec<u8>), //the encoding isn't known till the first bytes arrive, which are kept till then
+	Bincode(MesgBuilder),
+	Json(JsonBuilder)
+}
+
+impl Decoder{
+	fn new(encoding : Encoding) -> Self{
+		match encoding{
+		    Encoding::Bincode => {return Decoder::Bincode(MesgBuilder::new());}
+		    Encoding::Json => {return Decoder::Json(JsonBuilder::new());}
+		}
+	}
+}
+
 enum BuilderState{
 	GettingSize,
 	GettingPacket
@@ -92,6 +435,12 @@ pub struct MesgBuilder{
 	state : BuilderState
 }
 
+impl Default for MesgBuilder{
+	fn default() -> Self{
+		return MesgBuilder::new();
+	}
+}
+
 impl MesgBuilder{
 	pub fn new() -> Self{
 		return MesgBuilder{
@@ -102,144 +451,504 @@ impl MesgBuilder{
 			state : BuilderState::GettingSize
 		};
 	}
-	//this function eats a slice of u8, and returns messages it got from it
-	//or None if there is no complete messages
-	pub fn eat<RE_T>(&mut self, slice : &[u8] ) -> Option<Vec<RE_T>>
+	//this function eats a slice of u8, and returns messages it got from it, which may be none
+	//if the bytes can't be a packet, an error is returned, and the builder is not to be fed anymore,
+	//since the boundaries of the following packets can't be known
+	pub fn eat<RE_T>(&mut self, slice : &[u8] ) -> Result<Vec<RE_T>, Error>
 	where RE_T: for<'a> serde::Deserialize<'a>
 	{
 		let mut mesgs : Vec<RE_T> = Vec::new();
 		let mut pos = 0usize;
-		loop{
+		while pos < slice.len(){
 			match self.state{
 			    BuilderState::GettingSize => {
-					let mut to_read = self.size_left;
-					if to_read > slice.len() - pos{
-						to_read = slice.len() - pos;
-					}
-					self.size_buff.append(&mut slice[pos..pos+to_read].to_vec());
+					let to_read = self.size_left.min(slice.len() - pos);
+					self.size_buff.extend_from_slice(&slice[pos..pos+to_read]);
 					pos += to_read;
 					self.size_left -= to_read;
 
 					if self.size_left == 0{
-						self.state = BuilderState::GettingPacket;
-						let pck_size : pck_size_t = bincode::deserialize(&self.size_buff[0..2]).unwrap();
+						let pck_size : pck_size_t = match bincode::deserialize(&self.size_buff[..]){
+						    Ok(v) => {v}
+						    Err(e) => {return Err(Error::Malformed(e.to_string()));}
+						};
+						//no message serializes into nothing
+						if pck_size == 0{
+							return Err(Error::Malformed("Empty packet".to_string()));
+						}
 						self.size_buff.clear();
 						self.pck_left = pck_size as usize;
+						self.state = BuilderState::GettingPacket;
 					}
 				}
 			    BuilderState::GettingPacket => {
-					let mut to_read = self.pck_left;
-					if to_read > slice.len() - pos{
-						to_read = slice.len() - pos;
-					}
-					self.pck_buff.append(&mut slice[pos..pos+to_read].to_vec());
+					let to_read = self.pck_left.min(slice.len() - pos);
+					self.pck_buff.extend_from_slice(&slice[pos..pos+to_read]);
 					pos += to_read;
 					self.pck_left -= to_read;
 
 					if self.pck_left == 0{
-						self.state = BuilderState::GettingSize;
-						self.size_left = std::mem::size_of::<pck_size_t>();
-						let mesg = bincode::deserialize(&self.pck_buff[..]).unwrap();
+						let mesg = match decode(&self.pck_buff[..]){
+						    Ok(v) => {v}
+						    Err(e) => {return Err(Error::Malformed(e.to_string()));}
+						};
 						self.pck_buff.clear();
+						self.size_left = std::mem::size_of::<pck_size_t>();
+						self.state = BuilderState::GettingSize;
 						mesgs.push(mesg);
 					}
 				}
 			}
-			if pos == slice.len(){
-				break;
-			}
 		}
-		if mesgs.len() > 0{
-			return Some(mesgs);
+		return Ok(mesgs);
+	}
+}
+
+//deserializes a message the same way bincode::serialize has serialized it,
+//but doesn't let a packet claim more than it has, nor carry anything after the message
+fn decode<RE_T>(pck : &[u8]) -> bincode::Result<RE_T>
+where RE_T: for<'a> serde::Deserialize<'a>
+{
+	return bincode::DefaultOptions::new()
+		.with_fixint_encoding()
+		.with_limit(pck.len() as u64)
+		.reject_trailing_bytes()
+		.deserialize(pck);
+}
+
+
+//a connection to a peer, either plain or encrypted with TLS
+//the framing of messages is the same for both
+//the underlying socket is nonblocking, and is meant to be driven by a mio::Poll
+pub enum Stream{
+	Plain(TcpStream),
+	TlsServer(Box<rustls::StreamOwned<rustls::ServerConnection, TcpStream>>),
+	TlsClient(Box<rustls::StreamOwned<rustls::ClientConnection, TcpStream>>),
+	WebSocket(Box<crate::ws::WebSocket>) //over one of the others, carries messages in frames instead of bytes
+}
+
+impl Stream{
+	//the socket, which a WebSocket whose upgrade has failed no longer has
+	pub fn tcp(&self) -> Option<&TcpStream>{
+		match self{
+		    Stream::Plain(s) => {Some(s)}
+		    Stream::TlsServer(s) => {Some(s.get_ref())}
+		    Stream::TlsClient(s) => {Some(s.get_ref())}
+		    Stream::WebSocket(s) => {s.get_ref().and_then(|s| s.tcp())}
+		}
+	}
+
+	pub fn tcp_mut(&mut self) -> Option<&mut TcpStream>{
+		match self{
+		    Stream::Plain(s) => {Some(s)}
+		    Stream::TlsServer(s) => {Some(s.get_mut())}
+		    Stream::TlsClient(s) => {Some(s.get_mut())}
+		    Stream::WebSocket(s) => {s.get_mut().and_then(|s| s.tcp_mut())}
+		}
+	}
+}
+
+//bytes of a WebSocket are read and written by tungstenite, a peer deals with its frames
+fn frames_only() -> io::Error{
+	return io::Error::new(io::ErrorKind::Unsupported, "WebSocket carries frames, not bytes");
+}
+
+impl Read for Stream{
+	fn read(&mut self, buf : &mut [u8]) -> io::Result<usize>{
+		match self{
+		    Stream::Plain(s) => {s.read(buf)}
+		    Stream::TlsServer(s) => {s.read(buf)}
+		    Stream::TlsClient(s) => {s.read(buf)}
+		    Stream::WebSocket(_) => {Err(frames_only())}
 		}
-		return None;
 	}
 }
 
+impl Write for Stream{
+	fn write(&mut self, buf : &[u8]) -> io::Result<usize>{
+		match self{
+		    Stream::Plain(s) => {s.write(buf)}
+		    Stream::TlsServer(s) => {s.write(buf)}
+		    Stream::TlsClient(s) => {s.write(buf)}
+		    Stream::WebSocket(_) => {Err(frames_only())}
+		}
+	}
+
+	fn flush(&mut self) -> io::Result<()>{
+		match self{
+		    Stream::Plain(s) => {s.flush()}
+		    Stream::TlsServer(s) => {s.flush()}
+		    Stream::TlsClient(s) => {s.flush()}
+		    Stream::WebSocket(_) => {Err(frames_only())}
+		}
+	}
+}
 
 //A peer struct
 //TR_T is the type of message being transmitted, RE_T is the type being received.
 //For server's peer (which is a client) TR_T would be SeMessage, and RE_T would be ClMessage,
 //since server sends server's message, and receives client's message
+//Received messages are queued in arrival order, so they are to be taken with pop_front
 pub struct Peer<RE_T>{
 	pub username : String,
 	pub token : Token,
 	pub messages : std::collections::VecDeque<RE_T>,
-	stream : TcpStream,
+	stream : Stream,
 	pub state : PeerState,
+	pub version : u16, //of the protocol spoken over the connection
+	pub capabilities : Vec<String>, //of the other side
 	pub silent_from : time::Instant,
-	builder : MesgBuilder
+	pub connected_at : time::Instant,
+	encoding : Encoding, //of the messages sent, and received once the decoder knows it
+	decoder : Decoder,
+	out_buff : Vec<u8>, //bytes which the socket couldn't take yet
+	backlogged : bool, //a message couldn't be sent because too much was waiting
+	pub traffic : Option<Arc<Traffic>> //where what goes through the connection is counted, nowhere if None
 }
 
 impl<RE_T> Peer<RE_T>
 where RE_T: for<'a> serde::Deserialize<'a>
 {
 	pub fn connect(&mut self, addr : String) -> Result<(), Error>{
-		let stream = match TcpStream::connect(addr){
+		let stream = match std::net::TcpStream::connect(addr){
 		    Ok(v) => {v}
-		    Err(_) => {return Result::Err(Error{});}
+		    Err(e) => {return Result::Err(Error::Io(e.to_string()));}
 		};
-		self.stream = stream;
+		if let Err(e) = stream.set_nonblocking(true){
+			return Result::Err(Error::Io(e.to_string()));
+		}
+		self.stream = Stream::Plain(TcpStream::from_std(stream));
 		Ok(())
 	}
 
-	pub fn new(token : &Token, stream : TcpStream) -> Self{
-		stream.set_nonblocking(true);
+	//a peer speaking bincode
+	pub fn new(token : &Token, stream : Stream) -> Self{
+		return Peer::with_encoding(token, stream, Some(Encoding::Bincode));
+	}
+
+	//a peer speaking the encoding, or the one it turns out to speak if it is None
+	pub fn with_encoding(token : &Token, stream : Stream, encoding : Option<Encoding>) -> Self{
+		let decoder = match encoding{
+		    Some(v) => {Decoder::new(v)}
+		    None => {Decoder::Sniffing(Vec::new())}
+		};
 		let peer = Peer{
 			username : String::new(),
 			token :  *token,
 			messages : std::collections::VecDeque::new(),
 			stream,
 			state : PeerState::AwaitingAuth,
+			version : UNVERSIONED,
+			capabilities : Vec::new(),
 			silent_from : time::Instant::now(),
-			builder : MesgBuilder::new()
+			connected_at : time::Instant::now(),
+			encoding : encoding.unwrap_or(Encoding::Bincode),
+			decoder,
+			out_buff : Vec::new(),
+			backlogged : false,
+			traffic : None
 		};
 		return peer;
 	}
 
-	pub fn send<TR_T>(&mut self, mesg : &TR_T)
+	//queues a message and writes out what the socket takes
+	//a message which can't be sent is not queued, and nothing is sent in its place
+	pub fn send<TR_T>(&mut self, mesg : &TR_T) -> Result<(), Error>
 	where TR_T: serde::Serialize
 	{
-		self.keep();
+		if self.encoding == Encoding::Json{
+			let line = match serde_json::to_string(mesg){
+			    Ok(val) => {val}
+			    Err(e) => {return Err(Error::Serialize(e.to_string()));}
+			};
+			if line.len() > MAX_LINE{
+				return Err(Error::TooLarge(line.len()));
+			}
+			self.keep();
+			count(&self.traffic, |t| &t.messages_out, 1);
+			if let Stream::WebSocket(ws) = &mut self.stream{
+				count(&self.traffic, |t| &t.bytes_out, line.len());
+				let result = ws.write(tungstenite::Message::text(line));
+				self.backlogged |= matches!(result, Err(Error::Backlog));
+				return result;
+			}
+			self.check_backlog(line.len() + 1)?;
+			self.out_buff.extend_from_slice(line.as_bytes());
+			self.out_buff.push(b'\n');
+			self.flush();
+			return Ok(());
+		}
 		let mesg_ser : Vec<u8> = match bincode::serialize(mesg){
 		    Ok(val) => {val}
-		    Err(_) => {
-				eprintln!("Failed to serialize");
-				return;}
+		    Err(e) => {return Err(Error::Serialize(e.to_string()));}
 		};
-		//TODO - return error if the packet is too large and handle it
 		if mesg_ser.len() > pck_size_t::MAX.into(){
-			eprintln!("A packet is too large");
-			return;
+			return Err(Error::TooLarge(mesg_ser.len()));
+		}
+		self.keep();
+		count(&self.traffic, |t| &t.messages_out, 1);
+		if let Stream::WebSocket(ws) = &mut self.stream{
+			count(&self.traffic, |t| &t.bytes_out, mesg_ser.len());
+			let result = ws.write(tungstenite::Message::binary(mesg_ser));
+			self.backlogged |= matches!(result, Err(Error::Backlog));
+			return result;
 		}
+		self.check_backlog(mesg_ser.len() + std::mem::size_of::<pck_size_t>())?;
 		let size : pck_size_t = mesg_ser.len() as pck_size_t;
 		let size = bincode::serialize(&size).expect("Failed to serialize");
-		self.stream.write(&size[..]);
-		self.stream.write(&mesg_ser[..]);
+		self.out_buff.extend_from_slice(&size[..]);
+		self.out_buff.extend_from_slice(&mesg_ser[..]);
+		self.flush();
+		return Ok(());
+	}
+
+	//the most bytes a message may take in the encoding spoken with the peer
+	pub fn max_size(&self) -> usize{
+		match self.encoding{
+		    Encoding::Json => {return MAX_LINE;}
+		    Encoding::Bincode => {return pck_size_t::MAX.into();}
+		}
+	}
+
+	//the bytes a value takes in the encoding spoken with the peer, usize::MAX if it can't be serialized
+	pub fn size_of<T : serde::Serialize>(&self, value : &T) -> usize{
+		match self.encoding{
+		    Encoding::Json => {return serde_json::to_string(value).map(|v| v.len()).unwrap_or(usize::MAX);}
+		    Encoding::Bincode => {return bincode::serialized_size(value).map(|v| v as usize).unwrap_or(usize::MAX);}
+		}
 	}
 
-	pub fn get_messages(&mut self){
-		let mut buff = [0u8; 1024];
+	fn check_backlog(&mut self, size : usize) -> Result<(), Error>{
+		if !self.out_buff.is_empty() && self.out_buff.len() + size > MAX_BACKLOG{
+			self.backlogged = true;
+			return Err(Error::Backlog);
+		}
+		return Ok(());
+	}
+
+	//whether a message couldn't be sent because the other side had left too much unread,
+	//such a peer is better disconnected than kept waiting for what it has missed
+	pub fn is_backlogged(&self) -> bool{
+		return self.backlogged;
+	}
 
-		let n = match self.stream.read(&mut buff){
-		    Ok(n) => n,
-		    Err(_) => {return;}
+	//writes out as much of the pending bytes as the socket takes
+	//the rest is written once the socket becomes writable again
+	pub fn flush(&mut self){
+		if let Stream::WebSocket(ws) = &mut self.stream{
+			ws.flush();
+			return;
+		}
+		while !self.out_buff.is_empty(){
+			match self.stream.write(&self.out_buff){
+			    Ok(0) => {break;}
+			    Ok(n) => {
+					count(&self.traffic, |t| &t.bytes_out, n);
+					self.out_buff.drain(..n);
+				}
+			    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {continue;}
+			    //the socket is busy, or the peer might have already gone, then there is nobody to complain to
+			    Err(_) => {break;}
+			}
+		}
+		//TLS keeps its own buffer of encrypted bytes
+		let _ = self.stream.flush();
+	}
+
+	//reads everything that has arrived, since readiness of the socket is only reported once
+	//an error means the peer is not to be read from anymore, either because it has sent
+	//something else than packets, or because the connection is closed (Error::Closed)
+	//messages which have arrived before the error are still queued
+	pub fn get_messages(&mut self) -> Result<(), Error>{
+		let result = self.read_messages();
+		if matches!(result, Err(ref e) if !matches!(e, Error::Closed)){
+			count(&self.traffic, |t| &t.decode_failures, 1);
+		}
+		return result;
+	}
+
+	fn read_messages(&mut self) -> Result<(), Error>{
+		if let Stream::WebSocket(ws) = &mut self.stream{
+			let mut frames = Vec::new();
+			let result = ws.read(&mut frames);
+			for frame in frames{
+				self.feed_frame(frame)?;
+			}
+			return result;
+		}
+		let mut buff = [0u8; 4096];
+		loop{
+			let n = match self.stream.read(&mut buff){
+			    Ok(0) => {return Err(Error::Closed);}
+			    Ok(n) => n,
+			    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {return Ok(());}
+			    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {continue;}
+			    Err(_) => {return Err(Error::Closed);}
+			};
+			count(&self.traffic, |t| &t.bytes_in, n);
+			self.feed(&buff[0..n])?;
+		}
+	}
+
+	//the encoding of the peer, which is Bincode till a peer of unknown encoding has sent something
+	pub fn encoding(&self) -> Encoding{
+		return self.encoding;
+	}
+
+	fn feed(&mut self, bytes : &[u8]) -> Result<(), Error>{
+		let mesgs = match &mut self.decoder{
+		    Decoder::Sniffing(seen) => {
+				seen.extend_from_slice(bytes);
+				let encoding = match sniff(seen){
+				    Some(v) => {v}
+				    None => {return Ok(());}
+				};
+				let seen = std::mem::take(seen);
+				self.encoding = encoding;
+				self.decoder = Decoder::new(encoding);
+				if encoding == Encoding::Json{
+					return self.feed(seen.strip_prefix(BOM).unwrap_or(&seen));
+				}
+				return self.feed(&seen);
+			}
+		    Decoder::Bincode(builder) => {builder.eat::<RE_T>(bytes)?}
+		    Decoder::Json(builder) => {builder.eat::<RE_T>(bytes)?}
 		};
+		count(&self.traffic, |t| &t.messages_in, mesgs.len());
+		self.messages.extend(mesgs);
+		return Ok(());
+	}
 
-		let mut mesgs = match self.builder.eat::<RE_T>(&buff[0..n]){
-		    Some(mesgs) => mesgs,
-		    None => {return;}
+	//a frame of a WebSocket holds a whole message, the first one tells the encoding like the first bytes do
+	fn feed_frame(&mut self, frame : tungstenite::Message) -> Result<(), Error>{
+		count(&self.traffic, |t| &t.bytes_in, frame.len());
+		let encoding = match frame{
+		    tungstenite::Message::Text(_) => {Encoding::Json}
+		    _ => {Encoding::Bincode}
 		};
-		while mesgs.len() > 0{
-			let mesg = match mesgs.pop() {
-			    Some(mesg) => {mesg}
-			    None => {break;}
-			};
-			self.messages.push_back(mesg);
+		if let Decoder::Sniffing(_) = self.decoder{
+			self.encoding = encoding;
+			self.decoder = Decoder::new(encoding);
+		}
+		if encoding != self.encoding{
+			return Err(Error::Malformed("Frames of both kinds on one connection".to_string()));
 		}
+		let mesg = match &frame{
+		    tungstenite::Message::Text(text) => {serde_json::from_str(text.as_str()).map_err(|e| Error::Malformed(e.to_string()))?}
+		    _ => {decode(&frame.into_data()).map_err(|e| Error::Malformed(e.to_string()))?}
+		};
+		count(&self.traffic, |t| &t.messages_in, 1);
+		self.messages.push_back(mesg);
+		return Ok(());
 	}
 
 	pub fn keep(&mut self){
 		self.silent_from = time::Instant::now();
 	}
+
+	//the address of the other side, None if the peer isn't connected
+	pub fn peer_addr(&self) -> Option<std::net::SocketAddr>{
+		return self.stream.tcp()?.peer_addr().ok();
+	}
+}
+
+impl<RE_T> mio::event::Source for Peer<RE_T>{
+	fn register(&mut self, registry : &mio::Registry, token : mio::Token, interests : mio::Interest) -> io::Result<()>{
+		match self.stream.tcp_mut(){
+		    Some(s) => {return s.register(registry, token, interests);}
+		    None => {return Err(io::ErrorKind::NotConnected.into());}
+		}
+	}
+
+	fn reregister(&mut self, registry : &mio::Registry, token : mio::Token, interests : mio::Interest) -> io::Result<()>{
+		match self.stream.tcp_mut(){
+		    Some(s) => {return s.reregister(registry, token, interests);}
+		    None => {return Err(io::ErrorKind::NotConnected.into());}
+		}
+	}
+
+	//a closed socket is gone from the poll already
+	fn deregister(&mut self, registry : &mio::Registry) -> io::Result<()>{
+		match self.stream.tcp_mut(){
+		    Some(s) => {return s.deregister(registry);}
+		    None => {return Ok(());}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests{
+	use super::*;
+
+	//the first bytes a bincode client sends when its first packet has the given size
+	fn bincode_start(size : pck_size_t) -> Vec<u8>{
+		let version = ClMessage::Version(ClVersion{version : PROTOCOL_VERSION, oldest : OLDEST_PROTOCOL_VERSION, capabilities : Vec::new()});
+		let mut bytes = bincode::serialize(&size).unwrap();
+		bytes.extend(bincode::serialize(&version).unwrap());
+		return bytes;
+	}
+
+	//the bytes a message takes on the wire in bincode
+	fn bincode_size<T : serde::Serialize>(mesg : &T) -> u64{
+		return bincode::serialized_size(mesg).unwrap() + std::mem::size_of::<pck_size_t>() as u64;
+	}
+
+	#[test]
+	fn json_may_start_with_whitespace_and_a_bom(){
+		let version = b"{\"Version\":{\"version\":3,\"oldest\":3,\"capabilities\":[]}}\n";
+		for start in [&b""[..], b"\r\n", b" \t ", BOM, b"\xEF\xBB\xBF\n  "]{
+			let bytes = [start, &version[..]].concat();
+			assert_eq!(sniff(&bytes), Some(Encoding::Json));
+		}
+		assert_eq!(sniff(b"\n\n\n\n\n\n"), Some(Encoding::Json));
+	}
+
+	#[test]
+	fn sizes_which_look_like_json_are_bincode(){
+		//`{"`, `{ ` and ` {`
+		for size in [0x227B, 0x207B, 0x7B20]{
+			let bytes = bincode_start(size);
+			assert_eq!(sniff(&bytes[..2]), None);
+			assert_eq!(sniff(&bytes), Some(Encoding::Bincode));
+		}
+		assert_eq!(sniff(&bincode_start(12)), Some(Encoding::Bincode));
+	}
+
+	//two peers in one process, like a server and a client in a test, of which only one counts its traffic
+	#[test]
+	fn traffic_is_counted_by_the_peers_holding_it(){
+		let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+		let (server, _) = listener.accept().unwrap();
+		client.set_nonblocking(true).unwrap();
+		server.set_nonblocking(true).unwrap();
+		let mut client : Peer<SeMessage> = Peer::new(&0, Stream::Plain(TcpStream::from_std(client)));
+		let mut server : Peer<ClMessage> = Peer::new(&1, Stream::Plain(TcpStream::from_std(server)));
+		let traffic = Arc::new(Traffic::default());
+		server.traffic = Some(traffic.clone());
+
+		client.send(&ClMessage::Ping(0)).unwrap();
+		let started_at = time::Instant::now();
+		while server.messages.is_empty() && started_at.elapsed() < time::Duration::from_secs(5){
+			server.get_messages().unwrap();
+		}
+		server.send(&SeMessage::Error(SeError{reason : "pong".to_string()})).unwrap();
+		while client.messages.is_empty() && started_at.elapsed() < time::Duration::from_secs(5){
+			client.get_messages().unwrap();
+		}
+		assert_eq!(client.messages.len(), 1);
+
+		let load = |counter : &AtomicU64| counter.load(Ordering::Relaxed);
+		assert_eq!(load(&traffic.messages_in), 1);
+		assert_eq!(load(&traffic.messages_out), 1);
+		assert_eq!(load(&traffic.bytes_in), bincode_size(&ClMessage::Ping(0)));
+		assert_eq!(load(&traffic.bytes_out), bincode_size(&SeMessage::Error(SeError{reason : "pong".to_string()})));
+	}
+
+	#[test]
+	fn too_few_bytes_tell_nothing(){
+		for bytes in [&b""[..], b"{", b"{\"Ver", b"  \n", &BOM[..2], BOM]{
+			assert_eq!(sniff(bytes), None);
+		}
+	}
 }
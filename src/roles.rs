@@ -0,0 +1,71 @@
+use std::fs;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use chat::admin::Role;
+
+//server-side roles of registered users, everyone else is Role::User
+//stored as a text file, one user per line: username:role, the whole file is rewritten on every change
+pub struct Roles{
+	path : PathBuf,
+	roles : HashMap<String, Role>
+}
+
+impl Roles{
+	pub fn load(path : &str) -> Self{
+		let mut roles = Roles{
+			path : PathBuf::from(path),
+			roles : HashMap::new()
+		};
+		let content = match fs::read_to_string(path){
+		    Ok(v) => {v}
+		    Err(_) => {return roles;} //no one has been given a role yet
+		};
+		for (n, line) in content.lines().enumerate(){
+			let (username, role) = match line.split_once(':'){
+			    Some(v) => {v}
+			    None => {
+					log!("Malformed line {} in {}", n + 1, path);
+					continue;
+				}
+			};
+			match Role::parse(role){
+			    Ok(role) => {roles.roles.insert(username.to_string(), role);}
+			    Err(_) => {log!("Malformed line {} in {}", n + 1, path);}
+			}
+		}
+		return roles;
+	}
+
+	pub fn get(&self, username : &str) -> Role{
+		return self.roles.get(username).copied().unwrap_or(Role::User);
+	}
+
+	pub fn set(&mut self, username : &str, role : Role) -> Result<(), String>{
+		let old = self.roles.get(username).copied();
+		if role == Role::User{
+			self.roles.remove(username);
+		}else{
+			self.roles.insert(username.to_string(), role);
+		}
+		if let Err(e) = self.save(){
+			log!("Couldn't save roles to {}: {}", self.path.display(), e);
+			match old{
+			    Some(old) => {self.roles.insert(username.to_string(), old);}
+			    None => {self.roles.remove(username);}
+			}
+			return Err("Couldn't save the role".to_string());
+		}
+		return Ok(());
+	}
+
+	//writes a new file and puts it in place of the old one, so that a failed write loses nothing
+	fn save(&self) -> std::io::Result<()>{
+		let mut users : Vec<(&String, &Role)> = self.roles.iter().collect();
+		users.sort();
+		let content : String = users.iter().map(|(username, role)| format!("{}:{}\n", username, role)).collect();
+		let mut tmp = self.path.clone().into_os_string();
+		tmp.push(".tmp");
+		fs::write(&tmp, content)?;
+		return fs::rename(&tmp, &self.path);
+	}
+}
@@ -1,121 +1,924 @@
+#![allow(clippy::needless_return)]
 extern crate rand;
 extern crate serde;
 extern crate bincode;
 
-use std::sync::{Arc, Mutex};
-use std::io::prelude::*;
-use std::net::TcpListener;
-use std::net::TcpStream;
-use std::collections::HashMap;
+use std::sync::{Arc, mpsc};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::net::IpAddr;
 use std::time;
-mod net;
-use net::*;
+use mio::net::{TcpListener, TcpStream};
+use chat::net::*;
+use chat::tls;
+use chat::ws;
+use chat::admin::{self, Command, Reply, Role};
+use serde_json::json;
+#[macro_use]
+mod log;
+use log::Level;
+mod config;
+use config::{Config, Settings};
+mod control;
+use control::{AdminConn, Control};
+mod roles;
+use roles::Roles;
+mod audit;
+use audit::Audit;
+mod bans;
+use bans::{Ban, Bans, Network, Target};
+mod limits;
+use limits::{Conns, Limits, LoginLimits, Verdict};
+mod metrics;
+use metrics::{Gauges, HttpConn, Metrics};
+mod history;
+use history::History;
+mod accounts;
+use accounts::Accounts;
+mod hasher;
+use hasher::{Done, Hasher, Job};
+
+//how many messages of the lobby a user gets right after joining
+const HISTORY_ON_JOIN : usize = 20;
+
+//the most of messages, and of their bytes, kept for a user whose connection is lost, older ones are dropped
+const MAX_MISSED : usize = 500;
+const MAX_MISSED_SIZE : usize = 1 << 18;
+
+//tokens of the event sources in poll, peers get theirs from FIRST_PEER on
+const LISTENER : mio::Token = mio::Token(0);
+const INPUT : mio::Token = mio::Token(1);
+const WS_LISTENER : mio::Token = mio::Token(2);
+const CONTROL : mio::Token = mio::Token(3);
+const METRICS : mio::Token = mio::Token(4);
+const FIRST_PEER : usize = 5;
+
+//connections over the limits are let in only to be told why they can't stay,
+//past this many of them at once the ones that come are closed without a word
+const MAX_REFUSED : usize = 64;
+
+//a user whose connection is lost, who keeps the name and the rooms till the grace period passes
+struct Session{
+	username : String,
+	lost_at : time::Instant,
+	missed : VecDeque<SeMessage>, //messages which were sent to the user while away
+	missed_size : usize //of the messages in bincode
+}
+
+impl Session{
+	fn keep(&mut self, mesg : &SeMessage){
+		self.missed.push_back(mesg.clone());
+		self.missed_size += missed_size(mesg);
+		while self.missed.len() > MAX_MISSED || self.missed_size > MAX_MISSED_SIZE{
+			match self.missed.pop_front(){
+			    Some(old) => {self.missed_size -= missed_size(&old)}
+			    None => {break;}
+			}
+		}
+	}
+}
+
+fn missed_size(mesg : &SeMessage) -> usize{
+	return bincode::serialized_size(mesg).map(|v| v as usize).unwrap_or(0);
+}
+
+//who gives an admin command
+enum Actor{
+	Console, //the terminal of the server
+	Socket, //chatctl, through the admin socket
+	User{username : String, role : Role}
+}
+
+impl Actor{
+	fn name(&self) -> &str{
+		match self{
+		    Actor::Console => {"console"}
+		    Actor::Socket => {"admin socket"}
+		    Actor::User{username, ..} => {username}
+		}
+	}
+}
 
 struct Server{
 	name_map : HashMap<String, Token>, //a map from username to token
+	rooms : HashMap<String, HashSet<Token>>, //a map from room name to tokens of its members
+	history : History,
+	accounts : Accounts,
+	roles : Roles,
+	audit : Audit,
+	muted : HashMap<String, Option<time::Instant>>, //usernames which may not post, till the time if it is set
+	slow_rooms : HashMap<String, time::Duration>, //rooms where each user may only post once in the duration
+	last_posts : HashMap<(Token, String), time::Instant>, //when a user has last posted in a room
+	limits : Limits, //how fast peers may send
+	login_limits : LoginLimits, //how fast passwords may be guessed
+	hasher : Hasher, //hashes and checks the passwords of peers, it wakes poll with INPUT once it is done
+	hashing : HashSet<Token>, //peers whose passwords the hasher is busy with
+	refused : HashMap<Token, String>, //peers over the connection limits, who get the reason on their first message
+	conns_in_use : Conns, //the connected peers which count against the limits, the refused ones don't
+	lost_sessions : Conns, //the suspended sessions, each address may keep as many as it may have connections
+	bans : Bans, //users and addresses which may not join
+	config : Config,
+	tls : Option<Arc<rustls::ServerConfig>>, //peers are served over TLS if it is set
 	peers : HashMap<Token, Peer<ClMessage>>, //key is token
-	rx : std::sync::mpsc::Receiver<TcpStream>, //receiver of stream from listening_thread
-	input_rx : std::sync::mpsc::Receiver<String>,
+	suspended : HashMap<Token, Session>, //users whose connection is lost, key is token
+	poll : mio::Poll, //reports readiness of the listener, the peers, and input from input_thread
+	listener : TcpListener,
+	ws_listener : Option<TcpListener>, //for clients connecting with WebSocket, if they are served
+	conns : HashMap<mio::Token, Token>, //a map from the token of a peer's connection in poll to its token
+	next_conn : usize,
+	control : Option<Control>, //the admin socket, if the server has one
+	admin_conns : HashMap<mio::Token, AdminConn>, //connections to the admin socket, they take tokens as peers do
+	metrics_listener : Option<TcpListener>, //serves the metrics over HTTP, if it is set
+	metrics_conns : HashMap<mio::Token, HttpConn>, //its connections, which take tokens as peers do
+	metrics : Metrics,
+	input_rx : mpsc::Receiver<String>, //input_thread wakes poll with INPUT after sending, nothing comes in daemon mode
 	should_stop : bool
 }
 
 impl Server{
-	pub fn new(rx : std::sync::mpsc::Receiver<TcpStream>, input_rx : std::sync::mpsc::Receiver<String>,) -> Self{
-		let server = Server{
+	#[allow(clippy::too_many_arguments)]
+	pub fn new(poll : mio::Poll, waker : Arc<mio::Waker>, mut listener : TcpListener, mut ws_listener : Option<TcpListener>,
+		mut control : Option<Control>, mut metrics_listener : Option<TcpListener>, input_rx : mpsc::Receiver<String>,
+		tls : Option<Arc<rustls::ServerConfig>>, config : Config) -> io::Result<Self>{
+		poll.registry().register(&mut listener, LISTENER, mio::Interest::READABLE)?;
+		if let Some(ws_listener) = &mut ws_listener{
+			poll.registry().register(ws_listener, WS_LISTENER, mio::Interest::READABLE)?;
+		}
+		if let Some(control) = &mut control{
+			poll.registry().register(&mut control.listener, CONTROL, mio::Interest::READABLE)?;
+		}
+		if let Some(metrics_listener) = &mut metrics_listener{
+			poll.registry().register(metrics_listener, METRICS, mio::Interest::READABLE)?;
+		}
+		let mut server = Server{
 			input_rx,
-			rx,
+			poll,
+			listener,
+			ws_listener,
+			conns : HashMap::new(),
+			next_conn : FIRST_PEER,
+			control,
+			admin_conns : HashMap::new(),
+			metrics_listener,
+			metrics_conns : HashMap::new(),
+			metrics : Metrics::new(),
+			tls,
 			peers : HashMap::new(),
+			suspended : HashMap::new(),
 			should_stop : false,
-		 	name_map : HashMap::new()};
-		return server;
+			name_map : HashMap::new(),
+			rooms : HashMap::new(),
+			history : History::new(&config.history_dir),
+			accounts : Accounts::load(&config.accounts_file),
+			roles : Roles::load(&config.roles_file),
+			audit : Audit::new(&config.audit_log),
+			muted : HashMap::new(),
+			slow_rooms : HashMap::new(),
+			last_posts : HashMap::new(),
+			refused : HashMap::new(),
+			conns_in_use : Conns::new(),
+			lost_sessions : Conns::new(),
+			limits : Limits::new(config.rate_limit, config.rate_burst, config.ip_rate_limit, config.ip_rate_burst, config.flood_kick),
+			login_limits : LoginLimits::new(),
+			hasher : Hasher::start(waker),
+			hashing : HashSet::new(),
+			bans : Bans::load(&config.bans_file),
+			config};
+		server.rooms.insert(LOBBY.to_string(), HashSet::new());
+		return Ok(server);
 	}
 
-	pub fn get_messages(&mut self){
-		for (_, peer) in self.peers.iter_mut(){
-			peer.get_messages();
+	//checks whether a peer may join under the username, disconnecting it if not
+	fn check_name(&mut self, token : Token, username : &str) -> bool{
+		if self.name_map.len() >= self.config.max_users{
+			self.disconnect(token, "Server is full");
+			return false;
+		}
+		if let Err(reason) = self.name_is_free(username){
+			self.disconnect(token, reason);
+			return false;
+		}
+		if let Some(ban) = self.bans.of_user(username){
+			let reason = ban.message();
+			self.disconnect(token, &reason);
+			return false;
+		}
+		match self.peers.get(&token){
+		    Some(peer) => {
+				match peer.state {
+				    PeerState::AwaitingAuth => {return true;}
+				    PeerState::Chatting => {return false;} //an attempt to authorize while authorized
+				    PeerState::Quitting => {return true;}
+				}
+			}
+		    None => {
+				invalid_tok();
+				return false;
+			}
+		}
+	}
+
+	//whether the username is one a user may take, the reason why not otherwise
+	fn name_is_free(&self, username : &str) -> Result<(), &'static str>{
+		if !username.chars().all(char::is_alphanumeric){
+			return Err("Username contains illegal character");
+		}
+		if username.len() > MAX_NAME_LEN{
+			return Err("Username is too long");
+		}
+		if self.name_map.contains_key(username){
+			return Err("Username is in use");
+		}
+		return Ok(());
+	}
+
+	//changes the username of a user who has joined
+	fn rename(&mut self, token : Token, mesg : &ClRename){
+		if !self.verify(token, mesg.token){
+			return;
+		}
+		let old = match self.peers.get(&token){
+		    Some(peer) if matches!(peer.state, PeerState::Chatting) => {peer.username.clone()}
+		    _ => {return;}
+		};
+		if mesg.username.is_empty(){
+			self.send_error(token, "Username is empty");
+			return;
+		}
+		if let Err(reason) = self.name_is_free(&mesg.username){
+			self.send_error(token, reason);
+			return;
+		}
+		if self.accounts.is_registered(&mesg.username) || self.role(&mesg.username) > Role::User{
+			self.send_error(token, "Username is registered, log in with a password to use it");
+			return;
+		}
+		if self.bans.of_user(&mesg.username).is_some(){
+			self.send_error(token, "Username is banned");
+			return;
+		}
+		//a mute follows the user
+		if let Some(until) = self.muted.remove(&old){
+			self.muted.insert(mesg.username.clone(), until);
+		}
+		self.log_peer(Level::Info, "rename", token, json!({"old" : old, "new" : mesg.username}));
+		self.name_map.remove(&old);
+		self.name_map.insert(mesg.username.clone(), token);
+		if let Some(peer) = self.peers.get_mut(&token){
+			peer.username = mesg.username.clone();
+			deliver(peer, &SeMessage::Renamed(SeRenamed{old : old.clone(), new : mesg.username.clone()}));
+		}
+		self.broadcast(&format!("{} is now {}", old, mesg.username));
+	}
+
+	//chooses the newest version of the protocol both the peer and the server speak, before the peer joins
+	fn negotiate(&mut self, token : Token, mesg : &ClVersion){
+		match self.peers.get(&token){
+		    Some(peer) if matches!(peer.state, PeerState::AwaitingAuth) => {}
+		    Some(_) => {return;} //the version can't change after joining
+		    None => {
+				invalid_tok();
+				return;
+			}
+		}
+		let version = mesg.version.min(PROTOCOL_VERSION);
+		if version < mesg.oldest.max(OLDEST_PROTOCOL_VERSION){
+			let reason = format!("Incompatible protocol version: the client speaks {} to {}, the server speaks {} to {}",
+				mesg.oldest, mesg.version, OLDEST_PROTOCOL_VERSION, PROTOCOL_VERSION);
+			self.disconnect(token, &reason);
+			return;
+		}
+		if let Some(peer) = self.peers.get_mut(&token){
+			peer.version = version;
+			peer.capabilities = mesg.capabilities.clone();
+			let capabilities = CAPABILITIES.iter().map(|c| c.to_string()).collect();
+			deliver(peer, &SeMessage::Version(SeVersion{version, capabilities}));
 		}
 	}
 
 	fn authorize(&mut self, token : Token, mesg : &ClHello){
-		if !mesg.username.chars().all(char::is_alphanumeric){
-			self.disconnect(token, &"Username contains illegal character".to_string());
+		if !self.check_name(token, &mesg.username){
+			return;
+		}
+		if self.accounts.is_registered(&mesg.username){
+			self.disconnect(token, "Username is registered, log in with a password to use it");
 			return;
 		}
-		if self.name_map.contains_key(&mesg.username){
-			self.disconnect(token, &"Username is in use".to_string());
+		//the usernames of privileged users may only be taken by logging in, not by guests
+		if self.role(&mesg.username) > Role::User{
+			self.disconnect(token, "Username has a role on this server, log in with a password to use it");
 			return;
 		}
+		self.admit(token, &mesg.username);
+	}
 
+	//the role of a username, the configuration raises the ones it names over what the roles file says
+	fn role(&self, username : &str) -> Role{
+		let mut role = self.roles.get(username);
+		if self.config.admins.iter().any(|admin| admin == username){
+			role = role.max(Role::Admin);
+		}
+		if self.config.owner.as_deref() == Some(username){
+			role = Role::Owner;
+		}
+		return role;
+	}
 
+	fn login(&mut self, token : Token, mesg : &ClCredentials){
+		if !self.check_name(token, &mesg.username){
+			return;
+		}
+		if !self.accounts.is_registered(&mesg.username){
+			self.disconnect(token, "No account with this username, register it first");
+			return;
+		}
+		//a password is only checked as often as it isn't guessed
+		let addr = self.addr_of(token);
+		if !self.login_limits.allows(&mesg.username, addr){
+			self.log_peer(Level::Warn, "login_throttled", token, json!({"username" : mesg.username}));
+			self.disconnect(token, "Too many failed logins, try again later");
+			return;
+		}
+		let hash = match self.accounts.hash_of(&mesg.username){
+		    Some(v) => {v.to_string()}
+		    None => {return;}
+		};
+		//the peer is let in once the hasher has checked the password
+		if self.hashing.insert(token){
+			self.hasher.send(Job::Check{token, username : mesg.username.clone(), password : mesg.password.clone(), hash});
+		}
+	}
+
+	//the address a peer has connected from
+	fn addr_of(&self, token : Token) -> Option<IpAddr>{
+		return self.peers.get(&token).and_then(|peer| peer.peer_addr()).map(|addr| addr.ip());
+	}
+
+	//goes on with the logins and registrations of the peers whose passwords the hasher is done with
+	fn process_hashed(&mut self){
+		for done in self.hasher.finished(){
+			let token = match &done{
+			    Done::Checked{token, ..} | Done::Hashed{token, ..} => {*token}
+			};
+			//the peer may have gone, or joined otherwise, in the meantime
+			let waiting = self.hashing.remove(&token);
+			if !waiting || !matches!(self.peers.get(&token).map(|peer| &peer.state), Some(PeerState::AwaitingAuth)){
+				continue;
+			}
+			match done{
+			    Done::Checked{token, username, matches} => {self.logged_in(token, &username, matches)}
+			    Done::Hashed{token, username, hash} => {self.signed_up(token, &username, hash)}
+			}
+		}
+	}
+
+	fn logged_in(&mut self, token : Token, username : &str, matches : bool){
+		if !matches{
+			let addr = self.addr_of(token);
+			self.login_limits.failed(username, addr);
+			self.log_peer(Level::Warn, "login_failed", token, json!({"username" : username}));
+			self.disconnect(token, "Wrong password");
+			return;
+		}
+		//someone else may have taken the name while the password was checked
+		if !self.check_name(token, username){
+			return;
+		}
+		self.admit(token, username);
+	}
+
+	fn sign_up(&mut self, token : Token, mesg : &ClCredentials){
+		if !self.check_name(token, &mesg.username){
+			return;
+		}
+		if mesg.username.is_empty(){
+			self.disconnect(token, "Username is empty");
+			return;
+		}
+		if mesg.password.is_empty(){
+			self.disconnect(token, "Password is empty");
+			return;
+		}
+		//or whoever comes first would get the role of the user
+		if self.role(&mesg.username) > Role::User{
+			self.disconnect(token, "Username has a role on this server, its account is created by the server's admins");
+			return;
+		}
+		if self.accounts.is_registered(&mesg.username){
+			self.disconnect(token, "Username is already registered");
+			return;
+		}
+		if self.hashing.contains(&token){
+			return;
+		}
+		if !self.login_limits.sign_up(self.addr_of(token)){
+			self.log_peer(Level::Warn, "register_throttled", token, json!({"username" : mesg.username}));
+			self.disconnect(token, "Too many accounts registered from your address, try again later");
+			return;
+		}
+		//the account is made once the hasher has hashed the password
+		self.hashing.insert(token);
+		self.hasher.send(Job::Hash{token, username : mesg.username.clone(), password : mesg.password.clone()});
+	}
+
+	fn signed_up(&mut self, token : Token, username : &str, hash : Result<String, String>){
+		let hash = match hash{
+		    Ok(v) => {v}
+		    Err(reason) => {
+				self.disconnect(token, &reason);
+				return;
+			}
+		};
+		if !self.check_name(token, username){
+			return;
+		}
+		if let Err(reason) = self.accounts.add(username, hash){
+			self.disconnect(token, &reason);
+			return;
+		}
+		self.log_peer(Level::Info, "register", token, json!({"username" : username}));
+		self.admit(token, username);
+	}
+
+	//lets a peer, which has passed the checks, into the chat
+	fn admit(&mut self, token : Token, username : &str){
 		match self.peers.get_mut(&token){
 		    Some(peer) => {
-				match peer.state {
-				    PeerState::AwaitingAuth => {}
-				    PeerState::Chatting => {return;} //an attempt to authorize while authorized
-				    PeerState::Quitting => {}
-				}
 				let response = SeMessage::Hello(SeHello{token});
-				peer.send(&response);
-				self.name_map.insert(mesg.username.clone(), token);
+				deliver(peer, &response);
+				let mesgs = self.history.last(LOBBY, HISTORY_ON_JOIN);
+				if !mesgs.is_empty(){
+					let history = fit_history(peer, LOBBY, mesgs);
+					deliver(peer, &history);
+				}
+				if let Some(motd) = &self.config.motd{
+					deliver(peer, &SeMessage::Mesg(SeMesg{room : String::new(), mesg : motd.clone()}));
+				}
+				self.name_map.insert(username.to_string(), token);
 				peer.state = PeerState::Chatting;
 				peer.token = token;
-				peer.username = mesg.username.clone();
+				peer.username = username.to_string();
 			}
 		    None => {
 				invalid_tok();
 				return;
 			}
 		}
-		self.broadcast(&format!("{} joined", mesg.username));
+		let registered = self.accounts.is_registered(username);
+		self.log_peer(Level::Info, "join", token, json!({"registered" : registered}));
+		self.broadcast(&format!("{} joined", username));
+		self.enter_room(token, LOBBY);
 	}
 
-	fn verify(&self, token : &Token) -> bool{
-		match self.peers.get(token){
-		    Some(_) => {
-				return true;
-			}
-		    None => {
-				println!("Counldn't find user with token {}", token);
-				return false;
-			}
+	//checks the token in a request of the peer against the token of the connection it came on
+	//who the user is is only ever taken from the connection, a mismatch is refused and logged
+	fn verify(&mut self, token : Token, claimed : Token) -> bool{
+		if !self.peers.contains_key(&token){
+			invalid_tok();
+			return false;
+		}
+		if claimed == token{
+			return true;
+		}
+		//the user whose token it is, if there is one
+		let victim = match (self.peers.get(&claimed), self.suspended.get(&claimed)){
+		    (Some(peer), _) => {Some(peer.username.clone())}
+		    (None, Some(session)) => {Some(session.username.clone())}
+		    (None, None) => {None}
 		};
+		self.log_peer(Level::Warn, "impersonation", token, json!({"claimed" : claimed, "victim" : victim}));
+		self.send_error(token, "Token doesn't match the connection");
+		return false;
+	}
 
+	//shows what is said on the terminal of the server, a daemon has none
+	//what users post is kept out of the output as it is kept out of the log, unless log_bodies allows it
+	fn echo(&self, line : &str){
+		if !self.config.daemon && self.config.log_bodies{
+			println!("{}", line);
+		}
 	}
 
-	fn broadcast(&mut self, mesg : &String){
-		println!("{}", mesg);
+	//sends a message to everyone on the server
+	fn broadcast(&mut self, mesg : &str){
+		self.echo(mesg);
+		self.metrics.broadcasts += 1;
 		let mesg = SeMessage::Mesg(
-			SeMesg{ mesg : mesg.clone() });
+			SeMesg{ room : String::new(), mesg : mesg.to_string() });
+		//a peer which hasn't joined yet expects the answer to its hello first
 		for (_, peer) in self.peers.iter_mut(){
-			peer.send(&mesg);
+			if matches!(peer.state, PeerState::Chatting){
+				deliver(peer, &mesg);
+			}
+		}
+		for (_, session) in self.suspended.iter_mut(){
+			session.keep(&mesg);
+		}
+	}
+
+	//sends a message to a user, or keeps it for him if his connection is lost
+	fn send_to(&mut self, token : Token, mesg : &SeMessage){
+		if let Some(peer) = self.peers.get_mut(&token){
+			deliver(peer, mesg);
+		}else if let Some(session) = self.suspended.get_mut(&token){
+			session.keep(mesg);
+		}
+	}
+
+	//sends a message to the members of a room only
+	fn broadcast_room(&mut self, room : &str, mesg : &str){
+		let members = match self.rooms.get(room){
+		    Some(v) => {v}
+		    None => {return;}
+		};
+		self.echo(&format!("[{}] {}", room, mesg));
+		self.metrics.broadcasts += 1;
+		let mesg = SeMessage::Mesg(
+			SeMesg{ room : room.to_string(), mesg : mesg.to_string() });
+		let members : Vec<Token> = members.iter().cloned().collect();
+		for token in members{
+			self.send_to(token, &mesg);
+		}
+	}
+
+	fn send_error(&mut self, token : Token, reason : &str){
+		let mesg = SeMessage::Error(SeError{reason : reason.to_string()});
+		match self.peers.get_mut(&token){
+			Some(p) => {deliver(p, &mesg)}
+			None => {invalid_tok()}
+		}
+	}
+
+	//logs an event about a peer, with its token, address and username along with the fields
+	fn log_peer(&self, level : Level, event : &str, token : Token, mut fields : serde_json::Value){
+		if let (Some(peer), Some(map)) = (self.peers.get(&token), fields.as_object_mut()){
+			map.insert("token".to_string(), json!(token));
+			map.insert("addr".to_string(), json!(peer.peer_addr().map(|addr| addr.to_string())));
+			if !peer.username.is_empty(){
+				map.insert("username".to_string(), json!(peer.username));
+			}
+		}
+		log::event(level, event, fields);
+	}
+
+	//logs a message a user has posted, with what it says unless the log is to keep that private
+	fn log_post(&self, token : Token, event : &str, mut fields : serde_json::Value, mesg : &str){
+		fields["length"] = json!(mesg.chars().count());
+		if self.config.log_bodies{
+			fields["body"] = json!(mesg);
+		}
+		self.log_peer(Level::Info, event, token, fields);
+	}
+
+	//refuses a message longer than the server allows, or from a muted user
+	fn check_post(&mut self, token : Token, mesg : &str) -> bool{
+		let username = match self.peers.get(&token){
+		    Some(peer) => {peer.username.clone()}
+		    None => {return false;}
+		};
+		match self.muted.get(&username){
+		    Some(None) => {
+				self.send_error(token, "You are muted");
+				return false;
+			}
+		    Some(Some(until)) if *until > time::Instant::now() => {
+				let left = until.duration_since(time::Instant::now()).as_secs() + 1;
+				self.send_error(token, &format!("You are muted for {} more", admin::format_duration(left)));
+				return false;
+			}
+		    Some(Some(_)) => {self.muted.remove(&username);} //the mute is over
+		    None => {}
+		}
+		if mesg.chars().count() > self.config.max_message_len{
+			self.send_error(token, &format!("Message is longer than {} characters", self.config.max_message_len));
+			return false;
+		}
+		return true;
+	}
+
+	fn usernames(&self, tokens : &HashSet<Token>) -> Vec<String>{
+		let mut users : Vec<String> = Vec::with_capacity(tokens.len());
+		for token in tokens.iter(){
+			if let Some(peer) = self.peers.get(token){
+				users.push(peer.username.clone());
+			}else if let Some(session) = self.suspended.get(token){
+				users.push(session.username.clone());
+			}
 		}
+		users.sort();
+		return users;
 	}
 
 	fn send_info(&mut self, token : Token){
-		let mut users : Vec<String> = Vec::new();
-		users.reserve(self.name_map.len());
-		for (username, _) in self.name_map.iter_mut(){
+		let mut users : Vec<String> = Vec::with_capacity(self.name_map.len());
+		for (username, _) in self.name_map.iter(){
 			users.push(username.clone());
 		}
-		let mesg = SeMessage::Info(SeInfo{users});
+		users.sort();
+		let mut rooms : Vec<RoomInfo> = Vec::with_capacity(self.rooms.len());
+		for (name, members) in self.rooms.iter(){
+			rooms.push(RoomInfo{name : name.clone(), users : self.usernames(members)});
+		}
+		rooms.sort_by(|a, b| a.name.cmp(&b.name));
+		let mesg = SeMessage::Info(SeInfo{users, rooms});
 		match self.peers.get_mut(&token){
-			Some(p) => {p.send(&mesg)}
+			Some(p) => {deliver(p, &mesg)}
 			None => {invalid_tok()}
 		}
 	}
 
-	fn disconnect(&mut self, token : Token, reason : &String){
-		let username = match self.peers.get(&token){
-		    Some(v) => {v.username.clone()}
-		    None => {"".to_string()}
+	fn process_room(&mut self, token : Token, mesg : &ClRoom){
+		if !self.verify(token, mesg.token){
+			return;
+		}
+		if mesg.room.is_empty() || !mesg.room.chars().all(char::is_alphanumeric){
+			self.send_error(token, "Room name must be alphanumeric");
+			return;
+		}
+		match mesg.action{
+		    RoomAction::Create => {
+				if mesg.room.len() > MAX_NAME_LEN{
+					self.send_error(token, &format!("Room name is longer than {} bytes", MAX_NAME_LEN));
+					return;
+				}
+				if self.rooms.contains_key(&mesg.room){
+					self.send_error(token, &format!("Room {} already exists", mesg.room));
+					return;
+				}
+				self.rooms.insert(mesg.room.clone(), HashSet::new());
+				self.enter_room(token, &mesg.room);
+			}
+		    RoomAction::Enter => {
+				if !self.rooms.contains_key(&mesg.room){
+					self.send_error(token, &format!("No such room: {}", mesg.room));
+					return;
+				}
+				self.enter_room(token, &mesg.room);
+			}
+		    RoomAction::Leave => {
+				let is_member = match self.rooms.get(&mesg.room){
+				    Some(members) => {members.contains(&token)}
+				    None => {false}
+				};
+				if !is_member{
+					self.send_error(token, &format!("You are not in room {}", mesg.room));
+					return;
+				}
+				self.leave_room(token, &mesg.room);
+			}
+		}
+	}
+
+	fn send_history(&mut self, token : Token, mesg : &ClHistory){
+		if !self.verify(token, mesg.token){
+			return;
+		}
+		let is_member = match self.rooms.get(&mesg.room){
+		    Some(members) => {members.contains(&token)}
+		    None => {false}
 		};
-		let mesg = SeMessage::UQuit(SeUQuit{reason : reason.clone()});
+		if !is_member{
+			self.send_error(token, &format!("You are not in room {}", mesg.room));
+			return;
+		}
+		let mesgs = self.history.last(&mesg.room, mesg.count.min(MAX_HISTORY));
 		match self.peers.get_mut(&token){
-		    Some(p) => {p.send(&mesg)}
-		    None => {}
+			Some(p) => {
+				let history = fit_history(p, &mesg.room, mesgs);
+				deliver(p, &history);
+			}
+			None => {invalid_tok()}
+		}
+	}
+
+	fn send_direct(&mut self, token : Token, mesg : &ClDirect){
+		if !self.verify(token, mesg.token) || !self.check_post(token, &mesg.mesg){
+			return;
+		}
+		let to = match self.name_map.get(&mesg.to){
+		    Some(v) => {*v}
+		    None => {
+				self.send_error(token, &format!("No such user: {}", mesg.to));
+				return;
+			}
+		};
+		let from = match self.peers.get(&token){
+		    Some(peer) => {peer.username.clone()}
+		    None => {
+				invalid_tok();
+				return;
+			}
+		};
+		self.log_post(token, "direct", json!({"to" : mesg.to}), &mesg.mesg);
+		let direct = SeMessage::Direct(SeDirect{from, to : mesg.to.clone(), mesg : mesg.mesg.clone()});
+		self.send_to(to, &direct);
+		//echo back to the sender, unless he messages himself
+		if to != token{
+			if let Some(peer) = self.peers.get_mut(&token){
+				deliver(peer, &direct);
+			}
+		}
+	}
+
+	fn enter_room(&mut self, token : Token, room : &str){
+		let entered = match self.rooms.get_mut(room){
+		    Some(members) => {members.insert(token)}
+		    None => {return;}
+		};
+		let username = match self.peers.get_mut(&token){
+		    Some(peer) => {
+				deliver(peer, &SeMessage::Room(SeRoom{room : room.to_string(), action : RoomAction::Enter}));
+				peer.username.clone()
+			}
+		    None => {return;}
+		};
+		//entering a room once more only confirms the membership
+		if entered{
+			self.broadcast_room(room, &format!("{} entered the room", username));
+		}
+	}
+
+	fn leave_room(&mut self, token : Token, room : &str){
+		let username = match self.peers.get_mut(&token){
+		    Some(peer) => {
+				deliver(peer, &SeMessage::Room(SeRoom{room : room.to_string(), action : RoomAction::Leave}));
+				peer.username.clone()
+			}
+		    None => {String::new()}
+		};
+		self.remove_from_room(token, room);
+		self.broadcast_room(room, &format!("{} left the room", username));
+	}
+
+	//removes a member from the room, and the room itself if it has become empty
+	fn remove_from_room(&mut self, token : Token, room : &str){
+		let is_empty = match self.rooms.get_mut(room){
+		    Some(members) => {
+				members.remove(&token);
+				members.is_empty()
+			}
+		    None => {return;}
+		};
+		self.last_posts.remove(&(token, room.to_string()));
+		if is_empty && room != LOBBY{
+			self.rooms.remove(room);
+			self.slow_rooms.remove(room);
+		}
+	}
+
+	//refuses a message posted too soon after the user's last one in a room in slow mode
+	//moderators and those above them aren't slowed down
+	fn check_pace(&mut self, token : Token, room : &str) -> bool{
+		let interval = match self.slow_rooms.get(room){
+		    Some(v) => {*v}
+		    None => {self.config.slow_mode}
+		};
+		if interval.is_zero(){
+			return true;
+		}
+		let username = match self.peers.get(&token){
+		    Some(peer) => {peer.username.clone()}
+		    None => {return false;}
+		};
+		if self.role(&username) >= Role::Moderator{
+			return true;
+		}
+		let key = (token, room.to_string());
+		if let Some(last) = self.last_posts.get(&key){
+			let waited = last.elapsed();
+			if waited < interval{
+				let left = (interval - waited).as_secs() + 1;
+				self.send_error(token, &format!("Slow mode is on in {}, you may post again in {}", room, admin::format_duration(left)));
+				return false;
+			}
+		}
+		self.last_posts.insert(key, time::Instant::now());
+		return true;
+	}
+
+	fn disconnect(&mut self, token : Token, reason : &str){
+		//a suspended user has no peer, but still holds the name
+		let username = match (self.peers.get(&token), self.suspended.get(&token)){
+		    (Some(peer), _) => {peer.username.clone()}
+		    (None, Some(session)) => {session.username.clone()}
+		    (None, None) => {"".to_string()}
+		};
+		let mesg = SeMessage::UQuit(SeUQuit{reason : reason.to_string()});
+		if let Some(p) = self.peers.get_mut(&token){
+			deliver(p, &mesg);
+		}
+		let rooms : Vec<String> = self.rooms.keys().cloned().collect();
+		for room in rooms{
+			self.remove_from_room(token, &room);
+		}
+		if self.name_map.get(&username) == Some(&token){
+			self.name_map.remove(&username);
+		}
+		if let Some(mut peer) = self.peers.remove(&token){
+			let _ = self.poll.registry().deregister(&mut peer);
+		}
+		self.conns.retain(|_, t| *t != token);
+		self.suspended.remove(&token);
+		self.limits.forget(token);
+		self.refused.remove(&token);
+		self.conns_in_use.remove(token);
+		self.lost_sessions.remove(token);
+		self.hashing.remove(&token);
+	}
+
+	//whether the session of a peer whose connection is lost may be kept, an address may only keep so many,
+	//so that one can't take every name by joining and dropping the connection over and over
+	fn may_suspend(&self, token : Token) -> bool{
+		match self.conns_in_use.addr_of(token){
+		    Some(addr) => {return self.lost_sessions.from(addr) < self.config.max_connections_per_ip;}
+		    None => {return true;}
+		}
+	}
+
+	//keeps the name, the rooms and the messages of a user whose connection is lost, for him to resume
+	fn suspend(&mut self, token : Token){
+		let mut peer = match self.peers.remove(&token){
+		    Some(v) => {v}
+		    None => {return;}
+		};
+		let _ = self.poll.registry().deregister(&mut peer);
+		self.conns.retain(|_, t| *t != token);
+		self.lost_sessions.add(token, self.conns_in_use.addr_of(token));
+		self.conns_in_use.remove(token);
+		let session = Session{username : peer.username, lost_at : time::Instant::now(), missed : VecDeque::new(), missed_size : 0};
+		self.suspended.insert(token, session);
+	}
+
+	//gives a new connection the session of a lost one
+	fn resume(&mut self, token : Token, old : Token){
+		match self.peers.get(&token){
+		    Some(peer) if matches!(peer.state, PeerState::AwaitingAuth) => {}
+		    Some(_) => {return;} //an attempt to resume while authorized
+		    None => {
+				invalid_tok();
+				return;
+			}
+		}
+		//a connected user's session is not taken over, whoever knows the token
+		if self.peers.contains_key(&old){
+			self.disconnect(token, "Session is still connected");
+			return;
+		}
+		let session = match self.suspended.remove(&old){
+		    Some(v) => {v}
+		    None => {
+				self.disconnect(token, "No session to resume");
+				return;
+			}
+		};
+		let mut peer = match self.peers.remove(&token){
+		    Some(v) => {v}
+		    None => {return;}
+		};
+		for t in self.conns.values_mut(){
+			if *t == token{
+				*t = old;
+			}
+		}
+		self.limits.forget(token);
+		self.lost_sessions.remove(old);
+		self.conns_in_use.rename(token, old);
+		peer.token = old;
+		peer.username = session.username.clone();
+		peer.state = PeerState::Chatting;
+		deliver(&mut peer, &SeMessage::Hello(SeHello{token : old}));
+		let mut rooms : Vec<String> = Vec::new();
+		for (name, members) in self.rooms.iter(){
+			if members.contains(&old){
+				rooms.push(name.clone());
+			}
+		}
+		//the lobby goes first, so that the rooms the user has entered stay on top of it
+		rooms.sort_by_key(|room| (room != LOBBY, room.clone()));
+		for room in rooms{
+			deliver(&mut peer, &SeMessage::Room(SeRoom{room, action : RoomAction::Enter}));
+		}
+		for mesg in session.missed.iter(){
+			deliver(&mut peer, mesg);
+		}
+		self.peers.insert(old, peer);
+		self.log_peer(Level::Info, "resume", old, json!({"connection" : token}));
+		self.broadcast(&format!("{} is back", session.username));
+	}
+
+	//lets go of the sessions which weren't resumed in time
+	fn expire_sessions(&mut self){
+		let mut expired = Vec::new();
+		for (token, session) in self.suspended.iter(){
+			if session.lost_at.elapsed() > self.config.resume_grace{
+				expired.push((*token, session.username.clone()));
+			}
+		}
+		for (token, username) in expired{
+			log::event(Level::Info, "leave", json!({"token" : token, "username" : username, "reason" : "session expired"}));
+			self.disconnect(token, "");
+			self.broadcast(&format!("{} left (connection lost)", username));
 		}
-		self.name_map.remove(&username);
-		self.peers.remove(&token);
 	}
 
 	fn keep_peer(&mut self, token : Token){
@@ -125,37 +928,144 @@ impl Server{
 		}
 	}
 
+	//drops a message of a peer which sends faster than it may, warning it the first time,
+	//and disconnects it if it goes on
+	fn check_rate(&mut self, token : Token) -> bool{
+		let (addr, username, joined) = match self.peers.get(&token){
+		    Some(peer) => {(peer.peer_addr().map(|addr| addr.ip()), peer.username.clone(), matches!(peer.state, PeerState::Chatting))}
+		    None => {return false;} //the peer has been disconnected by an earlier message
+		};
+		match self.limits.check(token, addr){
+		    Verdict::Pass => {return true;}
+		    Verdict::Warn => {
+				self.send_error(token, "You are sending messages too fast, they are dropped");
+				return false;
+			}
+		    Verdict::Drop => {return false;}
+		    Verdict::Disconnect => {
+				self.log_peer(Level::Warn, "flood", token, json!({}));
+				self.disconnect(token, "Flooding");
+				if joined{
+					self.broadcast(&format!("{} was disconnected for flooding", username));
+				}
+				return false;
+			}
+		}
+	}
+
 	fn process_message(&mut self, token : Token, mesg : ClMessage){
+		//the refusal is sent once the peer has spoken, so that it comes in the encoding the peer understands
+		if let Some(reason) = self.refused.remove(&token){
+			self.disconnect(token, &reason);
+			return;
+		}
+		if !self.check_rate(token){
+			return;
+		}
+		//a client has to tell its version before anything else
+		let unversioned = match self.peers.get(&token){
+		    Some(peer) => {peer.version < OLDEST_PROTOCOL_VERSION}
+		    None => {false}
+		};
+		if unversioned && !matches!(mesg, ClMessage::Version(_)){
+			let reason = format!("Incompatible protocol version: the client speaks {}, the server speaks {} to {}",
+				UNVERSIONED, OLDEST_PROTOCOL_VERSION, PROTOCOL_VERSION);
+			self.disconnect(token, &reason);
+			return;
+		}
+		//nor may it send what its version doesn't have
+		let version = self.peers.get(&token).map(|peer| peer.version).unwrap_or(PROTOCOL_VERSION);
+		if !unversioned && mesg.since() > version{
+			let (username, joined) = match self.peers.get(&token){
+			    Some(peer) => {(peer.username.clone(), matches!(peer.state, PeerState::Chatting))}
+			    None => {return;}
+			};
+			let error = format!("a message of version {} over a connection of version {}", mesg.since(), version);
+			self.log_peer(Level::Warn, "protocol_error", token, json!({"error" : error}));
+			self.disconnect(token, &format!("Protocol error: {}", error));
+			if joined{
+				self.broadcast(&format!("{} was disconnected for a protocol error", username));
+			}
+			return;
+		}
 		self.keep_peer(token); //reset last activity time upon recieving a message from peer
 		match mesg {
 		    ClMessage::Hello(m) => {self.authorize(token, &m)}
 		    ClMessage::Mesg(m) => {
-				if !self.verify(&m.token){
+				if !self.verify(token, m.token) || !self.check_post(token, &m.mesg){
 					return;
 				}
-				self.broadcast(&(m.username.clone() + ": " + m.mesg.as_str()));
-			}
-		    ClMessage::IWantInfo(m) => {self.send_info(m)}
-		    ClMessage::IQuit(m) => {
-				let username = match self.peers.get(&m){
+				let is_member = match self.rooms.get(&m.room){
+				    Some(members) => {members.contains(&token)}
+				    None => {false}
+				};
+				if !is_member{
+					self.send_error(token, &format!("You are not in room {}", m.room));
+					return;
+				}
+				if !self.check_pace(token, &m.room){
+					return;
+				}
+				let username = match self.peers.get(&token){
 				    Some(p) => {p.username.clone()}
 				    None => {return;}
 				};
-				self.broadcast(&format!("{} left", username));
-				self.disconnect(m, &"".to_string());
+				self.log_post(token, "post", json!({"room" : m.room}), &m.mesg);
+				//only what users post is kept in the history, not the notices of the server
+				let post = username + ": " + m.mesg.as_str();
+				self.history.append(&m.room, &post);
+				self.broadcast_room(&m.room, &post);
+			}
+		    ClMessage::IWantInfo(m) => {
+				if self.verify(token, m){
+					self.send_info(token);
+				}
+			}
+		    ClMessage::IQuit(m) => {
+				if !self.verify(token, m){
+					return;
+				}
+				let joined = match self.peers.get(&token){
+				    Some(p) if matches!(p.state, PeerState::Chatting) => {Some(p.username.clone())}
+				    _ => {None}
+				};
+				if let Some(username) = joined{
+					self.log_peer(Level::Info, "leave", token, json!({"reason" : "quit"}));
+					self.broadcast(&format!("{} left", username));
+				}
+				self.disconnect(token, "");
+			}
+		    ClMessage::Ping(m) => {self.verify(token, m);}
+		    ClMessage::Room(m) => {self.process_room(token, &m)}
+		    ClMessage::Direct(m) => {self.send_direct(token, &m)}
+		    ClMessage::History(m) => {self.send_history(token, &m)}
+		    ClMessage::Register(m) => {self.sign_up(token, &m)}
+		    ClMessage::Login(m) => {self.login(token, &m)}
+		    ClMessage::Resume(m) => {self.resume(token, m)}
+		    ClMessage::Rename(m) => {self.rename(token, &m)}
+		    ClMessage::Version(m) => {self.negotiate(token, &m)}
+		    ClMessage::Admin(m) => {
+				if !self.verify(token, m.token){
+					return;
+				}
+				let username = match self.peers.get(&token){
+				    Some(peer) if matches!(peer.state, PeerState::Chatting) => {peer.username.clone()}
+				    _ => {return;}
+				};
+				let role = self.role(&username);
+				let reply = self.execute(&Actor::User{username, role}, m.command);
+				if let Some(peer) = self.peers.get_mut(&token){
+					deliver(peer, &SeMessage::Reply(reply));
+				}
 			}
-		    ClMessage::Ping(m) => {}
 		}
 	}
 
 	pub fn process_messages(&mut self){
 		let mut messages : Vec<(Token, ClMessage)> = Vec::new();
 		for (token, peer) in self.peers.iter_mut(){
-			while peer.messages.len() > 0{
-				match peer.messages.pop_back() {
-				    Some(mesg) => {messages.push((*token, mesg))}
-				    None => {eprintln!("Impossible!")}
-				}
+			while let Some(mesg) = peer.messages.pop_front(){
+				messages.push((*token, mesg));
 			}
 		}
 		for (token, mesg) in messages{
@@ -175,30 +1085,215 @@ impl Server{
 		}
 	}
 
-	pub fn register(&mut self, stream : TcpStream){
+	//why a new connection from the address can't be taken, if it can't
+	fn refusal(&self, addr : Option<IpAddr>) -> Option<String>{
+		if self.conns_in_use.total() >= self.config.max_connections{
+			return Some("Server has too many connections".to_string());
+		}
+		if addr.map(|addr| self.conns_in_use.from(addr)).unwrap_or(0) >= self.config.max_connections_per_ip{
+			return Some("Too many connections from your address".to_string());
+		}
+		return None;
+	}
+
+	//a connection from the WebSocket listener speaks WebSocket, over TLS if the server uses it
+	//one from a banned address is closed at once, without a word
+	pub fn register(&mut self, stream : TcpStream, websocket : bool){
+		let addr = stream.peer_addr().ok().map(|addr| addr.ip());
+		if let Some(addr) = addr{
+			if self.bans.of_address(addr).is_some(){
+				log::event(Level::Warn, "refused", json!({"addr" : addr.to_string(), "reason" : "banned"}));
+				return;
+			}
+		}
+		let refusal = self.refusal(addr);
+		if refusal.is_some() && self.refused.len() >= MAX_REFUSED{
+			log::event(Level::Warn, "refused", json!({"addr" : addr.map(|a| a.to_string()), "reason" : "too many refusals at once"}));
+			return;
+		}
+		let stream = match &self.tls{
+		    Some(config) => {
+				match tls::accept(config, stream){
+				    Ok(v) => {v}
+				    Err(e) => {
+						log!("Couldn't start TLS session: {}", e);
+						return;
+					}
+				}
+			}
+		    None => {Stream::Plain(stream)}
+		};
+		let stream = if websocket{
+			match ws::accept(stream){
+			    Ok(v) => {v}
+			    Err(e) => {
+					log!("Couldn't start WebSocket session: {}", e);
+					return;
+				}
+			}
+		}else{
+			stream
+		};
 		let token = self.make_token();
-		let peer = Peer::new(&token, stream);
+		//clients speak either bincode or JSON, which is told by what they send first,
+		//or by the kind of their first frame over WebSocket
+		let mut peer = Peer::with_encoding(&token, stream, None);
+		peer.traffic = Some(self.metrics.traffic.clone());
+		let conn = mio::Token(self.next_conn);
+		self.next_conn += 1;
+		let interests = mio::Interest::READABLE | mio::Interest::WRITABLE;
+		if let Err(e) = self.poll.registry().register(&mut peer, conn, interests){
+			log!("Couldn't watch a connection: {}", e);
+			return;
+		}
+		self.conns.insert(conn, token);
 		self.peers.insert(token, peer);
+		self.log_peer(Level::Debug, "connect", token, json!({"websocket" : websocket}));
+		match refusal{
+		    Some(reason) => {
+				self.log_peer(Level::Warn, "refused", token, json!({"reason" : reason}));
+				self.refused.insert(token, reason);
+			}
+		    None => {self.conns_in_use.add(token, addr)}
+		}
 	}
 
-	pub fn run(&mut self){
+	//takes all the connections which are waiting on the listener, or on the WebSocket one
+	fn accept(&mut self, websocket : bool){
+		loop{
+			let listener = match (websocket, &self.ws_listener){
+			    (false, _) => {&self.listener}
+			    (true, Some(v)) => {v}
+			    (true, None) => {return;}
+			};
+			match listener.accept(){
+			    Ok((stream, _)) => {self.register(stream, websocket)}
+			    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {return;}
+			    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {continue;}
+			    Err(_) => {
+					println!("Connection failed");
+					return;
+				}
+			}
+		}
+	}
+
+	//how long poll may wait before someone is to be kicked for inactivity, or a session expires
+	fn next_timeout(&self) -> Option<time::Duration>{
+		let mut timeout = None;
+		let peers = self.peers.values().map(|peer| self.config.max_silence.saturating_sub(peer.silent_from.elapsed()));
+		//a moment past the deadline of the peers which haven't joined
+		let unjoined = self.peers.values().filter(|peer| matches!(peer.state, PeerState::AwaitingAuth)).map(|peer|
+			(self.config.handshake_timeout + time::Duration::from_millis(1)).saturating_sub(peer.connected_at.elapsed()));
+		//a moment past the grace period, since the session expires after it
+		let sessions = self.suspended.values().map(|session|
+			(self.config.resume_grace + time::Duration::from_millis(1)).saturating_sub(session.lost_at.elapsed()));
+		let metrics_conns = self.metrics_conns.values().map(|conn|
+			(metrics::CONN_TIMEOUT + time::Duration::from_millis(1)).saturating_sub(conn.connected_at.elapsed()));
+		for left in peers.chain(unjoined).chain(sessions).chain(metrics_conns){
+			timeout = match timeout{
+			    Some(t) if t < left => {Some(t)}
+			    _ => {Some(left)}
+			};
+		}
+		return timeout;
+	}
 
+	pub fn run(&mut self){
+		let mut events = mio::Events::with_capacity(1024);
+		let mut failed : Vec<(Token, Error)> = Vec::new(); //peers which have gone or have sent garbage
 		while !self.should_stop {
-			match self.rx.try_recv(){
-			    Ok(peer) => {self.register(peer)}
-			    Err(_) => {}
+			match self.poll.poll(&mut events, self.next_timeout()){
+			    Ok(_) => {}
+			    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {continue;}
+			    Err(e) => {
+					log!("Failed to poll: {}", e);
+					return;
+				}
+			}
+			let woken_at = time::Instant::now();
+			for event in events.iter(){
+				match event.token(){
+				    LISTENER => {self.accept(false)}
+				    WS_LISTENER => {self.accept(true)}
+				    INPUT => {
+						self.process_input();
+						self.process_hashed();
+					}
+				    CONTROL => {self.accept_admin()}
+				    METRICS => {self.accept_metrics()}
+				    conn if self.admin_conns.contains_key(&conn) => {self.serve_admin(conn, event)}
+				    conn if self.metrics_conns.contains_key(&conn) => {self.serve_metrics(conn, event)}
+				    conn => {
+						let token = match self.conns.get(&conn){
+						    Some(v) => {*v}
+						    None => {continue;}
+						};
+						if let Some(peer) = self.peers.get_mut(&token){
+							if event.is_writable(){
+								peer.flush();
+							}
+							//a closed connection is reported as readable, or as an error if it was reset
+							if event.is_readable() || event.is_read_closed() || event.is_error(){
+								if let Err(e) = peer.get_messages(){
+									failed.push((token, e));
+								}
+							}
+						}
+					}
+				}
 			}
-			self.get_messages();
 			self.process_messages();
-			self.process_input();
+			for (token, e) in failed.drain(..){
+				self.drop_failed(token, e);
+			}
+			self.kick_backlogged();
 			self.kick_inactive();
+			self.kick_unjoined();
+			self.expire_sessions();
+			self.expire_metrics_conns();
+			self.limits.forget_idle();
+			self.login_limits.forget_idle();
+			self.metrics.observe_loop(woken_at.elapsed());
+		}
+	}
+
+	//removes a peer whose connection has closed, or whose packets can't be read
+	//everyone else is served as before
+	fn drop_failed(&mut self, token : Token, e : Error){
+		//the peer might have said goodbye before closing the connection, and be gone already
+		let (username, joined) = match self.peers.get(&token){
+		    Some(peer) => {(peer.username.clone(), matches!(peer.state, PeerState::Chatting))}
+		    None => {return;}
+		};
+		match e{
+		    Error::Closed => {
+				if joined && self.may_suspend(token){
+					self.log_peer(Level::Info, "lost", token, json!({}));
+					self.suspend(token);
+					self.broadcast(&format!("{} lost connection", username));
+				}else if joined{
+					self.log_peer(Level::Info, "leave", token, json!({"reason" : "too many lost sessions"}));
+					self.disconnect(token, "");
+					self.broadcast(&format!("{} left (connection lost)", username));
+				}else{
+					self.disconnect(token, "");
+				}
+			}
+		    e => {
+				self.log_peer(Level::Warn, "protocol_error", token, json!({"error" : e.to_string()}));
+				self.disconnect(token, &format!("Protocol error: {}", e));
+				if joined{
+					self.broadcast(&format!("{} was disconnected for a protocol error", username));
+				}
+			}
 		}
 	}
 
 	pub fn kick_inactive(&mut self){
 		let mut inactive = Vec::new();
 		for (token, peer) in &mut self.peers{
-			if peer.silent_from.elapsed() > MAX_SILENCE{
+			if peer.silent_from.elapsed() > self.config.max_silence{
 				inactive.push((*token, peer.username.clone()));
 			}
 		}
@@ -206,140 +1301,722 @@ impl Server{
 			self.broadcast(&format!("-- {} timed out --", username));
 		}
 		for (token, _) in & inactive{
-			self.disconnect(*token, &"timed out".to_string());
+			self.log_peer(Level::Info, "timeout", *token, json!({}));
+			self.metrics.timeouts += 1;
+			self.disconnect(*token, "timed out");
+		}
+	}
+
+	//disconnects the peers which leave what they are sent unread, so that it doesn't pile up in the server
+	fn kick_backlogged(&mut self){
+		let slow : Vec<(Token, String, bool)> = self.peers.iter()
+			.filter(|(_, peer)| peer.is_backlogged())
+			.map(|(token, peer)| (*token, peer.username.clone(), matches!(peer.state, PeerState::Chatting)))
+			.collect();
+		for (token, username, joined) in slow{
+			self.log_peer(Level::Warn, "backlog", token, json!({}));
+			self.disconnect(token, "Too slow to read messages");
+			if joined{
+				self.broadcast(&format!("{} was disconnected for not reading messages", username));
+			}
+		}
+	}
+
+	//closes the connections which haven't joined in time, so that slow clients can't hold on to the server
+	fn kick_unjoined(&mut self){
+		let late : Vec<Token> = self.peers.iter()
+			.filter(|(_, peer)| matches!(peer.state, PeerState::AwaitingAuth) && peer.connected_at.elapsed() > self.config.handshake_timeout)
+			.map(|(token, _)| *token)
+			.collect();
+		for token in late{
+			self.log_peer(Level::Info, "handshake_timeout", token, json!({}));
+			self.metrics.timeouts += 1;
+			self.disconnect(token, "Took too long to join");
 		}
 	}
 
 	pub fn process_input(&mut self){
-		let input = match self.input_rx.try_recv(){
-		    Ok(v) => {v},
-		    Err(_) => {return;}
-		};
-		let mut command = input.trim().to_string();
-		let arg = match command.find(' ') {
-		    Some(pos) => {command.split_off(pos)}
-		    None => {"".to_string()}
+		while let Ok(input) = self.input_rx.try_recv(){
+			self.process_command(input);
+		}
+	}
+
+	//runs a command typed into the terminal of the server
+	pub fn process_command(&mut self, input : String){
+		if input.trim() == "/help"{
+			Server::print_help();
+			return;
+		}
+		match Command::parse(&input){
+		    Ok(command) => {
+				match self.execute(&Actor::Console, command){
+				    Reply::Done(v) => {println!("{}", v)}
+				    Reply::Failed(v) => {println!("{}", v)}
+				}
+			}
+		    Err(e) => {println!("{}. Try /help", e)}
+		}
+	}
+
+	//runs a command, if the one who gives it may, and records it in the audit log
+	fn execute(&mut self, by : &Actor, command : Command) -> Reply{
+		let reply = match self.permit(by, &command){
+		    Ok(()) => {self.carry_out(by, command.clone())}
+		    Err(reason) => {Reply::Failed(reason)}
 		};
+		let command = command.redacted();
+		self.audit.record(by.name(), &command, &reply);
+		log::event(Level::Info, "admin", json!({"by" : by.name(), "command" : command, "reply" : reply}));
+		return reply;
+	}
 
-		//commands without arguments
-		match command.as_str(){
-			"/help" =>{Server::print_help();}
-			"/stop" => {
-				let reason = "Server closed".to_string();
-				let mut tokens = Vec::new();
-				for (token, _) in self.peers.iter_mut(){
-					tokens.push(token.clone());
+	//whether the one who gives the command may do it, why not otherwise
+	//a user needs the role the command asks for, and may only act on users of lower roles than his own
+	fn permit(&self, by : &Actor, command : &Command) -> Result<(), String>{
+		let role = match by{
+		    Actor::User{role, ..} => {*role}
+		    _ => {return Ok(());} //the terminal and the admin socket belong to whoever runs the server
+		};
+		let needed = command.required_role();
+		if role < needed{
+			return Err(format!("Only {} {} or higher may {}", needed.article(), needed, command.name()));
+		}
+		let target = match command{
+		    Command::Kick{username, ..} | Command::Mute{username, ..} | Command::Unmute{username}
+		    | Command::SetRole{username, ..} => {username}
+		    Command::Ban{target, ..} => {
+				if let Ok(Target::Addresses(network)) = Target::parse(target){
+					return self.permit_range(role, &network);
 				}
+				target
+			}
+		    Command::Unban{target} => {target} //no user has the name of an address
+		    Command::Say(_) | Command::Stop | Command::BanList | Command::SlowMode{..} | Command::Register{..} => {return Ok(());}
+		};
+		if self.role(target) >= role{
+			return Err(format!("{} has a role as high as yours", target));
+		}
+		if let Command::SetRole{role : new, ..} = command{
+			if *new >= role{
+				return Err("You may only give roles below your own".to_string());
+			}
+		}
+		return Ok(());
+	}
+
+	//a range of addresses may only be banned by a user of a higher role than everyone connected from it,
+	//and the broadest ranges, which take in much of the internet, only by the owner
+	fn permit_range(&self, role : Role, network : &Network) -> Result<(), String>{
+		if network.is_broad() && role < Role::Owner{
+			return Err(format!("Only an owner may ban a range as broad as {}", network));
+		}
+		for peer in self.peers.values(){
+			let covered = matches!(peer.peer_addr(), Some(addr) if network.contains(addr.ip()));
+			if covered && matches!(peer.state, PeerState::Chatting) && self.role(&peer.username) >= role{
+				return Err(format!("{} is connected from {} and has a role as high as yours", peer.username, network));
+			}
+		}
+		return Ok(());
+	}
+
+	fn carry_out(&mut self, by : &Actor, command : Command) -> Reply{
+		match command{
+		    Command::Kick{username, reason} => {return self.kick(&username, &reason);}
+		    Command::Say(mesg) => {
+				self.broadcast(&format!("Server -- {}", mesg));
+				return Reply::Done("Sent".to_string());
+			}
+		    Command::Stop => {
+				let tokens : Vec<Token> = self.peers.keys().cloned().collect();
 				for token in tokens{
-					self.disconnect(token, &reason)
+					self.disconnect(token, "Server closed");
 				}
 				self.should_stop = true;
+				return Reply::Done("Server stopped".to_string());
+			}
+		    Command::Mute{username, duration} => {
+				let now = time::Instant::now();
+				if matches!(self.muted.get(&username), Some(until) if until.is_none_or(|until| until > now)){
+					return Reply::Failed(format!("{} is muted already", username));
+				}
+				let lasting = match duration{
+				    Some(duration) => {format!(" for {}", admin::format_duration(duration))}
+				    None => {String::new()}
+				};
+				self.muted.insert(username.clone(), duration.map(|duration| now + time::Duration::from_secs(duration)));
+				self.notify(&username, &format!("You were muted{} by {}", lasting, by.name()));
+				return Reply::Done(format!("{} was muted{}", username, lasting));
+			}
+		    Command::Unmute{username} => {
+				if self.muted.remove(&username).is_none(){
+					return Reply::Failed(format!("{} isn't muted", username));
+				}
+				self.notify(&username, "You may post again");
+				return Reply::Done(format!("{} was unmuted", username));
+			}
+		    Command::Ban{target, reason, duration} => {return self.ban(by, &target, &reason, duration);}
+		    Command::Unban{target} => {
+				let target = match Target::parse(&target){
+				    Ok(v) => {v}
+				    Err(e) => {return Reply::Failed(e);}
+				};
+				match self.bans.remove(&target){
+				    Ok(true) => {return Reply::Done(format!("{} was unbanned", target));}
+				    Ok(false) => {return Reply::Failed(format!("{} isn't banned", target));}
+				    Err(e) => {return Reply::Failed(e);}
+				}
+			}
+		    Command::SetRole{username, role} => {
+				if !self.accounts.is_registered(&username){
+					return Reply::Failed(format!("{} isn't registered, only registered users may have roles", username));
+				}
+				if let Err(e) = self.roles.set(&username, role){
+					return Reply::Failed(e);
+				}
+				//the configuration has the last word
+				let role = self.role(&username);
+				self.notify(&username, &format!("You are now {} {}", role.article(), role));
+				return Reply::Done(format!("{} is now {} {}", username, role.article(), role));
 			}
-			"/kick" => {
-				self.kick(arg);
+		    Command::BanList => {
+				let bans = self.bans.list();
+				if bans.is_empty(){
+					return Reply::Done("No one is banned".to_string());
+				}
+				let lines : Vec<String> = bans.iter().map(|(_, ban)| ban.to_string()).collect();
+				return Reply::Done(lines.join("\n"));
+			}
+		    Command::SlowMode{room, seconds} => {
+				if !self.rooms.contains_key(&room){
+					return Reply::Failed(format!("No such room: {}", room));
+				}
+				let mesg = if seconds == 0{
+					format!("Slow mode is off in {}", room)
+				}else{
+					format!("Slow mode is on in {}, each user may post once in {}", room, admin::format_duration(seconds))
+				};
+				self.slow_rooms.insert(room.clone(), time::Duration::from_secs(seconds));
+				self.broadcast_room(&room, &mesg);
+				return Reply::Done(mesg);
 			}
-			"/say" =>{
-				self.broadcast(&("Server -- ".to_string() + &arg[..]));
+		    Command::Register{username, password} => {
+				if !username.chars().all(char::is_alphanumeric){
+					return Reply::Failed("Username contains illegal character".to_string());
+				}
+				if username.len() > MAX_NAME_LEN{
+					return Reply::Failed("Username is too long".to_string());
+				}
+				if let Err(reason) = self.accounts.register(&username, &password){
+					return Reply::Failed(reason);
+				}
+				return Reply::Done(format!("{} was registered", username));
 			}
-			_ =>{println!("Unrecognized command. Try /help")}
 		}
 	}
 
-	pub fn kick(&mut self, arg :String){
-		let mut iter = arg.split_whitespace();
-		let username = match iter.next(){
-		    Some(v) => {v.trim()}
-		    None => {
-				eprintln!("No username provided");
-				return;
+	//bans a user or a range of addresses, or changes the ban if there is one, and kicks whoever it covers
+	fn ban(&mut self, by : &Actor, target : &str, reason : &str, duration : Option<u64>) -> Reply{
+		let target = match Target::parse(target){
+		    Ok(v) => {v}
+		    Err(e) => {return Reply::Failed(e);}
+		};
+		let ban = Ban{
+			target : target.to_string(),
+			reason : reason.to_string(),
+			by : by.name().to_string(),
+			until : duration.map(|duration| bans::now() + duration)
+		};
+		let message = ban.message();
+		if let Err(e) = self.bans.add(target.clone(), ban){
+			return Reply::Failed(e);
+		}
+		let (covered, mut kicked) = match &target{
+		    Target::User(username) => {(self.name_map.get(username).copied().into_iter().collect(), vec![username.clone()])}
+		    Target::Addresses(network) => {
+				let covered : Vec<Token> = self.peers.iter()
+					.filter(|(_, peer)| matches!(peer.peer_addr(), Some(addr) if network.contains(addr.ip())))
+					.map(|(token, _)| *token)
+					.collect();
+				(covered, Vec::new())
 			}
 		};
-		let mut reason = String::new();
-		reason.reserve(arg.len());
-		loop{
-			match iter.next() {
-			    Some(v) => {
-					reason += v;
-					reason += " ";
+		for token in covered{
+			self.log_peer(Level::Info, "ban", token, json!({"target" : target.to_string(), "reason" : reason}));
+			if let Some(peer) = self.peers.get(&token){
+				if matches!(target, Target::Addresses(_)) && matches!(peer.state, PeerState::Chatting){
+					kicked.push(peer.username.clone());
 				}
-			    None => {break;}
 			}
+			self.disconnect(token, &message);
+		}
+		let lasting = match duration{
+		    Some(duration) => {format!(" for {}", admin::format_duration(duration))}
+		    None => {String::new()}
+		};
+		for username in kicked{
+			self.broadcast(&format!("{} was banned{} for the reason: {}", username, lasting, reason));
 		}
+		return Reply::Done(format!("{} was banned{}", target, lasting));
+	}
+
+	//tells a user something from the server, if he is here
+	fn notify(&mut self, username : &str, mesg : &str){
+		let token = match self.name_map.get(username){
+		    Some(v) => {*v}
+		    None => {return;}
+		};
+		self.send_to(token, &SeMessage::Mesg(SeMesg{room : String::new(), mesg : mesg.to_string()}));
+	}
+
+	pub fn kick(&mut self, username : &str, reason : &str) -> Reply{
 		let token = match self.name_map.get(username){
 			Some(v) => *v,
 			None =>{
-				println!("No such user: {}", username);
-				return;
+				return Reply::Failed(format!("No such user: {}", username));
 			}
 		};
-		self.disconnect(token, &reason.to_string());
+		self.log_peer(Level::Info, "kick", token, json!({"reason" : reason}));
+		self.metrics.kicks += 1;
+		self.disconnect(token, reason);
 		self.broadcast(&format!("{} was disconnected for the reason: {}", username, reason));
+		return Reply::Done(format!("{} was kicked", username));
 	}
 
 	pub fn print_help(){
 		println!("--------------------
 A list of availible commands:
-/help - displays help on commands
-/kick <username> - kicks a user with <username>
-/say <message> - sends a message
-/stop - stops the server
---------------------")
+/help - displays help on commands");
+		for line in admin::HELP.lines(){
+			println!("/{}", line);
+		}
+		println!("--------------------");
+	}
+
+	//takes all the connections which are waiting on the admin socket
+	fn accept_admin(&mut self){
+		loop{
+			let listener = match &self.control{
+			    Some(v) => {&v.listener}
+			    None => {return;}
+			};
+			let mut conn = match listener.accept(){
+			    Ok((stream, _)) => {AdminConn::new(stream)}
+			    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {return;}
+			    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {continue;}
+			    Err(e) => {
+					log!("Admin connection failed: {}", e);
+					return;
+				}
+			};
+			let token = mio::Token(self.next_conn);
+			self.next_conn += 1;
+			let interests = mio::Interest::READABLE | mio::Interest::WRITABLE;
+			if let Err(e) = self.poll.registry().register(&mut conn.stream, token, interests){
+				log!("Couldn't watch an admin connection: {}", e);
+				continue;
+			}
+			self.admin_conns.insert(token, conn);
+		}
+	}
+
+	//takes all the connections which are waiting on the metrics listener
+	fn accept_metrics(&mut self){
+		loop{
+			let listener = match &self.metrics_listener{
+			    Some(v) => {v}
+			    None => {return;}
+			};
+			let mut conn = match listener.accept(){
+			    Ok(_) if self.metrics_conns.len() >= metrics::MAX_CONNS => {continue;} //closed at once
+			    Ok((stream, _)) => {HttpConn::new(stream)}
+			    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {return;}
+			    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {continue;}
+			    Err(e) => {
+					log!("Metrics connection failed: {}", e);
+					return;
+				}
+			};
+			let token = mio::Token(self.next_conn);
+			self.next_conn += 1;
+			let interests = mio::Interest::READABLE | mio::Interest::WRITABLE;
+			if let Err(e) = self.poll.registry().register(&mut conn.stream, token, interests){
+				log!("Couldn't watch a metrics connection: {}", e);
+				continue;
+			}
+			self.metrics_conns.insert(token, conn);
+		}
+	}
+
+	//closes the metrics connections which have been kept too long, whether they have asked or not
+	fn expire_metrics_conns(&mut self){
+		let expired : Vec<mio::Token> = self.metrics_conns.iter()
+			.filter(|(_, conn)| conn.connected_at.elapsed() > metrics::CONN_TIMEOUT)
+			.map(|(token, _)| *token)
+			.collect();
+		for token in expired{
+			if let Some(mut conn) = self.metrics_conns.remove(&token){
+				let _ = self.poll.registry().deregister(&mut conn.stream);
+			}
+		}
+	}
+
+	//answers the request of a metrics connection, and closes it once the answer is written out
+	fn serve_metrics(&mut self, token : mio::Token, event : &mio::event::Event){
+		let mut conn = match self.metrics_conns.remove(&token){
+		    Some(v) => {v}
+		    None => {return;}
+		};
+		if event.is_writable(){
+			conn.flush();
+		}
+		let mut closed = false;
+		if !conn.is_answered() && (event.is_readable() || event.is_read_closed() || event.is_error()){
+			match conn.read(){
+			    Ok(Some(path)) if path.split('?').next() == Some("/metrics") => {
+					let body = self.metrics.render(&self.gauges());
+					conn.respond("200 OK", &body);
+				}
+			    Ok(Some(_)) => {conn.respond("404 Not Found", "Only /metrics is served here\n")}
+			    Ok(None) => {}
+			    Err(Error::Closed) => {closed = true}
+			    Err(_) => {conn.respond("400 Bad Request", "Bad request\n")}
+			}
+		}
+		if closed || conn.is_done(){
+			let _ = self.poll.registry().deregister(&mut conn.stream);
+		}else{
+			self.metrics_conns.insert(token, conn);
+		}
+	}
+
+	fn gauges(&self) -> Gauges{
+		let mut gauges = Gauges{awaiting_auth : 0, chatting : 0, quitting : 0, suspended : self.suspended.len(), rooms : self.rooms.len()};
+		for peer in self.peers.values(){
+			match peer.state{
+			    PeerState::AwaitingAuth => {gauges.awaiting_auth += 1}
+			    PeerState::Chatting => {gauges.chatting += 1}
+			    PeerState::Quitting => {gauges.quitting += 1}
+			}
+		}
+		return gauges;
+	}
+
+	//runs the commands which have come on an admin connection, replying to each
+	fn serve_admin(&mut self, token : mio::Token, event : &mio::event::Event){
+		let mut conn = match self.admin_conns.remove(&token){
+		    Some(v) => {v}
+		    None => {return;}
+		};
+		if event.is_writable(){
+			conn.flush();
+		}
+		let mut result = Ok(Vec::new());
+		if event.is_readable() || event.is_read_closed() || event.is_error(){
+			result = conn.read();
+		}
+		let (commands, closed) = match result{
+		    Ok(v) => {(v, false)}
+		    Err(Error::Closed) => {(Vec::new(), true)}
+		    Err(e) => {
+				conn.reply(&Reply::Failed(e.to_string()));
+				(Vec::new(), true)
+			}
+		};
+		for command in commands{
+			let reply = self.execute(&Actor::Socket, command);
+			conn.reply(&reply);
+		}
+		if closed{
+			let _ = self.poll.registry().deregister(&mut conn.stream);
+		}else{
+			self.admin_conns.insert(token, conn);
+		}
+	}
+}
+
+//the history of the room with as many of the newest messages as fit into a packet to the peer
+fn fit_history(peer : &Peer<ClMessage>, room : &str, mut mesgs : Vec<String>) -> SeMessage{
+	let mut size = peer.size_of(&SeMessage::History(SeHistory{room : room.to_string(), mesgs : Vec::new()}));
+	let mut first = mesgs.len();
+	for (n, mesg) in mesgs.iter().enumerate().rev(){
+		size = size.saturating_add(peer.size_of(mesg) + 1); //a comma between them in JSON
+		if size > peer.max_size(){
+			break;
+		}
+		first = n;
+	}
+	mesgs.drain(..first);
+	return SeMessage::History(SeHistory{room : room.to_string(), mesgs});
+}
+
+//sends a message to a peer, a message which can't be sent is only reported to the console
+//messages the peer's version of the protocol doesn't know are left out
+fn deliver(peer : &mut Peer<ClMessage>, mesg : &SeMessage){
+	if mesg.since() > peer.version{
+		return;
+	}
+	match peer.send(mesg){
+	    Ok(()) => {}
+	    Err(Error::Backlog) => {} //the peer is disconnected for it, once the messages at hand are handled
+	    Err(e) => {log!("Couldn't send a message to {}: {}", peer.username, e)}
 	}
 }
 
-fn listen(tx : std::sync::mpsc::Sender<TcpStream>, is_bound : Arc<Mutex<bool>>){
-	let listener = loop{
+//asks for the address until it manages to bind to it
+fn listen() -> TcpListener{
+	loop{
 		println!("Enter address to listen: ");
 		let mut addr = String::new();
-		std::io::stdin().read_line(&mut addr);
-		let listener = match TcpListener::bind(addr.trim()){
-		    Ok(n) => n,
+		match std::io::stdin().read_line(&mut addr){
+		    Ok(0) => {
+				eprintln!("No address to listen");
+				std::process::exit(1);
+			}
+		    Ok(_) => {}
+		    Err(_) => {continue;}
+		}
+		match bind(addr.trim()){
+		    Ok(v) => {return v;}
 		    Err(_) => {
 				eprintln!("Couldn't bind to adress");
 				continue;
 			}
-		};
-		*is_bound.lock().unwrap() = true;
-		break listener;
-	};
-
-	println!("Listening");
-	println!("Type /help for a list of commands");
-	for stream in listener.incoming(){
-		let stream = match stream {
-			Ok(val) => {val},
-			Err(_) => {println!("Connection failed");
-				continue;}
-		};
-		match tx.send(stream){
-			Ok(_) => {}
-			Err(_) => {return;}
 		}
 	}
 }
 
-fn get_input(tx : std::sync::mpsc::Sender<String>, is_bound : Arc<Mutex<bool>>){
-	while !*is_bound.lock().unwrap(){
-		//wait till it's bound
-	}
+fn bind(addr : &str) -> io::Result<TcpListener>{
+	let listener = std::net::TcpListener::bind(addr)?;
+	listener.set_nonblocking(true)?;
+	return Ok(TcpListener::from_std(listener));
+}
+
+//reads commands from stdin, the server is woken up by waker to process them
+fn get_input(tx : mpsc::Sender<String>, waker : Arc<mio::Waker>){
 	loop{
 		let mut command = String::new();
-		std::io::stdin().read_line(&mut command);
-		match tx.send(command){
-			Ok(_) => {continue;}
-			Err(_) => {return;}
+		match std::io::stdin().read_line(&mut command){
+		    Ok(0) => {return;} //stdin is closed, there will be no more commands
+		    Ok(_) => {}
+		    Err(_) => {continue;}
+		}
+		if tx.send(command).is_err(){
+			return;
+		}
+		if waker.wake().is_err(){
+			return;
+		}
+	}
+}
+
+fn print_usage(){
+	println!("Usage: server [--config <file>] [options]
+Options override the settings of the configuration file, which are named the same with _ in place of -
+--config <file> - TOML file with the settings
+--listen <address> - the address to serve clients on, asked for if not given
+--ws <address> - serves WebSocket clients on the address too, over TLS as well if it is used
+--cert <file> - PEM file with the certificate chain, serves clients over TLS if given
+--key <file> - PEM file with the private key of the certificate
+--max-silence <seconds> - how long a client may send nothing before it is kicked
+--resume-grace <seconds> - how long a lost session waits to be resumed
+--max-users <number> - how many users may be in the chat at once
+--max-message-len <number> - the most characters a message may have
+--motd <text> - a message every user gets on joining
+--history-dir <dir> - where the messages of the rooms are kept
+--accounts-file <file> - where the registered users are kept
+--log-file <file> - where events are logged as JSON lines, warnings and errors go to stderr if it isn't given
+--log-level <level> - the least level of the events logged: debug, info, warn or error
+--log-max-size <bytes> - the log file is rotated once it grows past it, 0 never to rotate it
+--log-keep <number> - how many rotated log files are kept
+--no-log-bodies - leaves what users post out of the log
+--owner <username> - the user who is the owner of the server
+--admin <username> - a user who is an admin, can be repeated
+--roles-file <file> - where the roles given to users are kept
+--audit-log <file> - where privileged commands are recorded
+--bans-file <file> - where the bans of users and addresses are kept
+--slow-mode <seconds> - how often each user may post in a room, unless the room is set otherwise
+--rate-limit <number> - messages a second a connection may send, 0 for no limit
+--rate-burst <number> - messages a connection may send at once
+--ip-rate-limit <number> - messages a second all the connections from an address may send together, 0 for no limit
+--ip-rate-burst <number> - messages they may send at once
+--flood-kick <number> - messages over the limit in a row before the connection is closed, 0 never to close it
+--max-connections <number> - how many clients may be connected at once, whether they have joined or not
+--max-connections-per-ip <number> - how many of them may come from one address
+--handshake-timeout <seconds> - how long a client may take to join after connecting
+--daemon - runs without a terminal, not reading commands from stdin
+--admin-socket <file> - a Unix socket to take commands from chatctl on
+--metrics <address> - serves metrics for Prometheus over HTTP on the address, at /metrics");
+}
+
+//takes the value of a flag which needs one
+fn value(flag : &str, args : &mut impl Iterator<Item = String>) -> Result<String, String>{
+	match args.next(){
+	    Some(v) => {return Ok(v);}
+	    None => {return Err(format!("{} needs a value", flag));}
+	}
+}
+
+fn number<T : std::str::FromStr>(flag : &str, args : &mut impl Iterator<Item = String>) -> Result<T, String>{
+	let v = value(flag, args)?;
+	match v.parse(){
+	    Ok(n) => {return Ok(n);}
+	    Err(_) => {return Err(format!("{} needs a number, not {}", flag, v));}
+	}
+}
+
+//the settings from the command-line arguments, over the ones of the configuration file if it is given
+fn parse_args() -> Result<Config, String>{
+	let mut flags = Settings::default();
+	let mut file = None;
+	let mut admins : Vec<String> = Vec::new();
+	let mut args = std::env::args().skip(1);
+	while let Some(arg) = args.next(){
+		let args = &mut args;
+		match arg.as_str(){
+		    "--config" => {file = Some(value(&arg, args)?)}
+		    "--listen" => {flags.listen = Some(value(&arg, args)?)}
+		    "--ws" => {flags.ws_listen = Some(value(&arg, args)?)}
+		    "--cert" => {flags.cert = Some(value(&arg, args)?)}
+		    "--key" => {flags.key = Some(value(&arg, args)?)}
+		    "--max-silence" => {flags.max_silence = Some(number(&arg, args)?)}
+		    "--resume-grace" => {flags.resume_grace = Some(number(&arg, args)?)}
+		    "--max-users" => {flags.max_users = Some(number(&arg, args)?)}
+		    "--max-message-len" => {flags.max_message_len = Some(number(&arg, args)?)}
+		    "--motd" => {flags.motd = Some(value(&arg, args)?)}
+		    "--history-dir" => {flags.history_dir = Some(value(&arg, args)?)}
+		    "--accounts-file" => {flags.accounts_file = Some(value(&arg, args)?)}
+		    "--log-file" => {flags.log_file = Some(value(&arg, args)?)}
+		    "--log-level" => {flags.log_level = Some(value(&arg, args)?)}
+		    "--log-max-size" => {flags.log_max_size = Some(number(&arg, args)?)}
+		    "--log-keep" => {flags.log_keep = Some(number(&arg, args)?)}
+		    "--no-log-bodies" => {flags.log_bodies = Some(false)}
+		    "--admin" => {admins.push(value(&arg, args)?)}
+		    "--owner" => {flags.owner = Some(value(&arg, args)?)}
+		    "--roles-file" => {flags.roles_file = Some(value(&arg, args)?)}
+		    "--audit-log" => {flags.audit_log = Some(value(&arg, args)?)}
+		    "--bans-file" => {flags.bans_file = Some(value(&arg, args)?)}
+		    "--slow-mode" => {flags.slow_mode = Some(number(&arg, args)?)}
+		    "--rate-limit" => {flags.rate_limit = Some(number(&arg, args)?)}
+		    "--rate-burst" => {flags.rate_burst = Some(number(&arg, args)?)}
+		    "--ip-rate-limit" => {flags.ip_rate_limit = Some(number(&arg, args)?)}
+		    "--ip-rate-burst" => {flags.ip_rate_burst = Some(number(&arg, args)?)}
+		    "--flood-kick" => {flags.flood_kick = Some(number(&arg, args)?)}
+		    "--max-connections" => {flags.max_connections = Some(number(&arg, args)?)}
+		    "--max-connections-per-ip" => {flags.max_connections_per_ip = Some(number(&arg, args)?)}
+		    "--handshake-timeout" => {flags.handshake_timeout = Some(number(&arg, args)?)}
+		    "--daemon" => {flags.daemon = Some(true)}
+		    "--admin-socket" => {flags.admin_socket = Some(value(&arg, args)?)}
+		    "--metrics" => {flags.metrics_listen = Some(value(&arg, args)?)}
+		    "--help" => {
+				print_usage();
+				std::process::exit(0);
+			}
+		    _ => {return Err(format!("Unknown argument: {}", arg));}
+		}
+	}
+	if !admins.is_empty(){
+		flags.admins = Some(admins);
+	}
+	let settings = match file{
+	    Some(path) => {flags.or(Settings::load(&path)?)}
+	    None => {flags}
+	};
+	return settings.validate();
+}
+
+//loads the certificate if the server is to use TLS
+fn load_tls(config : &Config) -> Result<Option<Arc<rustls::ServerConfig>>, String>{
+	match (&config.cert, &config.key){
+	    (Some(cert), Some(key)) => {
+			let (config, fingerprint) = tls::server_config(cert, key)?;
+			println!("Serving over TLS, certificate fingerprint: {}", fingerprint);
+			return Ok(Some(config));
 		}
+	    _ => {return Ok(None);}
 	}
 }
 
+fn fail(e : &str) -> !{
+	eprintln!("{}", e);
+	std::process::exit(1);
+}
+
 fn main() {
-	let mut is_bound = Arc::new(Mutex::new(false)); //whether server was bound to an address
-	let (cli_tx, cli_rx) = std::sync::mpsc::channel();
-	let (tx, rx) = std::sync::mpsc::channel();
-	let is_bound_clone = is_bound.clone();
-	let input_thread = std::thread::spawn(move || {get_input(cli_tx, is_bound_clone)});
-	let is_bound_clone = is_bound.clone();
-	let listening_thread = std::thread::spawn(move || {listen(tx, is_bound_clone)});
-	let mut server = Server::new(rx, cli_rx);
+	let config = match parse_args(){
+	    Ok(v) => {v}
+	    Err(e) => {
+			eprintln!("{}", e);
+			print_usage();
+			std::process::exit(1);
+		}
+	};
+	if let Some(path) = &config.log_file{
+		if let Err(e) = log::open(path, config.log_level, config.log_max_size, config.log_keep){
+			fail(&e);
+		}
+	}
+	let tls = match load_tls(&config){
+	    Ok(v) => {v}
+	    Err(e) => {fail(&e)}
+	};
+	let listener = match &config.listen{
+	    Some(addr) => {
+			match bind(addr){
+			    Ok(v) => {v}
+			    Err(e) => {fail(&format!("Couldn't bind to {}: {}", addr, e))}
+			}
+		}
+	    None => {listen()}
+	};
+	let ws_listener = match &config.ws_listen{
+	    Some(addr) => {
+			match bind(addr){
+			    Ok(v) => {
+					println!("Listening for WebSocket clients on {}", addr);
+					Some(v)
+				}
+			    Err(e) => {fail(&format!("Couldn't bind to {}: {}", addr, e))}
+			}
+		}
+	    None => {None}
+	};
+	let control = match &config.admin_socket{
+	    Some(path) => {
+			match Control::bind(path){
+			    Ok(v) => {
+					println!("Taking commands on {}", path);
+					Some(v)
+				}
+			    Err(e) => {fail(&e)}
+			}
+		}
+	    None => {None}
+	};
+	let metrics_listener = match &config.metrics_listen{
+	    Some(addr) => {
+			match bind(addr){
+			    Ok(v) => {
+					println!("Serving metrics on http://{}/metrics", addr);
+					Some(v)
+				}
+			    Err(e) => {fail(&format!("Couldn't bind to {}: {}", addr, e))}
+			}
+		}
+	    None => {None}
+	};
+	println!("Listening");
+	let poll = mio::Poll::new().expect("Failed to create poll");
+	//poll takes only one waker, the input thread and the password hasher share it
+	let waker = Arc::new(mio::Waker::new(poll.registry(), INPUT).expect("Failed to create waker"));
+	let (cli_tx, cli_rx) = mpsc::channel();
+	if !config.daemon{
+		println!("Type /help for a list of commands");
+		let waker = waker.clone();
+		std::thread::spawn(move || {get_input(cli_tx, waker)});
+	}
+	let mut server = match Server::new(poll, waker, listener, ws_listener, control, metrics_listener, cli_rx, tls, config){
+	    Ok(v) => {v}
+	    Err(e) => {fail(&format!("Couldn't start the server: {}", e))}
+	};
 	server.run();
 }
@@ -0,0 +1,233 @@
+use std::sync::Arc;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::TcpStream;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::{ClientConfig, ClientConnection, DigitallySignedStruct, ServerConfig, ServerConnection, SignatureScheme, StreamOwned};
+use sha2::{Digest, Sha256};
+use crate::net::Stream;
+
+//the client gives up if the handshake takes longer than this
+const HANDSHAKE_TIMEOUT : std::time::Duration = std::time::Duration::from_secs(10);
+
+fn load_certs(path : &str) -> Result<Vec<CertificateDer<'static>>, String>{
+	let file = File::open(path).map_err(|e| format!("Couldn't open {}: {}", path, e))?;
+	let certs : Result<Vec<_>, _> = rustls_pemfile::certs(&mut BufReader::new(file)).collect();
+	let certs = certs.map_err(|e| format!("Couldn't read certificates from {}: {}", path, e))?;
+	if certs.is_empty(){
+		return Err(format!("No certificates in {}", path));
+	}
+	return Ok(certs);
+}
+
+fn load_key(path : &str) -> Result<PrivateKeyDer<'static>, String>{
+	let file = File::open(path).map_err(|e| format!("Couldn't open {}: {}", path, e))?;
+	match rustls_pemfile::private_key(&mut BufReader::new(file)){
+	    Ok(Some(key)) => {return Ok(key);}
+	    Ok(None) => {return Err(format!("No private key in {}", path));}
+	    Err(e) => {return Err(format!("Couldn't read private key from {}: {}", path, e));}
+	}
+}
+
+//sha-256 of the certificate in hex, what a client pins to trust a server
+pub fn fingerprint(cert : &[u8]) -> String{
+	return Sha256::digest(cert).iter().map(|b| format!("{:02x}", b)).collect();
+}
+
+//server configuration from PEM files with the certificate chain and its private key
+//returns the configuration and the fingerprint of the server's certificate
+pub fn server_config(cert_path : &str, key_path : &str) -> Result<(Arc<ServerConfig>, String), String>{
+	let certs = load_certs(cert_path)?;
+	let key = load_key(key_path)?;
+	let fingerprint = fingerprint(&certs[0]);
+	let config = ServerConfig::builder()
+		.with_no_client_auth()
+		.with_single_cert(certs, key)
+		.map_err(|e| format!("Bad certificate or key: {}", e))?;
+	return Ok((Arc::new(config), fingerprint));
+}
+
+//client configuration trusting servers with certificates signed by the CAs from a PEM file
+pub fn client_config_ca(ca_path : &str) -> Result<Arc<ClientConfig>, String>{
+	let mut roots = rustls::RootCertStore::empty();
+	for cert in load_certs(ca_path)?{
+		roots.add(cert).map_err(|e| format!("Bad CA certificate in {}: {}", ca_path, e))?;
+	}
+	let config = ClientConfig::builder()
+		.with_root_certificates(roots)
+		.with_no_client_auth();
+	return Ok(Arc::new(config));
+}
+
+//client configuration trusting only the server with the given certificate fingerprint
+pub fn client_config_pinned(fingerprint : &str) -> Result<Arc<ClientConfig>, String>{
+	let pin : String = fingerprint.chars().filter(|c| *c != ':').collect::<String>().to_lowercase();
+	if pin.len() != 64 || !pin.chars().all(|c| c.is_ascii_hexdigit()){
+		return Err("A fingerprint must be 64 hex digits of sha-256".to_string());
+	}
+	let provider = match rustls::crypto::CryptoProvider::get_default(){
+	    Some(v) => {v.clone()}
+	    None => {Arc::new(rustls::crypto::ring::default_provider())}
+	};
+	let config = ClientConfig::builder_with_provider(provider.clone())
+		.with_safe_default_protocol_versions()
+		.map_err(|e| format!("{}", e))?
+		.dangerous()
+		.with_custom_certificate_verifier(Arc::new(PinnedVerifier{pin, provider}))
+		.with_no_client_auth();
+	return Ok(Arc::new(config));
+}
+
+//wraps a freshly accepted connection into TLS, the handshake is carried out while reading
+pub fn accept(config : &Arc<ServerConfig>, stream : mio::net::TcpStream) -> Result<Stream, String>{
+	let conn = ServerConnection::new(config.clone()).map_err(|e| format!("{}", e))?;
+	return Ok(Stream::TlsServer(Box::new(StreamOwned::new(conn, stream))));
+}
+
+//connects to a server at addr (host:port) and completes the handshake
+//the handshake is done before the stream becomes nonblocking, so nothing sent after it is lost
+pub fn connect(config : &Arc<ClientConfig>, addr : &str) -> Result<Stream, String>{
+	let host = match addr.rfind(':'){
+	    Some(pos) => {&addr[..pos]}
+	    None => {addr}
+	};
+	let host = host.trim_start_matches('[').trim_end_matches(']');
+	let name = ServerName::try_from(host.to_string()).map_err(|e| format!("Bad server name {}: {}", host, e))?;
+	let mut sock = TcpStream::connect(addr).map_err(|e| format!("Couldn't connect: {}", e))?;
+	let mut conn = ClientConnection::new(config.clone(), name).map_err(|e| format!("{}", e))?;
+	sock.set_read_timeout(Some(HANDSHAKE_TIMEOUT)).map_err(|e| format!("{}", e))?;
+	while conn.is_handshaking(){
+		conn.complete_io(&mut sock).map_err(|e| format!("TLS handshake failed: {}", e))?;
+	}
+	sock.set_nonblocking(true).map_err(|e| format!("{}", e))?;
+	let sock = mio::net::TcpStream::from_std(sock);
+	return Ok(Stream::TlsClient(Box::new(StreamOwned::new(conn, sock))));
+}
+
+//accepts the server's certificate if its fingerprint is the pinned one, whoever has signed it
+#[derive(Debug)]
+struct PinnedVerifier{
+	pin : String,
+	provider : Arc<rustls::crypto::CryptoProvider>
+}
+
+impl ServerCertVerifier for PinnedVerifier{
+	fn verify_server_cert(&self, end_entity : &CertificateDer<'_>, _intermediates : &[CertificateDer<'_>],
+		_server_name : &ServerName<'_>, _ocsp_response : &[u8], _now : UnixTime) -> Result<ServerCertVerified, rustls::Error>{
+		if fingerprint(end_entity) == self.pin{
+			return Ok(ServerCertVerified::assertion());
+		}
+		return Err(rustls::Error::General("Server certificate doesn't match the pinned fingerprint".to_string()));
+	}
+
+	fn verify_tls12_signature(&self, message : &[u8], cert : &CertificateDer<'_>, dss : &DigitallySignedStruct)
+		-> Result<HandshakeSignatureValid, rustls::Error>{
+		return rustls::crypto::verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms);
+	}
+
+	fn verify_tls13_signature(&self, message : &[u8], cert : &CertificateDer<'_>, dss : &DigitallySignedStruct)
+		-> Result<HandshakeSignatureValid, rustls::Error>{
+		return rustls::crypto::verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms);
+	}
+
+	fn supported_verify_schemes(&self) -> Vec<SignatureScheme>{
+		return self.provider.signature_verification_algorithms.supported_schemes();
+	}
+}
+
+#[cfg(test)]
+mod tests{
+	use super::*;
+	use crate::net::*;
+	use std::net::TcpListener;
+
+	//writes a self-signed certificate for localhost and its key into a temporary directory
+	fn self_signed(name : &str) -> (String, String, String){
+		let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+		let dir = std::env::temp_dir().join(format!("chat-tls-{}-{}", name, std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let cert_path = dir.join("cert.pem").to_str().unwrap().to_string();
+		let key_path = dir.join("key.pem").to_str().unwrap().to_string();
+		std::fs::write(&cert_path, cert.cert.pem()).unwrap();
+		std::fs::write(&key_path, cert.key_pair.serialize_pem()).unwrap();
+		return (cert_path, key_path, fingerprint(cert.cert.der()));
+	}
+
+	//runs a server which greets one client back, returns the address it listens at
+	fn serve_one(config : Arc<ServerConfig>) -> String{
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = format!("localhost:{}", listener.local_addr().unwrap().port());
+		std::thread::spawn(move || {
+			let (stream, _) = listener.accept().unwrap();
+			stream.set_nonblocking(true).unwrap();
+			let stream = mio::net::TcpStream::from_std(stream);
+			let mut peer : Peer<ClMessage> = Peer::new(&1, accept(&config, stream).unwrap());
+			let started_at = std::time::Instant::now();
+			while started_at.elapsed() < HANDSHAKE_TIMEOUT{
+				peer.get_messages().unwrap();
+				if let Some(ClMessage::Hello(hello)) = peer.messages.pop_front(){
+					assert_eq!(hello.username, "alice");
+					peer.send(&SeMessage::Hello(SeHello{token : 42})).unwrap();
+					break;
+				}
+			}
+			//keep the connection open until the client reads the reply
+			std::thread::sleep(std::time::Duration::from_secs(1));
+		});
+		return addr;
+	}
+
+	fn greet(config : &Arc<ClientConfig>, addr : &str) -> Token{
+		let stream = connect(config, addr).unwrap();
+		let mut peer : Peer<SeMessage> = Peer::new(&0, stream);
+		peer.send(&ClMessage::Hello(ClHello{username : "alice".to_string()})).unwrap();
+		let started_at = std::time::Instant::now();
+		while started_at.elapsed() < HANDSHAKE_TIMEOUT{
+			peer.get_messages().unwrap();
+			if let Some(SeMessage::Hello(hello)) = peer.messages.pop_front(){
+				return hello.token;
+			}
+		}
+		panic!("No hello from the server");
+	}
+
+	#[test]
+	fn ca_verified_exchange(){
+		let (cert, key, _) = self_signed("ca");
+		let (server, _) = server_config(&cert, &key).unwrap();
+		let addr = serve_one(server);
+		let client = client_config_ca(&cert).unwrap();
+		assert_eq!(greet(&client, &addr), 42);
+	}
+
+	#[test]
+	fn pinned_exchange(){
+		let (cert, key, pin) = self_signed("pin");
+		let (server, served_pin) = server_config(&cert, &key).unwrap();
+		assert_eq!(pin, served_pin);
+		let addr = serve_one(server);
+		let client = client_config_pinned(&pin).unwrap();
+		assert_eq!(greet(&client, &addr), 42);
+	}
+
+	#[test]
+	fn wrong_pin_is_refused(){
+		let (cert, key, _) = self_signed("wrongpin");
+		let (server, _) = server_config(&cert, &key).unwrap();
+		let addr = serve_one(server);
+		let client = client_config_pinned(&"00".repeat(32)).unwrap();
+		assert!(connect(&client, &addr).is_err());
+	}
+
+	#[test]
+	fn unknown_ca_is_refused(){
+		let (cert, key, _) = self_signed("unknownca");
+		let (other, _, _) = self_signed("otherca");
+		let (server, _) = server_config(&cert, &key).unwrap();
+		let addr = serve_one(server);
+		let client = client_config_ca(&other).unwrap();
+		assert!(connect(&client, &addr).is_err());
+	}
+}
@@ -0,0 +1,129 @@
+//WebSocket connections, so that browsers can chat too
+//a frame carries one message: JSON in a text frame, or bincode without the size in a binary one,
+//the size isn't needed since a frame has its own boundaries
+use std::io;
+use tungstenite::handshake::{HandshakeError, MidHandshake};
+use tungstenite::handshake::server::{NoCallback, ServerHandshake};
+use tungstenite::protocol::WebSocketConfig;
+use tungstenite::Message;
+use crate::net::{Error, Stream, MAX_BACKLOG, MAX_LINE};
+
+type Handshake = MidHandshake<ServerHandshake<Stream, NoCallback>>;
+
+//a WebSocket over a plain or a TLS stream
+pub enum WebSocket{
+	Handshaking(Handshake), //the HTTP upgrade hasn't completed yet
+	Open(tungstenite::WebSocket<Stream>),
+	Failed //the upgrade has failed, and the stream has been closed with it
+}
+
+//starts the upgrade of a freshly accepted stream, which completes as the client's request arrives
+pub fn accept(stream : Stream) -> Result<Stream, String>{
+	let config = WebSocketConfig::default()
+		.max_message_size(Some(MAX_LINE))
+		.max_frame_size(Some(MAX_LINE))
+		.max_write_buffer_size(MAX_BACKLOG + MAX_LINE);
+	match tungstenite::accept_with_config(stream, Some(config)){
+	    Ok(ws) => {return Ok(Stream::WebSocket(Box::new(WebSocket::Open(ws))));}
+	    Err(HandshakeError::Interrupted(mid)) => {return Ok(Stream::WebSocket(Box::new(WebSocket::Handshaking(mid))));}
+	    Err(HandshakeError::Failure(e)) => {return Err(e.to_string());}
+	}
+}
+
+impl WebSocket{
+	pub fn get_ref(&self) -> Option<&Stream>{
+		match self{
+		    WebSocket::Handshaking(mid) => {return Some(mid.get_ref().get_ref());}
+		    WebSocket::Open(ws) => {return Some(ws.get_ref());}
+		    WebSocket::Failed => {return None;}
+		}
+	}
+
+	pub fn get_mut(&mut self) -> Option<&mut Stream>{
+		match self{
+		    WebSocket::Handshaking(mid) => {return Some(mid.get_mut().get_mut());}
+		    WebSocket::Open(ws) => {return Some(ws.get_mut());}
+		    WebSocket::Failed => {return None;}
+		}
+	}
+
+	//takes the upgrade as far as the arrived bytes let it
+	fn upgrade(&mut self) -> Result<(), Error>{
+		if let WebSocket::Handshaking(_) = self{
+			let mid = match std::mem::replace(self, WebSocket::Failed){
+			    WebSocket::Handshaking(v) => {v}
+			    _ => {unreachable!()}
+			};
+			match mid.handshake(){
+			    Ok(ws) => {*self = WebSocket::Open(ws);}
+			    Err(HandshakeError::Interrupted(mid)) => {*self = WebSocket::Handshaking(mid);}
+			    Err(HandshakeError::Failure(e)) => {return Err(Error::Malformed(e.to_string()));}
+			}
+		}
+		if let WebSocket::Failed = self{
+			return Err(Error::Closed);
+		}
+		return Ok(());
+	}
+
+	//reads all the text and binary frames which have arrived, replying to pings and closing on its own
+	//frames read before an error are put to frames too
+	pub(crate) fn read(&mut self, frames : &mut Vec<Message>) -> Result<(), Error>{
+		self.upgrade()?;
+		let ws = match self{
+		    WebSocket::Open(v) => {v}
+		    _ => {return Ok(());}
+		};
+		let result = loop{
+			match ws.read(){
+			    Ok(Message::Close(_)) => {break Err(Error::Closed);}
+			    Ok(frame @ Message::Text(_)) | Ok(frame @ Message::Binary(_)) => {frames.push(frame);}
+			    Ok(_) => {} //pings are answered by tungstenite
+			    Err(tungstenite::Error::Io(ref e)) if e.kind() == io::ErrorKind::Interrupted => {}
+			    Err(e) => {
+					match into_error(e){
+					    None => {break Ok(());}
+					    Some(e) => {break Err(e);}
+					}
+				}
+			}
+		};
+		//sends the replies tungstenite has queued
+		let _ = ws.flush();
+		return result;
+	}
+
+	//sends a frame, it is buffered if the socket is busy, and written out by flush later
+	pub(crate) fn write(&mut self, frame : Message) -> Result<(), Error>{
+		let ws = match self{
+		    WebSocket::Open(v) => {v}
+		    WebSocket::Handshaking(_) => {return Err(Error::Io("WebSocket isn't open yet".to_string()));}
+		    WebSocket::Failed => {return Err(Error::Closed);}
+		};
+		if let Err(e) = ws.write(frame){
+			if let Some(e) = into_error(e){
+				return Err(e);
+			}
+		}
+		let _ = ws.flush();
+		return Ok(());
+	}
+
+	pub(crate) fn flush(&mut self){
+		if let WebSocket::Open(ws) = self{
+			let _ = ws.flush();
+		}
+	}
+}
+
+//what an error of tungstenite means for the connection, None if it is just that the socket is busy
+fn into_error(e : tungstenite::Error) -> Option<Error>{
+	match e{
+	    tungstenite::Error::Io(ref e) if e.kind() == io::ErrorKind::WouldBlock => {return None;}
+	    tungstenite::Error::Io(_) => {return Some(Error::Closed);}
+	    tungstenite::Error::ConnectionClosed => {return Some(Error::Closed);}
+	    tungstenite::Error::AlreadyClosed => {return Some(Error::Closed);}
+	    tungstenite::Error::WriteBufferFull(_) => {return Some(Error::Backlog);}
+	    e => {return Some(Error::Malformed(e.to_string()));}
+	}
+}
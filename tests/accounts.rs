@@ -0,0 +1,55 @@
+#![allow(clippy::needless_return)]
+//registered users log in with passwords, which are kept hashed, and may not be guessed quickly
+use chat::{ChatClient, ConnectError};
+use common::{chatctl, Server};
+
+mod common;
+
+//a server whose users are registered over the admin socket, so that none of them is connected
+fn start(name : &str, users : &[&str]) -> Server{
+	let server = Server::start_with(name, &["--daemon", "--admin-socket", "admin.sock"]);
+	let socket = server.dir.join("admin.sock").to_str().unwrap().to_string();
+	for username in users{
+		assert_eq!(chatctl(&socket, &["register", username, "secret"]), (true, format!("{} was registered", username)));
+	}
+	return server;
+}
+
+fn refusal(result : Result<ChatClient, ConnectError>) -> String{
+	match result{
+	    Err(ConnectError::Refused(reason)) => {return reason;}
+	    Err(e) => {panic!("The login failed without a reason: {}", e)}
+	    Ok(_) => {panic!("The login went through")}
+	}
+}
+
+#[test]
+fn passwords_are_hashed_with_argon2(){
+	let server = start("argon2", &["alice"]);
+	let accounts = std::fs::read_to_string(server.dir.join("users.db")).unwrap();
+	assert!(accounts.starts_with("alice:$argon2id$"), "{}", accounts);
+	assert!(!accounts.contains("secret"));
+
+	assert_eq!(refusal(ChatClient::login(&server.addr, "alice", "guess", None)), "Wrong password");
+	assert!(ChatClient::login(&server.addr, "alice", "secret", None).is_ok());
+}
+
+#[test]
+fn password_guessing_is_throttled(){
+	let server = start("guessing", &["carol"]);
+	for n in 0..5{
+		assert_eq!(refusal(ChatClient::login(&server.addr, "carol", &format!("guess{}", n), None)), "Wrong password");
+	}
+	//not even the right password is checked for a while
+	assert_eq!(refusal(ChatClient::login(&server.addr, "carol", "secret", None)), "Too many failed logins, try again later");
+}
+
+#[test]
+fn registrations_are_throttled(){
+	let server = Server::start("signups");
+	for n in 0..5{
+		ChatClient::register(&server.addr, &format!("user{}", n), "secret", None).unwrap().quit();
+	}
+	let refused = refusal(ChatClient::register(&server.addr, "user5", "secret", None));
+	assert_eq!(refused, "Too many accounts registered from your address, try again later");
+}
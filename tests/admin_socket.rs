@@ -0,0 +1,109 @@
+#![allow(clippy::needless_return)]
+//a server in daemon mode takes the commands of its terminal through the admin socket
+use chat::admin::{Command, Reply};
+use chat::net::*;
+use chat::ChatClient;
+use common::{chatctl, expect, free_addr, Server};
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::os::unix::net::UnixStream;
+use std::process::Command as Process;
+use std::time::{Duration, Instant};
+
+mod common;
+
+//a daemon with an admin socket, and the path of the socket
+fn start(name : &str) -> (Server, String){
+	let server = Server::start_with(name, &["--daemon", "--admin-socket", "admin.sock"]);
+	let socket = server.dir.join("admin.sock").to_str().unwrap().to_string();
+	return (server, socket);
+}
+
+#[test]
+fn chatctl_runs_commands(){
+	let (server, socket) = start("chatctl");
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+	let mut bob = ChatClient::join(&server.addr, "bob", None).unwrap();
+
+	assert_eq!(chatctl(&socket, &["say", "hello", "all"]), (true, "Sent".to_string()));
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "Server -- hello all")));
+
+	assert_eq!(chatctl(&socket, &["kick", "bob", "for", "spam"]), (true, "bob was kicked".to_string()));
+	assert!(expect(&mut bob, |m| matches!(m, SeMessage::UQuit(q) if q.reason == "for spam")));
+	assert_eq!(chatctl(&socket, &["kick", "bob"]), (false, "No such user: bob".to_string()));
+
+	let (ok, _) = chatctl(&socket, &["fly"]);
+	assert!(!ok);
+
+	assert_eq!(chatctl(&socket, &["stop"]), (true, "Server stopped".to_string()));
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::UQuit(q) if q.reason == "Server closed")));
+	let started_at = Instant::now();
+	while std::path::Path::new(&socket).exists(){
+		assert!(started_at.elapsed() < Duration::from_secs(5), "The socket was left behind");
+		std::thread::sleep(Duration::from_millis(10));
+	}
+}
+
+#[test]
+fn replies_are_structured(){
+	let (_server, socket) = start("replies");
+	let stream = UnixStream::connect(&socket).unwrap();
+	stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+	let mut reader = BufReader::new(stream.try_clone().unwrap());
+	let mut stream = stream;
+
+	//several commands on one connection, each gets its reply
+	let kick = Command::Kick{username : "nobody".to_string(), reason : String::new()};
+	let say = Command::Say("hi".to_string());
+	for command in [&kick, &say]{
+		stream.write_all((serde_json::to_string(command).unwrap() + "\n").as_bytes()).unwrap();
+	}
+	let mut replies = Vec::new();
+	for _ in 0..2{
+		let mut line = String::new();
+		reader.read_line(&mut line).unwrap();
+		replies.push(serde_json::from_str::<Reply>(&line).unwrap());
+	}
+	assert_eq!(replies, vec![Reply::Failed("No such user: nobody".to_string()), Reply::Done("Sent".to_string())]);
+
+	stream.write_all(b"not json\n").unwrap();
+	let mut line = String::new();
+	reader.read_line(&mut line).unwrap();
+	assert!(matches!(serde_json::from_str(&line).unwrap(), Reply::Failed(_)));
+}
+
+#[test]
+fn daemon_needs_an_address(){
+	let output = Process::new(env!("CARGO_BIN_EXE_server")).arg("--daemon").output().unwrap();
+	assert!(!output.status.success());
+	assert!(String::from_utf8_lossy(&output.stderr).contains("listen is needed in daemon mode"));
+}
+
+#[test]
+fn only_the_servers_user_may_use_the_socket(){
+	use std::os::unix::fs::PermissionsExt;
+	let (server, socket) = start("socket-mode");
+	let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+	assert_eq!(mode & 0o777, 0o600);
+	//nothing is left of where the socket was made
+	let files : Vec<_> = std::fs::read_dir(&server.dir).unwrap().map(|f| f.unwrap().file_name()).collect();
+	assert!(!files.iter().any(|f| f.to_string_lossy().starts_with(".admin.sock")), "{:?}", files);
+	assert!(chatctl(&socket, &["banlist"]).0);
+}
+
+#[test]
+fn a_file_in_the_way_of_the_socket_is_kept(){
+	let dir = std::env::temp_dir().join(format!("chat-test-socket-file-{}", std::process::id()));
+	std::fs::create_dir_all(&dir).unwrap();
+	let path = dir.join("admin.sock");
+	std::fs::write(&path, "not a socket").unwrap();
+	let output = Process::new(env!("CARGO_BIN_EXE_server"))
+		.args(["--daemon", "--listen", &free_addr(), "--admin-socket"])
+		.arg(&path)
+		.current_dir(&dir)
+		.output()
+		.unwrap();
+	assert!(!output.status.success());
+	assert!(String::from_utf8_lossy(&output.stderr).contains("exists and isn't a socket"));
+	assert_eq!(std::fs::read_to_string(&path).unwrap(), "not a socket");
+}
@@ -0,0 +1,114 @@
+#![allow(clippy::needless_return)]
+//closing a connection is noticed by the other side at once, not after a timeout
+use chat::net::*;
+use chat::{ChatClient, ConnectError};
+use common::{expect, greeting, Server};
+use std::io::prelude::*;
+use std::time::{Duration, Instant};
+
+mod common;
+
+#[test]
+fn server_announces_a_closed_connection(){
+	let server = Server::start("closed");
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+
+	let mut bob = std::net::TcpStream::connect(&server.addr).unwrap();
+	bob.write_all(&greeting("bob")).unwrap();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "bob joined")));
+	let closed_at = Instant::now();
+	drop(bob);
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "bob lost connection")));
+	assert!(closed_at.elapsed() < MAX_SILENCE / 2);
+
+	//the name is kept for bob to come back
+	let bob = ChatClient::join(&server.addr, "bob", None);
+	assert!(bob.is_err());
+}
+
+#[test]
+fn client_notices_the_server_going_down(){
+	let mut server = Server::start("down");
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+	server.kill();
+	let started_at = Instant::now();
+	let mut reason = None;
+	while reason.is_none() && started_at.elapsed() < Duration::from_secs(5){
+		for mesg in alice.poll(){
+			if let SeMessage::UQuit(m) = mesg{
+				reason = Some(m.reason);
+			}
+		}
+		alice.wait(Some(Duration::from_millis(100))).unwrap();
+	}
+	assert_eq!(reason.as_deref(), Some("Connection lost"));
+	assert!(alice.is_closed());
+	assert!(alice.is_lost());
+}
+
+
+#[test]
+fn resumed_session_gets_rooms_and_missed_messages(){
+	let server = Server::start("resume");
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+	let mut bob = ChatClient::join(&server.addr, "bob", None).unwrap();
+	bob.room("den", RoomAction::Create).unwrap();
+	assert!(expect(&mut bob, |m| matches!(m, SeMessage::Room(r) if r.room == "den")));
+	let token = bob.token();
+	drop(bob);
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "bob lost connection")));
+	alice.say(LOBBY, "where is bob?").unwrap();
+	alice.direct("bob", "psst").unwrap();
+
+	let mut bob = ChatClient::resume(&server.addr, "bob", token, None).unwrap();
+	assert_eq!(bob.token(), token);
+	let mut rooms = Vec::new();
+	let mut missed = Vec::new();
+	let started_at = Instant::now();
+	while missed.len() < 2 && started_at.elapsed() < Duration::from_secs(5){
+		for mesg in bob.poll(){
+			match mesg{
+			    SeMessage::Room(r) => {rooms.push(r.room)}
+			    SeMessage::Mesg(m) if m.mesg == "alice: where is bob?" => {missed.push(m.mesg)}
+			    SeMessage::Direct(m) => {missed.push(m.mesg)}
+			    _ => {}
+			}
+		}
+		bob.wait(Some(Duration::from_millis(100))).unwrap();
+	}
+	assert_eq!(rooms, vec![LOBBY.to_string(), "den".to_string()]);
+	assert_eq!(missed, vec!["alice: where is bob?".to_string(), "psst".to_string()]);
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "bob is back")));
+}
+
+#[test]
+fn unknown_session_is_not_resumed(){
+	let server = Server::start("unknown");
+	match ChatClient::resume(&server.addr, "bob", 42, None){
+	    Err(ConnectError::Refused(_)) => {}
+	    _ => {panic!("A session which never was has been resumed")}
+	}
+}
+
+#[test]
+fn name_of_an_expired_session_is_free_again(){
+	let server = Server::start_with("expired", &["--resume-grace", "1", "--max-users", "2"]);
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+	let bob = ChatClient::join(&server.addr, "bob", None).unwrap();
+	drop(bob);
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "bob left (connection lost)")));
+	//the name is neither in use, nor counted against max_users
+	assert!(ChatClient::join(&server.addr, "bob", None).is_ok());
+}
+
+#[test]
+fn connected_session_is_not_taken_over(){
+	let server = Server::start("takeover");
+	let mut bob = ChatClient::join(&server.addr, "bob", None).unwrap();
+	match ChatClient::resume(&server.addr, "bob", bob.token(), None){
+	    Err(ConnectError::Refused(reason)) => {assert_eq!(reason, "Session is still connected")}
+	    _ => {panic!("The session of a connected user has been taken over")}
+	}
+	bob.say(LOBBY, "still mine").unwrap();
+	assert!(expect(&mut bob, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "bob: still mine")));
+}
@@ -0,0 +1,127 @@
+#![allow(clippy::needless_return, dead_code)]
+//helpers shared by the tests which talk to a running server
+use chat::net::*;
+use chat::ChatClient;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+//a message in the wire format: its size, then the message itself
+pub fn packet<T : serde::Serialize>(mesg : &T) -> Vec<u8>{
+	let mesg = bincode::serialize(mesg).unwrap();
+	let mut pck = bincode::serialize(&(mesg.len() as pck_size_t)).unwrap();
+	pck.extend_from_slice(&mesg);
+	return pck;
+}
+
+//the versions of the protocol a client speaks, which it tells the server before anything else
+pub fn version() -> ClMessage{
+	return ClMessage::Version(ClVersion{version : PROTOCOL_VERSION, oldest : PROTOCOL_VERSION, capabilities : Vec::new()});
+}
+
+//the packets which join the user as a guest: the version, then the hello
+pub fn greeting(username : &str) -> Vec<u8>{
+	let mut pcks = packet(&version());
+	pcks.extend(packet(&ClMessage::Hello(ClHello{username : username.to_string()})));
+	return pcks;
+}
+
+//a server running in a temporary directory, killed when dropped
+pub struct Server{
+	child : Child,
+	pub dir : std::path::PathBuf,
+	pub addr : String
+}
+
+//an address with a port which was free a moment ago
+pub fn free_addr() -> String{
+	let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+	return format!("127.0.0.1:{}", port);
+}
+
+impl Server{
+	pub fn start(name : &str) -> Self{
+		return Server::start_with(name, &[]);
+	}
+
+	//a server started with the command-line arguments
+	pub fn start_with(name : &str, args : &[&str]) -> Self{
+		let dir = std::env::temp_dir().join(format!("chat-test-{}-{}", name, std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let addr = free_addr();
+		let child = Command::new(env!("CARGO_BIN_EXE_server"))
+			.arg("--listen")
+			.arg(&addr)
+			.args(args)
+			.current_dir(&dir)
+			.stdin(Stdio::piped())
+			.stdout(Stdio::null())
+			.stderr(Stdio::null())
+			.spawn()
+			.unwrap();
+		let started_at = Instant::now();
+		while std::net::TcpStream::connect(&addr).is_err(){
+			assert!(started_at.elapsed() < Duration::from_secs(5), "Server hasn't started");
+			std::thread::sleep(Duration::from_millis(10));
+		}
+		//the admin socket is bound after the listener
+		if let Some(i) = args.iter().position(|arg| *arg == "--admin-socket"){
+			while !dir.join(args[i + 1]).exists(){
+				assert!(started_at.elapsed() < Duration::from_secs(5), "Admin socket hasn't been bound");
+				std::thread::sleep(Duration::from_millis(10));
+			}
+		}
+		return Server{child, dir, addr};
+	}
+
+	//takes the server down at once, without it saying goodbye to anyone
+	pub fn kill(&mut self){
+		let _ = self.child.kill();
+		let _ = self.child.wait();
+	}
+}
+
+impl Drop for Server{
+	fn drop(&mut self){
+		self.kill();
+		let _ = std::fs::remove_dir_all(&self.dir);
+	}
+}
+
+//runs chatctl, telling whether the command has succeeded and what it has printed
+pub fn chatctl(socket : &str, args : &[&str]) -> (bool, String){
+	let output = Command::new(env!("CARGO_BIN_EXE_chatctl")).arg("--socket").arg(socket).args(args).output().unwrap();
+	let printed = String::from_utf8_lossy(&output.stdout).to_string() + &String::from_utf8_lossy(&output.stderr);
+	return (output.status.success(), printed.trim().to_string());
+}
+
+//waits for a message the check accepts, skipping others
+pub fn expect(client : &mut ChatClient, mut check : impl FnMut(&SeMessage) -> bool) -> bool{
+	let started_at = Instant::now();
+	while started_at.elapsed() < Duration::from_secs(5){
+		if client.poll().iter().any(&mut check){
+			return true;
+		}
+		client.wait(Some(Duration::from_millis(100))).unwrap();
+	}
+	return false;
+}
+
+//a connection without the help of ChatClient, to send whatever the test needs
+pub fn connect(addr : &str) -> Peer<SeMessage>{
+	let stream = std::net::TcpStream::connect(addr).unwrap();
+	stream.set_nonblocking(true).unwrap();
+	return Peer::new(&0, Stream::Plain(mio::net::TcpStream::from_std(stream)));
+}
+
+//waits for the next message from the server
+pub fn next(peer : &mut Peer<SeMessage>) -> Option<SeMessage>{
+	let started_at = Instant::now();
+	while started_at.elapsed() < Duration::from_secs(5){
+		let _ = peer.get_messages();
+		if let Some(mesg) = peer.messages.pop_front(){
+			return Some(mesg);
+		}
+		std::thread::sleep(Duration::from_millis(10));
+	}
+	return None;
+}
@@ -0,0 +1,114 @@
+#![allow(clippy::needless_return)]
+//the server takes its settings from a configuration file and from flags, and refuses bad ones
+use chat::net::*;
+use chat::{ChatClient, ConnectError};
+use common::{expect, Server};
+use std::process::Command;
+
+mod common;
+
+//a configuration file with the content, removed when dropped
+struct ConfigFile{
+	path : std::path::PathBuf
+}
+
+impl ConfigFile{
+	fn new(name : &str, content : &str) -> Self{
+		let path = std::env::temp_dir().join(format!("chat-test-{}-{}.toml", name, std::process::id()));
+		std::fs::write(&path, content).unwrap();
+		return ConfigFile{path};
+	}
+
+	fn path(&self) -> &str{
+		return self.path.to_str().unwrap();
+	}
+}
+
+impl Drop for ConfigFile{
+	fn drop(&mut self){
+		let _ = std::fs::remove_file(&self.path);
+	}
+}
+
+//what a server started with the arguments says before it exits, it is expected to exit at once
+fn refusal(args : &[&str]) -> String{
+	let output = Command::new(env!("CARGO_BIN_EXE_server")).args(args).output().unwrap();
+	assert!(!output.status.success(), "Server has accepted {:?}", args);
+	return String::from_utf8_lossy(&output.stderr).to_string();
+}
+
+#[test]
+fn settings_of_the_file_are_used(){
+	let file = ConfigFile::new("config", "motd = \"Welcome!\"\nmax_users = 1\nmax_message_len = 5\n");
+	let server = Server::start_with("config", &["--config", file.path()]);
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.room.is_empty() && m.mesg == "Welcome!")));
+
+	match ChatClient::join(&server.addr, "bob", None){
+	    Err(ConnectError::Refused(reason)) => {assert_eq!(reason, "Server is full")}
+	    _ => {panic!("A user has joined a full server")}
+	}
+
+	alice.say(LOBBY, "too long").unwrap();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Error(e) if e.reason == "Message is longer than 5 characters")));
+	alice.say(LOBBY, "short").unwrap();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "alice: short")));
+}
+
+#[test]
+fn flags_override_the_file(){
+	let file = ConfigFile::new("override", "max_users = 1\nadmins = [\"root\"]\n");
+	let server = Server::start_with("override", &["--config", file.path(), "--max-users", "2"]);
+	let _alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+	match ChatClient::join(&server.addr, "root", None){
+	    Err(ConnectError::Refused(reason)) => {assert!(reason.contains("has a role"))}
+	    _ => {panic!("A guest has taken the name of an admin")}
+	}
+	assert!(ChatClient::join(&server.addr, "bob", None).is_ok());
+}
+
+#[test]
+fn bad_settings_are_refused(){
+	assert!(refusal(&["--max-users", "0"]).contains("max_users must be at least 1"));
+	assert!(refusal(&["--max-silence", "often"]).contains("--max-silence needs a number"));
+	assert!(refusal(&["--max-silence", "2"]).contains("max_silence must be more than"));
+	assert!(refusal(&["--listen", "nowhere"]).contains("listen isn't a valid address"));
+	assert!(refusal(&["--cert", "cert.pem"]).contains("Both cert and key"));
+	assert!(refusal(&["--admin", "bad name"]).contains("isn't a valid username"));
+	assert!(refusal(&["--log-level", "loud"]).contains("No such log level"));
+	let too_long = (MAX_MESSAGE_LEN + 1).to_string();
+	assert!(refusal(&["--max-message-len", &too_long]).contains("max_message_len must be between 1 and"));
+	assert!(refusal(&["--config", "/nonexistent/chat.toml"]).contains("Couldn't read"));
+
+	let file = ConfigFile::new("unknown", "max_userz = 5\n");
+	assert!(refusal(&["--config", file.path()]).contains("max_userz"));
+	let file = ConfigFile::new("mistyped", "max_users = \"many\"\n");
+	assert!(refusal(&["--config", file.path()]).contains("max_users"));
+}
+
+#[test]
+fn messages_of_the_longest_allowed_length_are_delivered(){
+	let limit = MAX_MESSAGE_LEN.to_string();
+	let server = Server::start_with("longest", &["--max-message-len", &limit, "--rate-limit", "0"]);
+	//the longest names, of 2-byte characters, and a message of 4-byte ones
+	let (alice_name, bob_name, room) = ("é".repeat(MAX_NAME_LEN / 2), "ü".repeat(MAX_NAME_LEN / 2), "ß".repeat(MAX_NAME_LEN / 2));
+	let mut alice = ChatClient::join(&server.addr, &alice_name, None).unwrap();
+	let mut bob = ChatClient::join(&server.addr, &bob_name, None).unwrap();
+	alice.room(&room, RoomAction::Create).unwrap();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Room(r) if r.room == room)));
+	bob.room(&room, RoomAction::Enter).unwrap();
+	assert!(expect(&mut bob, |m| matches!(m, SeMessage::Room(r) if r.room == room)));
+
+	let mesg = "😀".repeat(MAX_MESSAGE_LEN);
+	alice.say(&room, &mesg).unwrap();
+	let posted = format!("{}: {}", alice_name, mesg);
+	assert!(expect(&mut bob, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == posted)));
+	alice.direct(&bob_name, &mesg).unwrap();
+	assert!(expect(&mut bob, |m| matches!(m, SeMessage::Direct(d) if d.mesg == mesg)));
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Direct(d) if d.mesg == mesg)));
+
+	//names any longer are refused
+	assert!(ChatClient::join(&server.addr, &"a".repeat(MAX_NAME_LEN + 1), None).is_err());
+	alice.room(&"r".repeat(MAX_NAME_LEN + 1), RoomAction::Create).unwrap();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Error(e) if e.reason.starts_with("Room name is longer than"))));
+}
@@ -0,0 +1,97 @@
+#![allow(clippy::needless_return)]
+//the server takes only so many connections, and doesn't wait long for a client to join
+use chat::net::*;
+use chat::{ChatClient, ConnectError, MesgBuilder};
+use common::{expect, Server};
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+mod common;
+
+fn refused(addr : &str) -> String{
+	match ChatClient::join(addr, "late", None){
+	    Err(ConnectError::Refused(reason)) => {return reason;}
+	    Err(e) => {panic!("The connection wasn't refused with a reason: {}", e)}
+	    Ok(_) => {panic!("A connection over the limit was taken")}
+	}
+}
+
+//connects once the server has let go of the connections that count against the limits,
+//such as the one which has checked that it has started
+fn connect(connect : impl Fn() -> Result<ChatClient, ConnectError>) -> ChatClient{
+	let started_at = Instant::now();
+	loop{
+		match connect(){
+		    Ok(client) => {return client;}
+		    Err(_) => {assert!(started_at.elapsed() < Duration::from_secs(5), "Old connections are still counted")}
+		}
+		std::thread::sleep(Duration::from_millis(50));
+	}
+}
+
+fn join(addr : &str, username : &str) -> ChatClient{
+	return connect(|| ChatClient::join(addr, username, None));
+}
+
+#[test]
+fn connections_over_the_limits_are_refused(){
+	let server = Server::start_with("connections", &["--max-connections", "2"]);
+	let _alice = join(&server.addr, "alice");
+	let _bob = join(&server.addr, "bob");
+	assert_eq!(refused(&server.addr), "Server has too many connections");
+
+	let server = Server::start_with("addresses", &["--max-connections-per-ip", "1"]);
+	let alice = join(&server.addr, "alice");
+	assert_eq!(refused(&server.addr), "Too many connections from your address");
+	//the place is taken by the next one once the connection is gone
+	drop(alice);
+	join(&server.addr, "bob");
+}
+
+#[test]
+fn places_are_given_back_however_clients_go(){
+	let server = Server::start_with("places", &["--max-connections-per-ip", "1"]);
+	join(&server.addr, "alice").quit();
+	//a lost connection gives its place back, though its session is kept
+	let bob = join(&server.addr, "bob");
+	let token = bob.token();
+	drop(bob);
+	join(&server.addr, "carol").quit();
+	//and takes one again when it is resumed
+	let _bob = connect(|| ChatClient::resume(&server.addr, "bob", token, None));
+	assert_eq!(refused(&server.addr), "Too many connections from your address");
+}
+
+#[test]
+fn clients_which_dont_join_in_time_are_dropped(){
+	let server = Server::start_with("handshake", &["--handshake-timeout", "1"]);
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+
+	//connects and says nothing
+	let mut idle = TcpStream::connect(&server.addr).unwrap();
+	idle.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+	let started_at = Instant::now();
+	let mut reply = Vec::new();
+	let _ = idle.read_to_end(&mut reply);
+	assert!(started_at.elapsed() < Duration::from_secs(4), "The connection was kept for too long");
+	match MesgBuilder::new().eat::<SeMessage>(&reply).unwrap().pop(){
+	    Some(SeMessage::UQuit(m)) => {assert_eq!(m.reason, "Took too long to join")}
+	    _ => {panic!("The client wasn't told why it was dropped")}
+	}
+
+	//the deadline is only for joining
+	alice.say(LOBBY, "still here").unwrap();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "alice: still here")));
+}
+
+#[test]
+fn dropped_connections_dont_take_every_name(){
+	let server = Server::start_with("dropping", &["--max-users", "4", "--max-connections-per-ip", "2"]);
+	//each dropped connection leaves its session behind, for a while, but an address keeps only so many
+	for n in 0..8{
+		drop(join(&server.addr, &format!("mallory{}", n)));
+	}
+	//the tests connect from one address only, the one which has been dropping its connections
+	join(&server.addr, "alice");
+}
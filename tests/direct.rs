@@ -0,0 +1,59 @@
+#![allow(clippy::needless_return)]
+//direct messages reach their recipient and come back to their sender, and no one else
+use chat::net::*;
+use chat::ChatClient;
+use common::{expect, Server};
+
+mod common;
+
+fn is_direct(m : &SeMessage, from : &str, to : &str, text : &str) -> bool{
+	return matches!(m, SeMessage::Direct(d) if d.from == from && d.to == to && d.mesg == text);
+}
+
+#[test]
+fn direct_messages_reach_only_their_recipient(){
+	let server = Server::start("direct_routing");
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+	let mut bob = ChatClient::join(&server.addr, "bob", None).unwrap();
+	let mut carol = ChatClient::join(&server.addr, "carol", None).unwrap();
+
+	alice.direct("bob", "psst").unwrap();
+	assert!(expect(&mut bob, |m| is_direct(m, "alice", "bob", "psst")));
+	//the sender gets the message back, as a sign it has been delivered
+	assert!(expect(&mut alice, |m| is_direct(m, "alice", "bob", "psst")));
+
+	//carol gets what is posted after it, but not the message itself
+	alice.say(LOBBY, "hi").unwrap();
+	let mut leaked = false;
+	assert!(expect(&mut carol, |m| {
+		leaked |= matches!(m, SeMessage::Direct(_)) || matches!(m, SeMessage::Mesg(m) if m.mesg.contains("psst"));
+		matches!(m, SeMessage::Mesg(m) if m.mesg == "alice: hi")
+	}));
+	assert!(!leaked);
+}
+
+#[test]
+fn messaging_oneself_delivers_once(){
+	let server = Server::start("direct_self");
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+
+	alice.direct("alice", "note").unwrap();
+	alice.say(LOBBY, "hi").unwrap();
+	let mut delivered = 0;
+	assert!(expect(&mut alice, |m| {
+		if is_direct(m, "alice", "alice", "note"){
+			delivered += 1;
+		}
+		matches!(m, SeMessage::Mesg(m) if m.mesg == "alice: hi")
+	}));
+	assert_eq!(delivered, 1);
+}
+
+#[test]
+fn unknown_recipient_is_an_error(){
+	let server = Server::start("direct_unknown");
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+
+	alice.direct("nobody", "hello?").unwrap();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Error(e) if e.reason == "No such user: nobody")));
+}
@@ -0,0 +1,107 @@
+#![allow(clippy::needless_return)]
+//the server logs what happens as JSON lines, rotating the file as it grows
+use chat::net::*;
+use chat::ChatClient;
+use common::{chatctl, expect, Server};
+use serde_json::Value;
+use std::time::{Duration, Instant};
+
+mod common;
+
+//the events in a log file, which is written as the server goes, so the ones expected might take a moment
+fn events(path : &std::path::Path) -> Vec<Value>{
+	let content = std::fs::read_to_string(path).unwrap_or_default();
+	return content.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+}
+
+fn wait_for(path : &std::path::Path, check : impl Fn(&Value) -> bool) -> Value{
+	let started_at = Instant::now();
+	loop{
+		if let Some(event) = events(path).into_iter().find(&check){
+			return event;
+		}
+		assert!(started_at.elapsed() < Duration::from_secs(5), "The event wasn't logged");
+		std::thread::sleep(Duration::from_millis(20));
+	}
+}
+
+#[test]
+fn events_are_logged_without_bodies(){
+	let server = Server::start_with("events", &["--daemon", "--admin-socket", "admin.sock", "--log-file", "server.log", "--no-log-bodies"]);
+	let socket = server.dir.join("admin.sock").to_str().unwrap().to_string();
+	let log = server.dir.join("server.log");
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+	let _bob = ChatClient::join(&server.addr, "bob", None).unwrap();
+
+	let join = wait_for(&log, |e| e["event"] == "join" && e["username"] == "alice");
+	assert_eq!(join["level"], "info");
+	assert!(join["token"].is_u64());
+	assert!(join["addr"].as_str().unwrap().starts_with("127.0.0.1:"));
+	assert!(join["time"].is_f64());
+
+	alice.say(LOBBY, "secret").unwrap();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "alice: secret")));
+	let post = wait_for(&log, |e| e["event"] == "post");
+	assert_eq!(post["length"], 6);
+	assert_eq!(post["room"], LOBBY);
+	assert!(post.get("body").is_none());
+
+	chatctl(&socket, &["kick", "bob", "spam"]);
+	let kick = wait_for(&log, |e| e["event"] == "kick");
+	assert_eq!((&kick["username"], &kick["reason"]), (&Value::from("bob"), &Value::from("spam")));
+	let admin = wait_for(&log, |e| e["event"] == "admin");
+	assert_eq!(admin["by"], "admin socket");
+	assert_eq!(admin["reply"]["Done"], "bob was kicked");
+}
+
+#[test]
+fn the_log_is_rotated(){
+	let server = Server::start_with("rotation", &["--log-file", "server.log", "--log-level", "debug",
+		"--log-max-size", "1000", "--log-keep", "2", "--rate-limit", "0"]);
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+	for n in 0..30{
+		alice.say(LOBBY, &format!("message {}", n)).unwrap();
+		assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == format!("alice: message {}", n))));
+	}
+	wait_for(&server.dir.join("server.log"), |e| e["body"] == "message 29");
+
+	for name in ["server.log", "server.log.1", "server.log.2"]{
+		let path = server.dir.join(name);
+		assert!(std::fs::metadata(&path).unwrap().len() <= 1000, "{} has grown too large", name);
+		assert!(!events(&path).is_empty());
+	}
+	assert!(!server.dir.join("server.log.3").exists());
+	//the oldest events are gone, the newest are in the file being written
+	assert!(events(&server.dir.join("server.log")).iter().all(|e| e["event"] != "join"));
+}
+
+#[test]
+fn bodies_are_kept_out_of_the_output(){
+	let dir = std::env::temp_dir().join(format!("chat-test-output-{}", std::process::id()));
+	std::fs::create_dir_all(&dir).unwrap();
+	let addr = common::free_addr();
+	let mut server = std::process::Command::new(env!("CARGO_BIN_EXE_server"))
+		.args(["--listen", &addr, "--no-log-bodies"])
+		.current_dir(&dir)
+		.stdin(std::process::Stdio::piped())
+		.stdout(std::process::Stdio::piped())
+		.stderr(std::process::Stdio::null())
+		.spawn()
+		.unwrap();
+	let started_at = Instant::now();
+	let mut alice = loop{
+		if let Ok(client) = ChatClient::register(&addr, "alice", "password", None){
+			break client;
+		}
+		assert!(started_at.elapsed() < Duration::from_secs(5), "Server hasn't started");
+		std::thread::sleep(Duration::from_millis(10));
+	};
+	alice.say(LOBBY, "secret").unwrap();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "alice: secret")));
+	server.kill().unwrap();
+	let output = server.wait_with_output().unwrap();
+	let _ = std::fs::remove_dir_all(&dir);
+	let output = String::from_utf8_lossy(&output.stdout);
+	assert!(output.contains("Listening"));
+	assert!(!output.contains("secret"), "{}", output);
+}
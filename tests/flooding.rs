@@ -0,0 +1,81 @@
+#![allow(clippy::needless_return)]
+//clients which send faster than the server allows are warned, then ignored, then dropped
+use chat::net::*;
+use chat::{ChatClient, MesgBuilder};
+use common::{expect, greeting, packet, Server};
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::time::Duration;
+
+mod common;
+
+const TOO_FAST : &str = "You are sending messages too fast, they are dropped";
+
+//joins as the user over a bare connection, returning the token the server has given
+fn join(stream : &mut TcpStream, username : &str) -> Token{
+	stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+	stream.write_all(&greeting(username)).unwrap();
+	let mut builder = MesgBuilder::new();
+	let mut buff = [0; 1024];
+	loop{
+		let n = stream.read(&mut buff).unwrap();
+		assert!(n > 0, "The server has closed the connection");
+		for mesg in builder.eat::<SeMessage>(&buff[..n]).unwrap(){
+			if let SeMessage::Hello(hello) = mesg{
+				return hello.token;
+			}
+		}
+	}
+}
+
+#[test]
+fn a_flooding_peer_is_warned_then_dropped(){
+	let server = Server::start_with("flood", &["--rate-limit", "5", "--rate-burst", "5", "--flood-kick", "20"]);
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+
+	let mut mallory = TcpStream::connect(&server.addr).unwrap();
+	let token = join(&mut mallory, "mallory");
+	//as many messages as one write takes
+	let mut flood = Vec::new();
+	for n in 0..200{
+		flood.extend(packet(&ClMessage::Mesg(ClMesg{token, room : LOBBY.to_string(), mesg : format!("spam {}", n)})));
+	}
+	mallory.write_all(&flood).unwrap();
+
+	let mut reply = Vec::new();
+	let _ = mallory.read_to_end(&mut reply);
+	let mesgs = MesgBuilder::new().eat::<SeMessage>(&reply).unwrap();
+	let warnings = mesgs.iter().filter(|m| matches!(m, SeMessage::Error(e) if e.reason == TOO_FAST)).count();
+	assert_eq!(warnings, 1);
+	assert!(matches!(mesgs.last(), Some(SeMessage::UQuit(q)) if q.reason == "Flooding"));
+
+	//only the burst has got through to everyone else
+	let mut spam = 0;
+	assert!(expect(&mut alice, |m| match m{
+	    SeMessage::Mesg(m) if m.mesg.starts_with("mallory: spam") => {
+			spam += 1;
+			false
+		}
+	    SeMessage::Mesg(m) => {m.mesg == "mallory was disconnected for flooding"}
+	    _ => {false}
+	}));
+	assert!(spam > 0 && spam <= 5, "{} messages of the flood have got through", spam);
+
+	alice.say(LOBBY, "quiet again").unwrap();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "alice: quiet again")));
+}
+
+#[test]
+fn connections_from_one_address_share_a_limit(){
+	let server = Server::start_with("address", &["--rate-limit", "0", "--ip-rate-limit", "1", "--ip-rate-burst", "10", "--flood-kick", "0"]);
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+	let mut bob = ChatClient::join(&server.addr, "bob", None).unwrap();
+
+	for n in 0..20{
+		alice.say(LOBBY, &format!("spam {}", n)).unwrap();
+	}
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Error(e) if e.reason == TOO_FAST)));
+	//bob has sent nothing yet, but alice has used up what their address may send
+	bob.say(LOBBY, "hi").unwrap();
+	assert!(expect(&mut bob, |m| matches!(m, SeMessage::Error(e) if e.reason == TOO_FAST)));
+}
@@ -0,0 +1,76 @@
+#![allow(clippy::needless_return)]
+//past messages of a room are kept on disk, and sent back as far as they fit into a packet
+use chat::net::*;
+use chat::ChatClient;
+use common::{expect, Server};
+
+mod common;
+
+//the messages of the history the client is sent next
+fn history(client : &mut ChatClient) -> Vec<String>{
+	let mut mesgs = Vec::new();
+	assert!(expect(client, |m| match m{
+	    SeMessage::History(h) => {
+			mesgs = h.mesgs.clone();
+			true
+		}
+	    _ => {false}
+	}));
+	return mesgs;
+}
+
+#[test]
+fn history_is_read_from_the_end(){
+	//a history longer than what is read at once, left by an earlier run of the server
+	let dir = std::env::temp_dir().join(format!("chat-test-longhistory-{}", std::process::id()));
+	std::fs::create_dir_all(dir.join("history")).unwrap();
+	let lines : Vec<String> = (0..20000).map(|n| format!("old: message {} with a line\\nbreak", n)).collect();
+	std::fs::write(dir.join("history").join("lobby.log"), lines.join("\n") + "\n").unwrap();
+	let server = Server::start("longhistory");
+
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+	let on_join = history(&mut alice);
+	assert_eq!(on_join.len(), 20);
+	assert_eq!(on_join[19], "old: message 19999 with a line\nbreak");
+	alice.history(LOBBY, 3).unwrap();
+	assert_eq!(history(&mut alice), vec![
+		"old: message 19997 with a line\nbreak".to_string(),
+		"old: message 19998 with a line\nbreak".to_string(),
+		"old: message 19999 with a line\nbreak".to_string()]);
+}
+
+#[test]
+fn only_posts_are_kept(){
+	let server = Server::start("posts");
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+	let bob = ChatClient::join(&server.addr, "bob", None).unwrap();
+	alice.say(LOBBY, "hi").unwrap();
+	bob.quit();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "bob left")));
+
+	//the notices of who came and went are of no use later
+	alice.history(LOBBY, 10).unwrap();
+	assert_eq!(history(&mut alice), vec!["alice: hi".to_string()]);
+}
+
+#[test]
+fn history_is_trimmed_to_fit_a_packet(){
+	let server = Server::start_with("bighistory", &["--rate-limit", "0"]);
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+	let long = "x".repeat(4000);
+	for n in 0..30{
+		alice.say(LOBBY, &format!("{} {}", n, long)).unwrap();
+		assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg.starts_with(&format!("alice: {} ", n)))));
+	}
+	let newest = format!("alice: 29 {}", long);
+
+	//the newest of them are sent, rather than nothing at all
+	let mut bob = ChatClient::join(&server.addr, "bob", None).unwrap();
+	let on_join = history(&mut bob);
+	assert!(!on_join.is_empty() && on_join.len() < 20, "{} messages were sent", on_join.len());
+	assert_eq!(on_join.last(), Some(&newest));
+	bob.history(LOBBY, MAX_HISTORY).unwrap();
+	let asked = history(&mut bob);
+	assert!(!asked.is_empty() && asked.len() < 20, "{} messages were sent", asked.len());
+	assert!(asked.contains(&newest));
+}
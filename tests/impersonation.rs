@@ -0,0 +1,63 @@
+#![allow(clippy::needless_return)]
+//a user is who his connection is, whatever token the requests carry
+use chat::net::*;
+use chat::ChatClient;
+use common::{connect, expect, next, version, Server};
+
+mod common;
+
+//joins without the help of ChatClient, to be able to send whatever token
+fn join_raw(addr : &str, username : &str) -> (Peer<SeMessage>, Token){
+	let mut peer = connect(addr);
+	peer.send(&version()).unwrap();
+	peer.send(&ClMessage::Hello(ClHello{username : username.to_string()})).unwrap();
+	assert!(matches!(next(&mut peer), Some(SeMessage::Version(_))));
+	let token = match next(&mut peer){
+	    Some(SeMessage::Hello(hello)) => {hello.token}
+	    _ => {panic!("No hello from the server")}
+	};
+	return (peer, token);
+}
+
+fn refused(peer : &mut Peer<SeMessage>) -> bool{
+	while let Some(mesg) = next(peer){
+		if let SeMessage::Error(e) = mesg{
+			return e.reason == "Token doesn't match the connection";
+		}
+	}
+	return false;
+}
+
+#[test]
+fn foreign_token_is_refused(){
+	let server = Server::start("impersonation");
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+	let (mut mallory, _) = join_raw(&server.addr, "mallory");
+
+	let mesg = ClMesg{token : alice.token(), room : LOBBY.to_string(), mesg : "I am alice".to_string()};
+	mallory.send(&ClMessage::Mesg(mesg)).unwrap();
+	assert!(refused(&mut mallory));
+	mallory.send(&ClMessage::IQuit(alice.token())).unwrap();
+	assert!(refused(&mut mallory));
+
+	//alice is still there, and has heard nothing in her name
+	alice.say(LOBBY, "it is me").unwrap();
+	let mut heard = Vec::new();
+	assert!(expect(&mut alice, |m| {
+		if let SeMessage::Mesg(m) = m{
+			heard.push(m.mesg.clone());
+		}
+		matches!(m, SeMessage::Mesg(m) if m.mesg == "alice: it is me")
+	}));
+	assert!(!heard.iter().any(|m| m.contains("I am alice")));
+}
+
+#[test]
+fn messages_are_signed_with_the_connection_name(){
+	let server = Server::start("signed");
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+	let (mut mallory, token) = join_raw(&server.addr, "mallory");
+	let mesg = ClMesg{token, room : LOBBY.to_string(), mesg : "hello".to_string()};
+	mallory.send(&ClMessage::Mesg(mesg)).unwrap();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "mallory: hello")));
+}
@@ -0,0 +1,97 @@
+#![allow(clippy::needless_return)]
+//clients speaking JSON are served alongside the ones speaking bincode
+use chat::net::*;
+use chat::{ChatClient, Encoding, JsonBuilder};
+use common::{expect, Server};
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::time::Duration;
+
+mod common;
+
+#[test]
+fn lines_split_anywhere_arrive_whole(){
+	let text = "{\"Ping\":1}\r\n\n{\"IWantInfo\":2}\n{\"Hello\":{\"username\":\"bob\"}}\n";
+	for chunk in 1..text.len(){
+		let mut builder = JsonBuilder::new();
+		let mut mesgs : Vec<ClMessage> = Vec::new();
+		for part in text.as_bytes().chunks(chunk){
+			mesgs.extend(builder.eat(part).unwrap());
+		}
+		assert_eq!(mesgs.len(), 3);
+		assert!(matches!(&mesgs[2], ClMessage::Hello(h) if h.username == "bob"));
+	}
+}
+
+#[test]
+fn bad_lines_are_refused(){
+	let mut builder = JsonBuilder::new();
+	assert!(builder.eat::<ClMessage>(b"{\"Fly\":1}\n").is_err());
+	let mut builder = JsonBuilder::new();
+	assert!(builder.eat::<ClMessage>(b"not json\n").is_err());
+	let mut builder = JsonBuilder::new();
+	assert!(builder.eat::<ClMessage>(&vec![b'x'; MAX_LINE + 1]).is_err());
+}
+
+//reads lines from the server till one the check accepts
+fn read_until(reader : &mut impl BufRead, check : impl Fn(&SeMessage) -> bool) -> bool{
+	let mut line = String::new();
+	while reader.read_line(&mut line).unwrap_or(0) > 0{
+		let mesg : SeMessage = serde_json::from_str(&line).unwrap();
+		if check(&mesg){
+			return true;
+		}
+		line.clear();
+	}
+	return false;
+}
+
+#[test]
+fn json_and_bincode_clients_talk(){
+	let server = Server::start("json");
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+
+	//the way one would do it with netcat
+	let mut bob = std::net::TcpStream::connect(&server.addr).unwrap();
+	bob.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+	let mut reader = BufReader::new(bob.try_clone().unwrap());
+	bob.write_all(b"{\"Version\":{\"version\":3,\"oldest\":3,\"capabilities\":[]}}\n").unwrap();
+	bob.write_all(b"{\"Hello\":{\"username\":\"bob\"}}\n").unwrap();
+	let mut users = None;
+	assert!(read_until(&mut reader, |m| matches!(m, SeMessage::Hello(_))));
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "bob joined")));
+
+	alice.say(LOBBY, "hi bob").unwrap();
+	assert!(read_until(&mut reader, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "alice: hi bob")));
+
+	alice.info().unwrap();
+	assert!(expect(&mut alice, |m| {
+		if let SeMessage::Info(i) = m{
+			users = Some(i.users.len());
+		}
+		matches!(m, SeMessage::Info(_))
+	}));
+	assert_eq!(users, Some(2));
+}
+
+//as some tools start their output
+#[test]
+fn json_may_start_with_a_bom_and_blank_lines(){
+	let server = Server::start("jsonbom");
+	let mut bob = std::net::TcpStream::connect(&server.addr).unwrap();
+	bob.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+	let mut reader = BufReader::new(bob.try_clone().unwrap());
+	bob.write_all(b"\xEF\xBB\xBF\r\n  {\"Version\":{\"version\":3,\"oldest\":3,\"capabilities\":[]}}\n").unwrap();
+	bob.write_all(b"{\"Hello\":{\"username\":\"bob\"}}\n").unwrap();
+	assert!(read_until(&mut reader, |m| matches!(m, SeMessage::Hello(_))));
+}
+
+#[test]
+fn chat_client_speaks_json(){
+	let server = Server::start("jsonclient");
+	let hello = ClMessage::Hello(ClHello{username : "carol".to_string()});
+	let mut carol = ChatClient::connect_encoded(&server.addr, "carol", &hello, None, Encoding::Json).unwrap();
+	assert_eq!(carol.version(), PROTOCOL_VERSION);
+	carol.say(LOBBY, "in json").unwrap();
+	assert!(expect(&mut carol, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "carol: in json")));
+}
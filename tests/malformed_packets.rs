@@ -0,0 +1,163 @@
+#![allow(clippy::needless_return)]
+//feeds the message builder with garbage, and the server with a client which sends it
+use chat::net::*;
+use chat::{ChatClient, MesgBuilder};
+use common::{expect, greeting, packet, Server};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use std::io::prelude::*;
+use std::time::Duration;
+
+mod common;
+
+const ROUNDS : usize = 2000;
+
+fn say(n : usize) -> ClMessage{
+	return ClMessage::Mesg(ClMesg{
+		token : n as Token,
+		room : LOBBY.to_string(),
+		mesg : "x".repeat(n)
+	});
+}
+
+//feeds bytes to a builder in chunks of random size, until all are eaten or an error comes
+fn feed<T>(rng : &mut StdRng, bytes : &[u8]) -> Result<Vec<T>, Error>
+where T : for<'a> serde::Deserialize<'a>
+{
+	let mut builder = MesgBuilder::new();
+	let mut mesgs = Vec::new();
+	let mut pos = 0;
+	while pos < bytes.len(){
+		let end = (pos + rng.gen_range(1, 64)).min(bytes.len());
+		mesgs.extend(builder.eat::<T>(&bytes[pos..end])?);
+		pos = end;
+	}
+	return Ok(mesgs);
+}
+
+#[test]
+fn garbage_doesnt_panic(){
+	let mut rng = StdRng::seed_from_u64(1);
+	for _ in 0..ROUNDS{
+		let len = rng.gen_range(0, 512);
+		let bytes : Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+		let _ = feed::<ClMessage>(&mut rng, &bytes);
+		let _ = feed::<SeMessage>(&mut rng, &bytes);
+	}
+}
+
+#[test]
+fn damaged_packets_dont_panic(){
+	let mut rng = StdRng::seed_from_u64(2);
+	for _ in 0..ROUNDS{
+		let mut bytes = packet(&say(rng.gen_range(0, 100)));
+		for _ in 0..rng.gen_range(1, 4){
+			let pos = rng.gen_range(0, bytes.len());
+			bytes[pos] = rng.gen();
+		}
+		let _ = feed::<ClMessage>(&mut rng, &bytes);
+	}
+}
+
+#[test]
+fn packets_split_anywhere_arrive_whole(){
+	let mut rng = StdRng::seed_from_u64(3);
+	let mut bytes = Vec::new();
+	for n in 0..200{
+		bytes.extend(packet(&say(n)));
+	}
+	let mesgs = feed::<ClMessage>(&mut rng, &bytes).unwrap();
+	assert_eq!(mesgs.len(), 200);
+	for (n, mesg) in mesgs.iter().enumerate(){
+		match mesg{
+		    ClMessage::Mesg(m) => {
+				assert_eq!(m.token, n as Token);
+				assert_eq!(m.mesg.len(), n);
+			}
+		    _ => {panic!("Got another message than was sent")}
+		}
+	}
+}
+
+#[test]
+fn empty_packet_is_refused(){
+	let mut builder = MesgBuilder::new();
+	assert!(builder.eat::<ClMessage>(&[0, 0]).is_err());
+}
+
+#[test]
+fn unknown_message_is_refused(){
+	let mut builder = MesgBuilder::new();
+	assert!(builder.eat::<ClMessage>(&[4, 0, 200, 0, 0, 0]).is_err());
+}
+
+#[test]
+fn bytes_after_the_message_are_refused(){
+	let mut bytes = bincode::serialize(&ClMessage::Ping(7)).unwrap();
+	bytes.push(0);
+	let mut pck = bincode::serialize(&(bytes.len() as pck_size_t)).unwrap();
+	pck.extend(bytes);
+	let mut builder = MesgBuilder::new();
+	assert!(builder.eat::<ClMessage>(&pck).is_err());
+}
+
+#[test]
+fn message_claiming_more_than_it_has_is_refused(){
+	//a string which says it is 4 gigabytes long
+	let mut bytes = bincode::serialize(&ClMessage::Hello(ClHello{username : String::new()})).unwrap();
+	let len = bytes.len();
+	bytes[len - 8..].copy_from_slice(&u64::from(u32::MAX).to_le_bytes());
+	let mut pck = bincode::serialize(&(bytes.len() as pck_size_t)).unwrap();
+	pck.extend(bytes);
+	let mut builder = MesgBuilder::new();
+	assert!(builder.eat::<ClMessage>(&pck).is_err());
+}
+
+#[test]
+fn too_large_message_isnt_sent(){
+	let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+	let stream = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+	stream.set_nonblocking(true).unwrap();
+	let mut peer : Peer<SeMessage> = Peer::new(&0, Stream::Plain(mio::net::TcpStream::from_std(stream)));
+	match peer.send(&say(pck_size_t::MAX as usize)){
+	    Err(Error::TooLarge(_)) => {}
+	    _ => {panic!("A too large message was sent")}
+	}
+	assert!(peer.send(&say(10)).is_ok());
+}
+
+#[test]
+fn server_drops_only_the_offender(){
+	let server = Server::start("offender");
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+
+	let mut rogue = std::net::TcpStream::connect(&server.addr).unwrap();
+	rogue.write_all(&[4, 0, 200, 0, 0, 0]).unwrap();
+	rogue.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+	let mut reply = Vec::new();
+	let _ = rogue.read_to_end(&mut reply);
+	let mut builder = MesgBuilder::new();
+	match builder.eat::<SeMessage>(&reply).unwrap().pop(){
+	    Some(SeMessage::UQuit(m)) => {assert!(m.reason.starts_with("Protocol error"))}
+	    _ => {panic!("The rogue client wasn't told why it was dropped")}
+	}
+
+	alice.say(LOBBY, "still here").unwrap();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "alice: still here")));
+}
+
+#[test]
+fn server_drops_a_joined_user_sending_garbage(){
+	let server = Server::start("joined");
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+
+	let mut mallory = std::net::TcpStream::connect(&server.addr).unwrap();
+	mallory.write_all(&greeting("mallory")).unwrap();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "mallory joined")));
+	mallory.write_all(&[0, 0]).unwrap();
+	let dropped = "mallory was disconnected for a protocol error";
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == dropped)));
+
+	alice.info().unwrap();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Info(i) if i.users == vec!["alice".to_string()])));
+}
@@ -0,0 +1,77 @@
+#![allow(clippy::needless_return)]
+//the server tells Prometheus how it is doing over HTTP
+use chat::net::*;
+use chat::ChatClient;
+use common::{expect, free_addr, Server};
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+mod common;
+
+//asks for the path, giving the whole response
+fn get(addr : &str, path : &str) -> String{
+	let mut stream = TcpStream::connect(addr).unwrap();
+	stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+	write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", path, addr).unwrap();
+	let mut response = String::new();
+	stream.read_to_string(&mut response).unwrap();
+	return response;
+}
+
+//the value of a sample, found by its name and labels
+fn sample(response : &str, name : &str) -> f64{
+	let line = response.lines().find(|line| line.rsplit_once(' ').map(|(n, _)| n) == Some(name));
+	return line.unwrap_or_else(|| panic!("{} isn't served", name)).rsplit_once(' ').unwrap().1.parse().unwrap();
+}
+
+#[test]
+fn metrics_are_served(){
+	let metrics = free_addr();
+	let server = Server::start_with("metrics", &["--metrics", &metrics]);
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+	alice.say(LOBBY, "hi").unwrap();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "alice: hi")));
+
+	let response = get(&metrics, "/metrics");
+	assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+	assert!(response.contains("# TYPE chat_loop_seconds histogram"));
+	assert_eq!(sample(&response, "chat_peers{state=\"chatting\"}"), 1.0);
+	assert_eq!(sample(&response, "chat_rooms"), 1.0);
+	assert!(sample(&response, "chat_messages_received_total") >= 2.0);
+	assert!(sample(&response, "chat_messages_sent_total") >= 2.0);
+	assert!(sample(&response, "chat_bytes_received_total") > 0.0);
+	assert!(sample(&response, "chat_broadcasts_total") >= 1.0);
+	assert!(sample(&response, "chat_loop_seconds_count") > 0.0);
+
+	assert!(get(&metrics, "/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+}
+
+//whether the server has closed the connection before the read has timed out
+fn closed(conn : &mut TcpStream) -> bool{
+	match conn.read(&mut [0; 16]){
+	    Ok(n) => {return n == 0;}
+	    Err(e) => {return e.kind() == std::io::ErrorKind::ConnectionReset;}
+	}
+}
+
+#[test]
+fn idle_metrics_connections_are_limited_and_closed(){
+	let metrics = free_addr();
+	let _server = Server::start_with("idlemetrics", &["--metrics", &metrics]);
+	let started_at = Instant::now();
+	let idle : Vec<TcpStream> = (0..16).map(|_| TcpStream::connect(&metrics).unwrap()).collect();
+
+	//one too many is closed at once
+	let mut extra = TcpStream::connect(&metrics).unwrap();
+	extra.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+	assert!(closed(&mut extra));
+
+	//the ones which never ask are closed after a while, and make room for scrapers
+	for mut conn in idle{
+		conn.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+		assert!(closed(&mut conn));
+	}
+	assert!(started_at.elapsed() < Duration::from_secs(8), "Idle connections were kept too long");
+	assert!(get(&metrics, "/metrics").starts_with("HTTP/1.1 200 OK\r\n"));
+}
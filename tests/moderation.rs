@@ -0,0 +1,207 @@
+#![allow(clippy::needless_return)]
+//privileged users moderate the chat from their clients, as far as their roles let them
+use chat::admin::{Command, Reply, Role};
+use chat::net::*;
+use chat::{ChatClient, ConnectError};
+use common::{chatctl, expect, Server};
+
+mod common;
+
+fn reply(client : &mut ChatClient, command : &str) -> Reply{
+	client.admin(Command::parse(command).unwrap()).unwrap();
+	let mut reply = None;
+	assert!(expect(client, |m| match m{
+	    SeMessage::Reply(r) => {
+			reply = Some(r.clone());
+			true
+		}
+	    _ => {false}
+	}));
+	return reply.unwrap();
+}
+
+fn done(text : &str) -> Reply{
+	return Reply::Done(text.to_string());
+}
+
+fn failed(text : &str) -> Reply{
+	return Reply::Failed(text.to_string());
+}
+
+//a server owned by root, whose account is created over the admin socket, as clients may not register it
+fn owned(name : &str) -> (Server, ChatClient){
+	let server = Server::start_with(name, &["--owner", "root", "--daemon", "--admin-socket", "admin.sock"]);
+	let socket = server.dir.join("admin.sock").to_str().unwrap().to_string();
+	assert_eq!(chatctl(&socket, &["register", "root", "secret"]), (true, "root was registered".to_string()));
+	let root = ChatClient::login(&server.addr, "root", "secret", None).unwrap();
+	return (server, root);
+}
+
+#[test]
+fn roles_limit_what_users_may_do(){
+	let (server, mut root) = owned("roles");
+	let mut alice = ChatClient::register(&server.addr, "alice", "secret", None).unwrap();
+	let mut bob = ChatClient::join(&server.addr, "bob", None).unwrap();
+
+	assert_eq!(reply(&mut bob, "kick alice"), failed("Only a moderator or higher may kick"));
+	assert_eq!(reply(&mut root, "role bob moderator"), failed("bob isn't registered, only registered users may have roles"));
+	assert_eq!(reply(&mut root, "role alice moderator"), done("alice is now a moderator"));
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "You are now a moderator")));
+
+	assert_eq!(reply(&mut alice, "mute bob"), done("bob was muted"));
+	bob.say(LOBBY, "spam").unwrap();
+	assert!(expect(&mut bob, |m| matches!(m, SeMessage::Error(e) if e.reason == "You are muted")));
+	assert_eq!(reply(&mut alice, "unmute bob"), done("bob was unmuted"));
+	bob.say(LOBBY, "sorry").unwrap();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "bob: sorry")));
+
+	assert_eq!(reply(&mut alice, "ban bob"), failed("Only an admin or higher may ban"));
+	assert_eq!(reply(&mut alice, "kick root"), failed("root has a role as high as yours"));
+	assert_eq!(reply(&mut alice, "role bob admin"), failed("Only an admin or higher may role"));
+	assert_eq!(reply(&mut alice, "kick bob flooding"), done("bob was kicked"));
+	assert!(expect(&mut bob, |m| matches!(m, SeMessage::UQuit(q) if q.reason == "flooding")));
+}
+
+#[test]
+fn banned_users_cant_come_back(){
+	let (server, mut root) = owned("bans");
+	let mut carol = ChatClient::join(&server.addr, "carol", None).unwrap();
+
+	assert_eq!(reply(&mut root, "ban carol trolling"), done("carol was banned"));
+	assert!(expect(&mut carol, |m| matches!(m, SeMessage::UQuit(q) if q.reason == "You are banned: trolling")));
+	match ChatClient::join(&server.addr, "carol", None){
+	    Err(ConnectError::Refused(reason)) => {assert_eq!(reason, "You are banned: trolling")}
+	    _ => {panic!("A banned user has come back")}
+	}
+	assert_eq!(reply(&mut root, "unban carol"), done("carol was unbanned"));
+	assert!(ChatClient::join(&server.addr, "carol", None).is_ok());
+
+	let mut dave = ChatClient::join(&server.addr, "dave", None).unwrap();
+	assert_eq!(reply(&mut root, "ban dave 10m flooding"), done("dave was banned for 10m"));
+	assert!(expect(&mut dave, |m| matches!(m, SeMessage::UQuit(q) if q.reason.starts_with("You are banned for ") && q.reason.ends_with(": flooding"))));
+	match reply(&mut root, "banlist"){
+	    Reply::Done(list) => {assert!(list.starts_with("dave - banned by root for ") && list.ends_with(": flooding"), "{}", list)}
+	    r => {panic!("{:?}", r)}
+	}
+	assert_eq!(reply(&mut root, "unban dave"), done("dave was unbanned"));
+	assert_eq!(reply(&mut root, "banlist"), done("No one is banned"));
+	assert_eq!(reply(&mut root, "unban dave"), failed("dave isn't banned"));
+	assert_eq!(reply(&mut root, "ban 300.1.2.3"), failed("300.1.2.3 is neither a username nor an address"));
+}
+
+#[test]
+fn bans_respect_roles(){
+	let (server, mut root) = owned("ranges");
+	let mut alice = ChatClient::register(&server.addr, "alice", "secret", None).unwrap();
+	assert_eq!(reply(&mut root, "role alice admin"), done("alice is now an admin"));
+
+	//root is connected from the range, and the broadest ranges are for the owner only
+	match reply(&mut alice, "ban 127.0.0.0/16"){
+	    Reply::Failed(reason) => {assert!(reason.ends_with("is connected from 127.0.0.0/16 and has a role as high as yours"), "{}", reason)}
+	    r => {panic!("{:?}", r)}
+	}
+	assert_eq!(reply(&mut alice, "ban 0.0.0.0/0"), failed("Only an owner may ban a range as broad as 0.0.0.0/0"));
+	assert_eq!(reply(&mut alice, "ban 10.0.0.0/8"), failed("Only an owner may ban a range as broad as 10.0.0.0/8"));
+	assert_eq!(reply(&mut alice, "ban 10.1.0.0/16"), done("10.1.0.0/16 was banned"));
+	assert_eq!(reply(&mut root, "ban 0.0.0.0/0"), failed("root is connected from 0.0.0.0/0 and has a role as high as yours"));
+
+	//a banned name can't be taken by renaming either
+	let mut bob = ChatClient::join(&server.addr, "bob", None).unwrap();
+	assert_eq!(reply(&mut alice, "ban carol"), done("carol was banned"));
+	bob.rename("carol").unwrap();
+	assert!(expect(&mut bob, |m| matches!(m, SeMessage::Error(e) if e.reason == "Username is banned")));
+}
+
+#[test]
+fn bans_of_addresses_outlive_the_server(){
+	//bans left by an earlier run of the server, one of them is over
+	let dir = std::env::temp_dir().join(format!("chat-test-addresses-{}", std::process::id()));
+	std::fs::create_dir_all(&dir).unwrap();
+	std::fs::write(dir.join("bans.db"), "{\"target\":\"erin\",\"reason\":\"old\",\"by\":\"root\",\"until\":1}
+{\"target\":\"frank\",\"reason\":\"spam\",\"by\":\"root\",\"until\":null}\n").unwrap();
+	let server = Server::start_with("addresses", &["--daemon", "--admin-socket", "admin.sock"]);
+	let socket = server.dir.join("admin.sock").to_str().unwrap().to_string();
+
+	let mut erin = ChatClient::join(&server.addr, "erin", None).unwrap();
+	match ChatClient::join(&server.addr, "frank", None){
+	    Err(ConnectError::Refused(reason)) => {assert_eq!(reason, "You are banned: spam")}
+	    _ => {panic!("A banned user has come back")}
+	}
+
+	//the whole range is banned, whoever is connected from it is kicked
+	assert_eq!(chatctl(&socket, &["ban", "127.1.2.3/8", "1h", "flood"]), (true, "127.0.0.0/8 was banned for 1h".to_string()));
+	assert!(expect(&mut erin, |m| matches!(m, SeMessage::UQuit(q) if q.reason.starts_with("You are banned for ") && q.reason.ends_with(": flood"))));
+	assert!(ChatClient::join(&server.addr, "grace", None).is_err());
+	let (ok, list) = chatctl(&socket, &["banlist"]);
+	assert!(ok);
+	assert!(list.starts_with("frank - banned by root for good: spam\n127.0.0.0/8 - banned by admin socket for "), "{}", list);
+
+	assert_eq!(chatctl(&socket, &["unban", "127.0.0.0/8"]), (true, "127.0.0.0/8 was unbanned".to_string()));
+	assert!(ChatClient::join(&server.addr, "grace", None).is_ok());
+	//the ban which is over has been dropped from the file with the change
+	let bans = std::fs::read_to_string(server.dir.join("bans.db")).unwrap();
+	assert_eq!(bans.lines().count(), 1);
+	assert!(bans.contains("frank"));
+}
+
+#[test]
+fn roles_and_commands_are_kept_on_disk(){
+	let server = Server::start_with("audit", &["--owner", "root", "--daemon", "--admin-socket", "admin.sock"]);
+	let socket = server.dir.join("admin.sock").to_str().unwrap().to_string();
+	//a guest may not take the name of a privileged user, even before the user has registered, nor may a client register it
+	match ChatClient::join(&server.addr, "root", None){
+	    Err(ConnectError::Refused(reason)) => {assert!(reason.contains("has a role"))}
+	    _ => {panic!("A guest has taken the name of the owner")}
+	}
+	match ChatClient::register(&server.addr, "root", "guessed", None){
+	    Err(ConnectError::Refused(reason)) => {assert!(reason.contains("has a role"))}
+	    _ => {panic!("A client has registered the name of the owner")}
+	}
+	assert_eq!(chatctl(&socket, &["register", "root", "secret"]), (true, "root was registered".to_string()));
+	let mut root = ChatClient::login(&server.addr, "root", "secret", None).unwrap();
+	let _dave = ChatClient::register(&server.addr, "dave", "secret", None).unwrap();
+	assert_eq!(reply(&mut root, "role dave admin"), done("dave is now an admin"));
+	assert_eq!(reply(&mut root, "kick nobody"), failed("No such user: nobody"));
+
+	let roles = std::fs::read_to_string(server.dir.join("roles.db")).unwrap();
+	assert_eq!(roles, "dave:admin\n");
+
+	let audit = std::fs::read_to_string(server.dir.join("audit.log")).unwrap();
+	let entries : Vec<serde_json::Value> = audit.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+	assert_eq!(entries.len(), 3);
+	//passwords are left out
+	assert_eq!(entries[0]["by"], "admin socket");
+	assert_eq!(entries[0]["command"]["Register"]["password"], "***");
+	assert_eq!(entries[1]["by"], "root");
+	assert_eq!(entries[1]["command"]["SetRole"]["role"], serde_json::to_value(Role::Admin).unwrap());
+	assert_eq!(entries[2]["reply"]["Failed"], "No such user: nobody");
+}
+
+#[test]
+fn mutes_expire_and_slow_mode_paces_posts(){
+	let (server, mut root) = owned("slow");
+	let mut bob = ChatClient::join(&server.addr, "bob", None).unwrap();
+
+	assert_eq!(reply(&mut root, "mute bob 1s"), done("bob was muted for 1s"));
+	assert!(expect(&mut bob, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "You were muted for 1s by root")));
+	bob.say(LOBBY, "spam").unwrap();
+	assert!(expect(&mut bob, |m| matches!(m, SeMessage::Error(e) if e.reason == "You are muted for 1s more")));
+	std::thread::sleep(std::time::Duration::from_millis(1100));
+	bob.say(LOBBY, "back").unwrap();
+	assert!(expect(&mut root, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "bob: back")));
+
+	assert_eq!(reply(&mut root, "slow nowhere 1m"), failed("No such room: nowhere"));
+	assert_eq!(reply(&mut root, "slow lobby 1m"), done("Slow mode is on in lobby, each user may post once in 1m"));
+	bob.say(LOBBY, "one").unwrap();
+	assert!(expect(&mut root, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "bob: one")));
+	bob.say(LOBBY, "two").unwrap();
+	assert!(expect(&mut bob, |m| matches!(m, SeMessage::Error(e) if e.reason.starts_with("Slow mode is on in lobby, you may post again in "))));
+	//moderators aren't slowed down
+	root.say(LOBBY, "first").unwrap();
+	root.say(LOBBY, "second").unwrap();
+	assert!(expect(&mut bob, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "root: second")));
+
+	assert_eq!(reply(&mut root, "slow lobby off"), done("Slow mode is off in lobby"));
+	bob.say(LOBBY, "three").unwrap();
+	assert!(expect(&mut root, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "bob: three")));
+}
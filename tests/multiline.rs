@@ -0,0 +1,40 @@
+#![allow(clippy::needless_return)]
+//messages may have many lines, which reach everyone as they were written, up to the length the server allows
+use chat::net::*;
+use chat::{ChatClient, Encoding};
+use common::{expect, Server};
+
+mod common;
+
+const POEM : &str = "roses are red\n  violets are blue\n\nthe end";
+
+#[test]
+fn line_breaks_are_kept(){
+	let server = Server::start("multiline");
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+	let hello = ClMessage::Hello(ClHello{username : "carol".to_string()});
+	let mut carol = ChatClient::connect_encoded(&server.addr, "carol", &hello, None, Encoding::Json).unwrap();
+
+	alice.say(LOBBY, POEM).unwrap();
+	let posted = format!("alice: {}", POEM);
+	assert!(expect(&mut carol, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == posted)));
+	carol.direct("alice", POEM).unwrap();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Direct(d) if d.from == "carol" && d.mesg == POEM)));
+
+	carol.history(LOBBY, 10).unwrap();
+	assert!(expect(&mut carol, |m| matches!(m, SeMessage::History(h) if h.mesgs.contains(&posted))));
+}
+
+#[test]
+fn messages_over_the_length_are_refused(){
+	let server = Server::start_with("length", &["--max-message-len", "10"]);
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+
+	//line breaks count as characters
+	alice.say(LOBBY, "12345\n67890").unwrap();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Error(e) if e.reason == "Message is longer than 10 characters")));
+	alice.direct("alice", "12345\n67890").unwrap();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Error(e) if e.reason == "Message is longer than 10 characters")));
+	alice.say(LOBBY, "12345\n6789").unwrap();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "alice: 12345\n6789")));
+}
@@ -0,0 +1,49 @@
+#![allow(clippy::needless_return)]
+//renaming goes through the server, which tells everyone
+use chat::net::*;
+use chat::ChatClient;
+use common::{expect, Server};
+
+mod common;
+
+fn is_error(mesg : &SeMessage) -> bool{
+	return matches!(mesg, SeMessage::Error(_));
+}
+
+#[test]
+fn rename_is_confirmed_and_announced(){
+	let server = Server::start("rename");
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+	let mut bob = ChatClient::join(&server.addr, "bob", None).unwrap();
+
+	bob.rename("robert").unwrap();
+	assert!(expect(&mut bob, |m| matches!(m, SeMessage::Renamed(r) if r.old == "bob" && r.new == "robert")));
+	assert_eq!(bob.username(), "robert");
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "bob is now robert")));
+
+	alice.direct("robert", "hi").unwrap();
+	assert!(expect(&mut bob, |m| matches!(m, SeMessage::Direct(d) if d.to == "robert" && d.mesg == "hi")));
+	alice.direct("bob", "hi").unwrap();
+	assert!(expect(&mut alice, is_error));
+
+	//the old name is free again
+	assert!(ChatClient::join(&server.addr, "bob", None).is_ok());
+}
+
+#[test]
+fn rename_is_validated(){
+	let server = Server::start("badrename");
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+	let _bob = ChatClient::join(&server.addr, "bob", None).unwrap();
+
+	alice.rename("bob").unwrap();
+	assert!(expect(&mut alice, is_error));
+	alice.rename("not a name").unwrap();
+	assert!(expect(&mut alice, is_error));
+	alice.rename("").unwrap();
+	assert!(expect(&mut alice, is_error));
+	assert_eq!(alice.username(), "alice");
+
+	alice.info().unwrap();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Info(i) if i.users == vec!["alice".to_string(), "bob".to_string()])));
+}
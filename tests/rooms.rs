@@ -0,0 +1,143 @@
+#![allow(clippy::needless_return)]
+//rooms keep their messages among their members, and go away once the last member has left
+use chat::net::*;
+use chat::ChatClient;
+use common::{expect, Server};
+
+mod common;
+
+fn is_mesg(m : &SeMessage, room : &str, text : &str) -> bool{
+	return matches!(m, SeMessage::Mesg(m) if m.room == room && m.mesg == text);
+}
+
+fn is_error(m : &SeMessage, reason : &str) -> bool{
+	return matches!(m, SeMessage::Error(e) if e.reason == reason);
+}
+
+fn room(client : &mut ChatClient, name : &str, action : RoomAction){
+	client.room(name, action).unwrap();
+	//a created room is confirmed as entered
+	let confirmed = if action == RoomAction::Leave {RoomAction::Leave} else {RoomAction::Enter};
+	assert!(expect(client, |m| matches!(m, SeMessage::Room(r) if r.room == name && r.action == confirmed)));
+}
+
+//the rooms of /info, with their members
+fn rooms(client : &mut ChatClient) -> Vec<(String, Vec<String>)>{
+	client.info().unwrap();
+	let mut rooms = None;
+	assert!(expect(client, |m| match m{
+	    SeMessage::Info(info) => {
+			rooms = Some(info.rooms.iter().map(|r| (r.name.clone(), r.users.clone())).collect());
+			true
+		}
+	    _ => {false}
+	}));
+	return rooms.unwrap();
+}
+
+fn users(names : &[&str]) -> Vec<String>{
+	return names.iter().map(|n| n.to_string()).collect();
+}
+
+#[test]
+fn messages_reach_only_the_members_of_the_room(){
+	let server = Server::start("room_routing");
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+	let mut bob = ChatClient::join(&server.addr, "bob", None).unwrap();
+	let mut carol = ChatClient::join(&server.addr, "carol", None).unwrap();
+
+	room(&mut alice, "games", RoomAction::Create);
+	room(&mut bob, "games", RoomAction::Enter);
+	assert!(expect(&mut alice, |m| is_mesg(m, "games", "bob entered the room")));
+
+	alice.say("games", "gg").unwrap();
+	assert!(expect(&mut bob, |m| is_mesg(m, "games", "alice: gg")));
+	//carol is only in the lobby, so what comes after the post there must come without it
+	alice.say(LOBBY, "hi").unwrap();
+	let mut leaked = false;
+	assert!(expect(&mut carol, |m| {
+		leaked |= is_mesg(m, "games", "alice: gg");
+		is_mesg(m, LOBBY, "alice: hi")
+	}));
+	assert!(!leaked);
+
+	carol.say("games", "let me in").unwrap();
+	assert!(expect(&mut carol, |m| is_error(m, "You are not in room games")));
+}
+
+#[test]
+fn members_who_leave_hear_the_room_no_more(){
+	let server = Server::start("room_leaving");
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+	let mut bob = ChatClient::join(&server.addr, "bob", None).unwrap();
+
+	room(&mut alice, "games", RoomAction::Create);
+	room(&mut bob, "games", RoomAction::Enter);
+	room(&mut bob, "games", RoomAction::Leave);
+	assert!(expect(&mut alice, |m| is_mesg(m, "games", "bob left the room")));
+
+	alice.say("games", "anyone?").unwrap();
+	alice.say(LOBBY, "hi").unwrap();
+	let mut leaked = false;
+	assert!(expect(&mut bob, |m| {
+		leaked |= is_mesg(m, "games", "alice: anyone?");
+		is_mesg(m, LOBBY, "alice: hi")
+	}));
+	assert!(!leaked);
+
+	bob.say("games", "back").unwrap();
+	assert!(expect(&mut bob, |m| is_error(m, "You are not in room games")));
+	bob.room("games", RoomAction::Leave).unwrap();
+	assert!(expect(&mut bob, |m| is_error(m, "You are not in room games")));
+}
+
+#[test]
+fn empty_rooms_are_removed(){
+	let server = Server::start("room_removal");
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+	let mut bob = ChatClient::join(&server.addr, "bob", None).unwrap();
+
+	room(&mut alice, "games", RoomAction::Create);
+	room(&mut bob, "games", RoomAction::Enter);
+	bob.room("games", RoomAction::Create).unwrap();
+	assert!(expect(&mut bob, |m| is_error(m, "Room games already exists")));
+
+	room(&mut alice, "games", RoomAction::Leave);
+	room(&mut bob, "games", RoomAction::Leave);
+	bob.room("games", RoomAction::Enter).unwrap();
+	assert!(expect(&mut bob, |m| is_error(m, "No such room: games")));
+
+	//the lobby stays even when everyone has left it
+	room(&mut alice, LOBBY, RoomAction::Leave);
+	room(&mut bob, LOBBY, RoomAction::Leave);
+	room(&mut bob, LOBBY, RoomAction::Enter);
+	//the name of the removed room is free again
+	room(&mut alice, "games", RoomAction::Create);
+}
+
+#[test]
+fn info_lists_the_rooms_with_their_members(){
+	let server = Server::start("room_info");
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+	let mut bob = ChatClient::join(&server.addr, "bob", None).unwrap();
+	let mut carol = ChatClient::join(&server.addr, "carol", None).unwrap();
+
+	room(&mut carol, "music", RoomAction::Create);
+	room(&mut bob, "games", RoomAction::Create);
+	room(&mut alice, "games", RoomAction::Enter);
+	room(&mut carol, LOBBY, RoomAction::Leave);
+	assert_eq!(rooms(&mut alice), vec![
+		("games".to_string(), users(&["alice", "bob"])),
+		(LOBBY.to_string(), users(&["alice", "bob"])),
+		("music".to_string(), users(&["carol"]))
+	]);
+
+	room(&mut carol, "music", RoomAction::Leave);
+	assert_eq!(rooms(&mut alice), vec![
+		("games".to_string(), users(&["alice", "bob"])),
+		(LOBBY.to_string(), users(&["alice", "bob"]))
+	]);
+
+	carol.room("bad name", RoomAction::Create).unwrap();
+	assert!(expect(&mut carol, |m| is_error(m, "Room name must be alphanumeric")));
+}
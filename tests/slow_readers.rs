@@ -0,0 +1,36 @@
+#![allow(clippy::needless_return)]
+//a client which doesn't read what it is sent is disconnected, rather than have it pile up in the server
+use chat::net::*;
+use chat::ChatClient;
+use common::{expect, greeting, Server};
+use std::io::prelude::*;
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+mod common;
+
+#[test]
+fn a_peer_which_doesnt_read_is_dropped(){
+	let server = Server::start_with("backlog", &["--rate-limit", "0", "--ip-rate-limit", "0"]);
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+	let mut mallory = TcpStream::connect(&server.addr).unwrap();
+	mallory.write_all(&greeting("mallory")).unwrap();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "mallory joined")));
+
+	//far more than the socket buffers and MAX_BACKLOG take, alice reads as she goes
+	let mesg = "x".repeat(4000);
+	let mut dropped = false;
+	let started_at = Instant::now();
+	while !dropped{
+		assert!(started_at.elapsed() < Duration::from_secs(20), "mallory is still connected");
+		//until alice's own connection has as much waiting as it takes
+		for _ in 0..50{
+			if let Err(e) = alice.say(LOBBY, &mesg){
+				assert!(matches!(e, Error::Backlog), "{}", e);
+				break;
+			}
+		}
+		alice.wait(Some(Duration::from_millis(1))).unwrap();
+		dropped = alice.poll().iter().any(|m| matches!(m, SeMessage::Mesg(m) if m.mesg == "mallory was disconnected for not reading messages"));
+	}
+}
@@ -0,0 +1,129 @@
+#![allow(clippy::needless_return)]
+//the server serves both the current version of the protocol and the one before it, and no older one
+use chat::net::*;
+use chat::ChatClient;
+use common::{connect, expect, next, Server};
+
+mod common;
+
+fn greet(peer : &mut Peer<SeMessage>, version : u16, oldest : u16){
+	let version = ClVersion{version, oldest, capabilities : Vec::new()};
+	peer.send(&ClMessage::Version(version)).unwrap();
+	peer.send(&ClMessage::Hello(ClHello{username : "alice".to_string()})).unwrap();
+}
+
+#[test]
+fn client_gets_the_current_version(){
+	let server = Server::start("current");
+	let alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+	assert_eq!(alice.version(), PROTOCOL_VERSION);
+	assert!(alice.has_capability("rooms"));
+	assert!(!alice.has_capability("telepathy"));
+}
+
+#[test]
+fn client_of_the_previous_version_is_served(){
+	let server = Server::start("previous");
+	let mut bob = ChatClient::join(&server.addr, "bob", None).unwrap();
+	let mut peer = connect(&server.addr);
+	greet(&mut peer, PROTOCOL_VERSION - 1, PROTOCOL_VERSION - 1);
+	match next(&mut peer){
+	    Some(SeMessage::Version(v)) => {assert_eq!(v.version, PROTOCOL_VERSION - 1)}
+	    _ => {panic!("No version from the server")}
+	}
+	let token = match next(&mut peer){
+	    Some(SeMessage::Hello(hello)) => {hello.token}
+	    _ => {panic!("No hello from the server")}
+	};
+
+	//everything it is sent is of its version
+	let mut received = Vec::new();
+	bob.say(LOBBY, "hi").unwrap();
+	while let Some(mesg) = next(&mut peer){
+		let hi = matches!(&mesg, SeMessage::Mesg(m) if m.mesg == "bob: hi");
+		received.push(mesg);
+		if hi{
+			break;
+		}
+	}
+	peer.send(&ClMessage::IWantInfo(token)).unwrap();
+	received.extend(next(&mut peer));
+	assert!(received.iter().any(|m| matches!(m, SeMessage::Mesg(m) if m.mesg == "bob: hi")));
+	assert!(matches!(received.last(), Some(SeMessage::Info(_))));
+	assert!(received.iter().all(|m| m.since() < PROTOCOL_VERSION));
+}
+
+#[test]
+fn client_may_not_send_what_its_version_doesnt_have(){
+	let server = Server::start("newer-message");
+	let mut bob = ChatClient::join(&server.addr, "bob", None).unwrap();
+	let mut peer = connect(&server.addr);
+	greet(&mut peer, PROTOCOL_VERSION - 1, PROTOCOL_VERSION - 1);
+	assert!(matches!(next(&mut peer), Some(SeMessage::Version(_))));
+	let token = match next(&mut peer){
+	    Some(SeMessage::Hello(hello)) => {hello.token}
+	    _ => {panic!("No hello from the server")}
+	};
+
+	let command = chat::admin::Command::BanList;
+	peer.send(&ClMessage::Admin(ClAdmin{token, command})).unwrap();
+	let mut reason = None;
+	while let Some(mesg) = next(&mut peer){
+		assert!(mesg.since() < PROTOCOL_VERSION);
+		if let SeMessage::UQuit(m) = mesg{
+			reason = Some(m.reason);
+			break;
+		}
+	}
+	assert_eq!(reason.as_deref(), Some("Protocol error: a message of version 3 over a connection of version 2"));
+	assert!(expect(&mut bob, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "alice was disconnected for a protocol error")));
+	//the command wasn't carried out
+	let audit = std::fs::read_to_string(server.dir.join("audit.log")).unwrap_or_default();
+	assert!(audit.is_empty(), "{}", audit);
+}
+
+#[test]
+fn client_without_a_version_is_refused(){
+	let server = Server::start("unversioned");
+	let mut peer = connect(&server.addr);
+	//clients of before the versions were exchanged greet right away
+	peer.send(&ClMessage::Hello(ClHello{username : "alice".to_string()})).unwrap();
+	match next(&mut peer){
+	    Some(SeMessage::UQuit(m)) => {assert!(m.reason.starts_with("Incompatible protocol version"), "{}", m.reason)}
+	    _ => {panic!("The client wasn't refused")}
+	}
+}
+
+#[test]
+fn client_of_two_versions_before_is_refused(){
+	let server = Server::start("older");
+	let mut peer = connect(&server.addr);
+	greet(&mut peer, PROTOCOL_VERSION - 2, PROTOCOL_VERSION - 2);
+	match next(&mut peer){
+	    Some(SeMessage::UQuit(m)) => {assert!(m.reason.starts_with("Incompatible protocol version"), "{}", m.reason)}
+	    _ => {panic!("The client wasn't refused")}
+	}
+}
+
+#[test]
+fn newer_client_gets_the_newest_common_version(){
+	let server = Server::start("newer");
+	let mut peer = connect(&server.addr);
+	greet(&mut peer, PROTOCOL_VERSION + 1, PROTOCOL_VERSION);
+	match next(&mut peer){
+	    Some(SeMessage::Version(v)) => {assert_eq!(v.version, PROTOCOL_VERSION)}
+	    _ => {panic!("No version from the server")}
+	}
+	assert!(matches!(next(&mut peer), Some(SeMessage::Hello(_))));
+}
+
+#[test]
+fn incompatible_client_is_told_why(){
+	let server = Server::start("incompatible");
+	let mut peer = connect(&server.addr);
+	greet(&mut peer, PROTOCOL_VERSION + 2, PROTOCOL_VERSION + 1);
+	match next(&mut peer){
+	    Some(SeMessage::UQuit(m)) => {assert!(m.reason.starts_with("Incompatible protocol version"))}
+	    _ => {panic!("The client wasn't refused")}
+	}
+}
@@ -0,0 +1,102 @@
+#![allow(clippy::needless_return)]
+//clients connecting with WebSocket share the server with the ones connecting over plain TCP
+use chat::net::*;
+use chat::ChatClient;
+use common::{expect, free_addr, version, Server};
+use std::net::TcpStream;
+use std::time::Duration;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Message, WebSocket};
+
+mod common;
+
+//a server with a WebSocket listener, and the address of it
+fn start(name : &str) -> (Server, String){
+	let ws_addr = free_addr();
+	let server = Server::start_with(name, &["--ws", &ws_addr]);
+	return (server, ws_addr);
+}
+
+fn ws_connect(addr : &str) -> WebSocket<MaybeTlsStream<TcpStream>>{
+	let (ws, _) = tungstenite::connect(format!("ws://{}", addr)).unwrap();
+	if let MaybeTlsStream::Plain(stream) = ws.get_ref(){
+		stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+	}
+	return ws;
+}
+
+//reads frames till a message the check accepts, the kind of the frames is the one the client has used
+fn read_until(ws : &mut WebSocket<MaybeTlsStream<TcpStream>>, mut check : impl FnMut(&SeMessage) -> bool) -> bool{
+	while let Ok(frame) = ws.read(){
+		let mesg : SeMessage = match frame{
+		    Message::Text(text) => {serde_json::from_str(text.as_str()).unwrap()}
+		    Message::Binary(bytes) => {bincode::deserialize(&bytes).unwrap()}
+		    Message::Close(_) => {return false;}
+		    _ => {continue;}
+		};
+		if check(&mesg){
+			return true;
+		}
+	}
+	return false;
+}
+
+fn text(mesg : &ClMessage) -> Message{
+	return Message::text(serde_json::to_string(mesg).unwrap());
+}
+
+#[test]
+fn websocket_and_tcp_users_talk(){
+	let (server, ws_addr) = start("websocket");
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+
+	let mut bob = ws_connect(&ws_addr);
+	bob.send(text(&version())).unwrap();
+	bob.send(text(&ClMessage::Hello(ClHello{username : "bob".to_string()}))).unwrap();
+	let mut token = 0;
+	assert!(read_until(&mut bob, |m| match m{
+	    SeMessage::Hello(h) => {
+			token = h.token;
+			true
+		}
+	    _ => {false}
+	}));
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "bob joined")));
+
+	bob.send(text(&ClMessage::Mesg(ClMesg{token, room : LOBBY.to_string(), mesg : "hi".to_string()}))).unwrap();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "bob: hi")));
+
+	alice.direct("bob", "psst").unwrap();
+	assert!(read_until(&mut bob, |m| matches!(m, SeMessage::Direct(d) if d.from == "alice" && d.mesg == "psst")));
+
+	bob.send(text(&ClMessage::IWantInfo(token))).unwrap();
+	assert!(read_until(&mut bob, |m| matches!(m, SeMessage::Info(i) if i.users.len() == 2)));
+}
+
+#[test]
+fn binary_frames_carry_bincode(){
+	let (server, ws_addr) = start("websocket-binary");
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+
+	let mut carol = ws_connect(&ws_addr);
+	carol.send(Message::binary(bincode::serialize(&version()).unwrap())).unwrap();
+	let hello = bincode::serialize(&ClMessage::Hello(ClHello{username : "carol".to_string()})).unwrap();
+	carol.send(Message::binary(hello)).unwrap();
+	assert!(read_until(&mut carol, |m| matches!(m, SeMessage::Hello(_))));
+
+	alice.say(LOBBY, "hello carol").unwrap();
+	assert!(read_until(&mut carol, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "alice: hello carol")));
+}
+
+#[test]
+fn bad_frame_drops_only_the_sender(){
+	let (server, ws_addr) = start("websocket-bad");
+	let mut alice = ChatClient::join(&server.addr, "alice", None).unwrap();
+
+	let mut rogue = ws_connect(&ws_addr);
+	rogue.send(Message::text("not json")).unwrap();
+	assert!(read_until(&mut rogue, |m| matches!(m, SeMessage::UQuit(q) if q.reason.starts_with("Protocol error"))));
+
+	alice.say(LOBBY, "still here").unwrap();
+	assert!(expect(&mut alice, |m| matches!(m, SeMessage::Mesg(m) if m.mesg == "alice: still here")));
+}